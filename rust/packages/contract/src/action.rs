@@ -5,7 +5,7 @@ use std::str::FromStr;
 use malloc_call_core::ft::MALLOC_CALL_CORE_GAS_FOR_FT_TRANSFER_CALL;
 use malloc_call_core::ReturnItem;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
     env, log,
@@ -15,60 +15,196 @@ use near_sdk::{
 use near_sdk::{utils, Gas};
 
 use crate::actions::{self, ActionFunctions};
-use crate::errors::PanicError;
+use crate::errors::MallocError;
+use crate::events::{ActionCallStartData, MallocEvent};
+use crate::gas::{CALLBACK_GAS, STEP_RECEIPT_FEES_GAS};
 use crate::malloc_utils::GenericId;
+use crate::splits::SplitSpec;
 use crate::{
-    errors::panic_errors, vector_wrapper::VectorWrapper, Construction, ConstructionCall,
-    ConstructionCallId, ConstructionId, Contract,
+    vector_wrapper::{ClearStorage, VectorWrapper}, Construction, ConstructionCall, ConstructionCallId,
+    ConstructionId, Contract,
 };
 
 pub type ActionId = GenericId;
 
 pub type NextActionsIndicesForAction = VectorWrapper<VectorWrapper<u64>>;
-pub type NextActionsSplitsForAction = VectorWrapper<VectorWrapper<U128>>;
+pub type NextActionsSplitsForAction = VectorWrapper<SplitSpec>;
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub enum ActionCallStatus {
-    /// The splitter call errored
-    Error { message: String },
+    /// The action call errored. The error's code and message can be derived from it, see MallocError
+    Error { error: MallocError },
     /// The splitter call is waiting to be started
     WaitingCall,
     /// The splitter call is currently executing and waiting for a result
     Executing { block_index_start: u64 },
     /// The splitter call succeeded
     Success,
+    /// The action call is a join which is waiting for the inputs from its incoming edges
+    WaitingInputs,
+    /// The action call succeeded and its revert was called as its construction call was aborted.
+    /// Action calls which cannot be reverted keep their success status
+    Reverted,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+impl From<MallocError> for ActionCallStatus {
+    fn from(error: MallocError) -> Self {
+        ActionCallStatus::Error { error }
+    }
+}
+
+/// An amount of a token
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
 #[serde(crate = "near_sdk::serde")]
+pub struct TokenAmount {
+    pub token_id: AccountId,
+    pub amount: U128,
+}
 
-// TODO: expected_number_inputs if we make this a dag
-// https://github.com/Lev-Stambler/malloc-near-2/issues/26
+impl ClearStorage for VectorWrapper<Vec<TokenAmount>> {
+    fn clear_storage(&mut self) {
+        self.0.clear();
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
 pub struct ActionCall {
-    action_index_in_construction: u64,
+    pub action_index_in_construction: u64,
     block_index: u64,
     pub amount: u128,
     /// The length of children_status should always equal the length of the splitter's children
     status: ActionCallStatus,
+    /// The number of incoming edges which the action call waits on before it is run. This is 1 unless the action is a join
+    pub expected_number_inputs: u64,
+    /// The number of incoming edges which passed on tokens
+    pub number_inputs_received: u64,
+    /// The number of incoming edges which failed or did not pass on any tokens
+    pub number_inputs_failed: u64,
+    /// The amounts passed on by the incoming edges of a join, summed per token
+    pub input_amounts: Vec<TokenAmount>,
+    /// The tokens returned by the action call once it succeeded
+    pub returned_items: Vec<TokenAmount>,
+    /// The branch which a route action call passed its input on to, see Route
+    pub route_branch: Option<u64>,
+    /// The block at which the action call started executing
+    pub block_index_start: Option<u64>,
+    /// The block at which the action call succeeded or errored
+    pub block_index_end: Option<u64>,
 }
 
 pub type ActionCallId = u64;
 
+/// A registered action along with its metadata
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ActionInfo {
+    pub id: ActionId,
+    /// The token which the action spends
+    pub token_id: AccountId,
+    pub action: Action,
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub enum Action {
-    // TODO: add ft transfer see https://github.com/Lev-Stambler/malloc-near-2/issues/20
-    // FtTransfer {
-    //     receiver_id: AccountId,
-    //     token_id: AccountId,
-    // },
+    FtTransfer(actions::ft_calls::FtTransfer),
     FtTransferCallToMallocCall(actions::ft_calls::FtTransferCallToMallocCall),
     WithdrawFromMallocCall(actions::ft_calls::WithdrawFromMallocCall),
     MallocCall(actions::malloc_call::MallocCall),
+    NearTransfer(actions::near_calls::NearTransfer),
+    WrapNear(actions::near_calls::WrapNear),
+    UnwrapNear(actions::near_calls::UnwrapNear),
+    Route(actions::route::Route),
 }
 
 impl Contract {
+    /// Get the action. If the id has no version, the latest version of the action is returned
+    pub fn get_action(&self, id: &ActionId) -> Result<Action, MallocError> {
+        self.actions
+            .get(&self.resolve_action_id(id)?)
+            .ok_or(MallocError::ActionNotFound {
+                action_id: id.clone(),
+            })
+    }
+
+    /// Get the id of the exact action version which the id refers to
+    pub(crate) fn resolve_action_id(&self, id: &ActionId) -> Result<ActionId, MallocError> {
+        id.resolve_version(&self.action_versions)
+            .ok_or(MallocError::ActionNotFound {
+                action_id: id.clone(),
+            })
+    }
+
+    /// Mark an executing action call as timed out once its construction's timeout has passed since it started.
+    /// Timed out action calls are treated like failed ones, so the joins downstream of them can still resolve.
+    /// Nothing is credited back here: the tokens which were sent to the malloc call are reclaimed by
+    /// querying the malloc call's balance for the caller, see reclaim_timed_out_action_call
+    /// @returns the action which timed out, the construction call's caller and the amount which the action call sent on
+    pub(crate) fn timeout_action_call_internal(
+        &mut self,
+        construction_call_id: &ConstructionCallId,
+        action_call_id: ActionCallId,
+    ) -> Result<(Action, AccountId, u128), MallocError> {
+        let construction_call = self.construction_calls.get(construction_call_id).ok_or(
+            MallocError::ConstructionCallNotFound {
+                construction_call_id: construction_call_id.clone(),
+            },
+        )?;
+        if !construction_call
+            .action_calls
+            .0
+            .iter()
+            .any(|id| id == action_call_id)
+        {
+            return Err(MallocError::ActionCallNotFound { action_call_id });
+        }
+        let mut action_call = self
+            .action_calls
+            .get(&action_call_id)
+            .ok_or(MallocError::ActionCallNotFound { action_call_id })?;
+        let block_index_start = match action_call.get_status() {
+            ActionCallStatus::Executing { block_index_start } => *block_index_start,
+            _ => return Err(MallocError::ActionCallNotExecuting),
+        };
+        let construction = self.get_construction(&construction_call.construction_id)?;
+        let block_index_timeout = block_index_start + construction.get_action_call_timeout_blocks();
+        if env::block_index() < block_index_timeout {
+            return Err(MallocError::ActionCallNotTimedOut {
+                block_index_timeout,
+            });
+        }
+        let action = self.get_action_call_action(&construction, &action_call)?;
+
+        action_call.handle_action_failure_internal(
+            self,
+            construction_call_id.clone(),
+            MallocError::ActionCallTimedOut,
+        );
+        self.action_calls.insert(&action_call_id, &action_call);
+        MallocEvent::emit_action_call_finished(construction_call_id, action_call_id, &action_call);
+        self.try_collect_construction_call(construction_call_id);
+        Ok((action, construction_call.caller, action_call.amount))
+    }
+
+    /// Get the action which the action call runs
+    pub(crate) fn get_action_call_action(
+        &self,
+        construction: &Construction,
+        action_call: &ActionCall,
+    ) -> Result<Action, MallocError> {
+        let action_id = construction
+            .actions
+            .0
+            .get(action_call.action_index_in_construction)
+            .ok_or(MallocError::ActionIndexOutOfBounds {
+                index: action_call.action_index_in_construction,
+                number_of_actions: construction.actions.0.len(),
+            })?;
+        self.get_action(&action_id)
+    }
+
     /// Increments the next action call id and returns the current one
     fn incr_action_call_id(&mut self) -> ActionCallId {
         let curr = self.next_action_call_id;
@@ -78,6 +214,20 @@ impl Contract {
 }
 
 impl ActionCall {
+    /// An action call is finished once it either succeeded or errored
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.status,
+            ActionCallStatus::Success
+                | ActionCallStatus::Error { .. }
+                | ActionCallStatus::Reverted
+        )
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self.status, ActionCallStatus::Error { .. })
+    }
+
     pub fn new_call_id(contract: &mut Contract) -> ActionCallId {
         contract.incr_action_call_id()
     }
@@ -88,86 +238,439 @@ impl ActionCall {
         action_index_in_construction: u64,
     ) -> (ActionCall, ActionCallId) {
         (
-            ActionCall {
-                amount,
-                status: ActionCallStatus::WaitingCall,
-                block_index: env::block_index(),
-                action_index_in_construction,
-            },
+            ActionCall::new_unregistered(amount, action_index_in_construction),
             ActionCall::new_call_id(contract),
         )
     }
 
-    pub fn action_calls_from_construction_indices(
+    /// Create the action call for a join, an action with several incoming edges.
+    /// The join waits for all of its inputs to resolve before it is run on their sum
+    pub fn new_join(
         contract: &mut Contract,
-        action_indices: Vec<u64>,
-        amounts: Vec<u128>,
-    ) -> Result<Vec<ActionCallId>, PanicError> {
-        if action_indices.len() != amounts.len() {
-            return Err(panic_errors::NUMB_NODES_DNE_NUMB_SPLITS.to_string());
+        expected_number_inputs: u64,
+        action_index_in_construction: u64,
+    ) -> (ActionCall, ActionCallId) {
+        let mut action_call = ActionCall::new_unregistered(0, action_index_in_construction);
+        action_call.status = ActionCallStatus::WaitingInputs;
+        action_call.expected_number_inputs = expected_number_inputs;
+        action_call.number_inputs_received = 0;
+        (action_call, ActionCall::new_call_id(contract))
+    }
+
+    /// Create an action call which is not registered in the contract. This is used to estimate the gas of an action
+    pub(crate) fn new_unregistered(amount: u128, action_index_in_construction: u64) -> ActionCall {
+        ActionCall {
+            amount,
+            status: ActionCallStatus::WaitingCall,
+            block_index: env::block_index(),
+            action_index_in_construction,
+            expected_number_inputs: 1,
+            number_inputs_received: 1,
+            number_inputs_failed: 0,
+            input_amounts: vec![],
+            returned_items: vec![],
+            route_branch: None,
+            block_index_start: None,
+            block_index_end: None,
+        }
+    }
+
+    pub fn get_status(&self) -> &ActionCallStatus {
+        &self.status
+    }
+
+    pub fn is_executing(&self) -> bool {
+        matches!(self.status, ActionCallStatus::Executing { .. })
+    }
+
+    pub fn is_waiting_inputs(&self) -> bool {
+        matches!(self.status, ActionCallStatus::WaitingInputs)
+    }
+
+    /// Add the tokens passed on by one of the join's incoming edges
+    pub(crate) fn add_join_input(&mut self, token_id: &AccountId, amount: u128) {
+        self.number_inputs_received += 1;
+        match self
+            .input_amounts
+            .iter_mut()
+            .find(|input| &input.token_id == token_id)
+        {
+            Some(input) => input.amount = U128(input.amount.0 + amount),
+            None => self.input_amounts.push(TokenAmount {
+                token_id: token_id.clone(),
+                amount: U128(amount),
+            }),
         }
+    }
+
+    /// Record that one of the join's incoming edges will never pass on tokens
+    pub(crate) fn add_failed_join_input(&mut self) {
+        self.number_inputs_failed += 1;
+    }
 
-        let mut action_calls: Vec<ActionCallId> = Vec::with_capacity(action_indices.len());
-        for i in 0..action_indices.len() {
-            let (action_call, id) =
-                ActionCall::new(contract, amounts[i], action_indices[i].to_owned());
-            contract.action_calls.insert(&id, &action_call);
+    /// Whether all of the join's incoming edges either passed on tokens or failed
+    pub(crate) fn has_all_inputs(&self) -> bool {
+        self.number_inputs_received + self.number_inputs_failed >= self.expected_number_inputs
+    }
 
-            action_calls.push(id);
+    /// Stop waiting on inputs. The join runs on the summed amount of the token which its action spends,
+    /// the inputs of any other token are left with the caller.
+    /// If none of the incoming edges passed on tokens, the join errors instead
+    /// @returns true if the join should be run
+    pub(crate) fn resolve_join(&mut self, token_id: &AccountId) -> bool {
+        if self.number_inputs_received == 0 {
+            self.set_error(MallocError::JoinReceivedNoInputs);
+            return false;
         }
+        for input in self.input_amounts.iter() {
+            if &input.token_id == token_id {
+                self.amount = input.amount.0;
+            } else {
+                log!(
+                    "Leaving {} of {} with the caller as the join spends {}",
+                    input.amount.0,
+                    input.token_id,
+                    token_id
+                );
+            }
+        }
+        self.status = ActionCallStatus::WaitingCall;
+        true
+    }
+
+    /// Set the action call as errored
+    pub(crate) fn set_error(&mut self, error: MallocError) {
+        self.status = error.into();
+        self.block_index_end = Some(env::block_index());
+    }
 
-        Ok(action_calls)
+    pub fn is_success(&self) -> bool {
+        matches!(self.status, ActionCallStatus::Success)
+    }
+
+    pub(crate) fn set_reverted(&mut self) {
+        self.status = ActionCallStatus::Reverted;
+    }
+
+    pub(crate) fn set_success(&mut self) {
+        self.status = ActionCallStatus::Success;
+        self.block_index_end = Some(env::block_index());
     }
 }
 
 impl Contract {
     /// This call on _run assumes a well formed splitter
     /// Returns a refunded amount
-    pub(crate) fn _run_step(&mut self, construction_call_id: ConstructionCallId) -> u64 {
+    pub(crate) fn _run_step(&mut self, construction_call_id: ConstructionCallId) -> Result<u64, MallocError> {
         let mut construction_call = self.get_construction_call_unchecked(&construction_call_id);
-
         let action_call_index = construction_call
-            .next_action_calls_stack
+            .pop_next_action_call(self)
+            .unwrap_or_else(|| panic!("{}", MallocError::ConstructionCallStackEmpty));
+        self.construction_calls
+            .insert(&construction_call_id, &construction_call);
+        self._run_action_call(construction_call_id, action_call_index, CALLBACK_GAS)
+    }
+
+    /// Run as many steps of the construction call as fit within the gas budget.
+    /// The steps are taken off of the next action calls in scheduling order, so independent branches are started in parallel.
+    /// Any gas left over is split evenly between the callbacks of the started steps so that they can
+    /// schedule the next steps themselves.
+    /// If a step cannot be run, its action call errors and the construction call stops driving itself
+    /// @returns the number of steps started, or the error of the first step which could not be run
+    pub(crate) fn _run_steps(
+        &mut self,
+        construction_call_id: ConstructionCallId,
+        gas_budget: Gas,
+    ) -> Result<u64, MallocError> {
+        let mut construction_call = self.get_construction_call_unchecked(&construction_call_id);
+        let construction = self.get_construction(&construction_call.construction_id)?;
+
+        // Take the next steps, in scheduling order, while they fit within the budget
+        let mut gas_required: Gas = 0;
+        let mut steps: Vec<(u64, bool)> = vec![];
+        let mut failed_step: Option<(u64, MallocError)> = None;
+        while let Some(action_call_index) = construction_call.peek_next_action_call(self) {
+            let step_gas = self.get_action_call_step(&construction, &construction_call, action_call_index);
+            let (step_gas, has_callback) = match step_gas {
+                Ok(step) => step,
+                Err(e) => {
+                    construction_call.pop_next_action_call(self);
+                    failed_step = Some((action_call_index, e));
+                    break;
+                }
+            };
+            if gas_required + step_gas > gas_budget {
+                break;
+            }
+            gas_required += step_gas;
+            construction_call.pop_next_action_call(self);
+            steps.push((action_call_index, has_callback));
+        }
+
+        if !construction_call.next_action_calls.is_empty() {
+            log!(
+                "Not enough gas to run all the next action calls, {} are left to run",
+                construction_call.next_action_calls.len()
+            );
+        }
+        self.construction_calls
+            .insert(&construction_call_id, &construction_call);
+
+        let numb_callbacks = steps.iter().filter(|(_, has_cb)| *has_cb).count() as u64;
+        let extra_callback_gas = (gas_budget - gas_required)
+            .checked_div(numb_callbacks)
+            .unwrap_or(0);
+        let mut first_error = None;
+        for (action_call_index, has_callback) in steps.iter() {
+            let callback_gas = if *has_callback {
+                CALLBACK_GAS + extra_callback_gas
+            } else {
+                CALLBACK_GAS
+            };
+            if let Err(e) = self._run_action_call(construction_call_id.clone(), *action_call_index, callback_gas) {
+                first_error.get_or_insert(e);
+            }
+        }
+        if let Some((action_call_index, e)) = failed_step {
+            self.fail_action_call(&construction_call_id, action_call_index, e.clone());
+            first_error.get_or_insert(e);
+        }
+
+        match first_error {
+            None => Ok(steps.len() as u64),
+            Some(e) => {
+                if let Some(mut construction_call) = self.construction_calls.get(&construction_call_id) {
+                    construction_call.is_self_driving = false;
+                    self.construction_calls
+                        .insert(&construction_call_id, &construction_call);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Get the gas which the step of the action call at action_call_index needs and whether the step has a callback
+    fn get_action_call_step(
+        &self,
+        construction: &Construction,
+        construction_call: &ConstructionCall,
+        action_call_index: u64,
+    ) -> Result<(Gas, bool), MallocError> {
+        let action_call_id = construction_call
+            .action_calls
             .0
-            .pop()
-            .unwrap_or_else(|| panic!(panic_errors::CONSTRUCTION_CALL_SPLITTER_STACK_EMPTY));
+            .get(action_call_index)
+            .unwrap();
+        let action_call = self
+            .action_calls
+            .get(&action_call_id)
+            .ok_or(MallocError::ActionCallNotFound { action_call_id })?;
+        let action = self.get_action_call_action(construction, &action_call)?;
+        Ok((action.get_step_gas(&action_call)?, action.has_callback()))
+    }
+
+    /// Run the action call at action_call_index in the construction call's action calls.
+    /// The action call must already be taken off of the construction call's next action calls.
+    /// If the action call cannot be run, it errors and the error is returned
+    fn _run_action_call(
+        &mut self,
+        construction_call_id: ConstructionCallId,
+        action_call_index: u64,
+        callback_gas: Gas,
+    ) -> Result<u64, MallocError> {
+        let mut construction_call = self.get_construction_call_unchecked(&construction_call_id);
+
         let action_call_id = construction_call
             .action_calls
             .0
             .get(action_call_index)
             .unwrap();
-        let mut action_call = self
+        let action_call = self
             .action_calls
             .get(&action_call_id)
-            .unwrap_or_else(|| panic!(panic_errors::NODE_CALL_NOT_FOUND));
+            .ok_or(MallocError::ActionCallNotFound { action_call_id })?;
 
-        let construction = self
-            .get_construction(&construction_call.construction_id)
-            .unwrap_or_else(|e| panic!("{}", e));
+        let construction = self.get_construction(&construction_call.construction_id)?;
 
         let action_index = action_call.action_index_in_construction;
         let action_id = construction.actions.0.get(action_index).unwrap();
 
-        let mut action = self.actions.get(&action_id).unwrap();
-
-        self.construction_calls
-            .insert(&construction_call_id, &&construction_call);
+        let handled = self.get_action(&action_id).and_then(|mut action| {
+            action
+                .handle_action(
+                    self,
+                    action_call,
+                    &construction_call_id,
+                    action_call_id,
+                    &construction_call.caller,
+                    callback_gas,
+                )
+                .map(|(prom, action_call)| (prom, action_call, action))
+        });
+        let (prom, action_call, action) = match handled {
+            Ok(handled) => handled,
+            Err(e) => {
+                self.fail_action_call(&construction_call_id, action_call_index, e.clone());
+                return Err(e);
+            }
+        };
+        self.action_calls.insert(&action_call_id, &action_call);
+        MallocEvent::ActionCallStart(vec![ActionCallStartData {
+            construction_call_id: construction_call_id.clone(),
+            action_call_id: U64(action_call_id),
+            action_index,
+            amount: U128(action_call.amount),
+        }])
+        .emit();
+        // An action without a callback never passes tokens on to its next actions
+        if !action.has_callback() {
+            construction_call.drop_downstream_inputs(self, &construction, action_index);
+            self.construction_calls
+                .insert(&construction_call_id, &construction_call);
+        }
+        self.try_collect_construction_call(&construction_call_id);
+        Ok(prom)
+    }
 
-        let (prom, action_call) = action
-            .handle_action(
-                self,
-                action_call,
-                &construction_call_id,
-                action_call_id,
-                &env::predecessor_account_id(),
-            )
-            .unwrap_or_else(|e| panic!("{}", e));
+    /// Record that the action call at action_call_index could not be run. The action checks that it can be run
+    /// before it takes anything from the caller's balance, so nothing is credited back
+    fn fail_action_call(
+        &mut self,
+        construction_call_id: &ConstructionCallId,
+        action_call_index: u64,
+        error: MallocError,
+    ) {
+        let construction_call = self.get_construction_call_unchecked(construction_call_id);
+        let action_call_id = construction_call
+            .action_calls
+            .0
+            .get(action_call_index)
+            .unwrap();
+        let mut action_call = match self.action_calls.get(&action_call_id) {
+            Some(action_call) => action_call,
+            None => return,
+        };
+        action_call.handle_action_failure_internal(self, construction_call_id.clone(), error);
         self.action_calls.insert(&action_call_id, &action_call);
-        prom
+        MallocEvent::emit_action_call_finished(construction_call_id, action_call_id, &action_call);
+        self.try_collect_construction_call(construction_call_id);
     }
 }
 
 impl Action {
+    pub fn has_callback(&self) -> bool {
+        match self {
+            Action::FtTransfer(action) => action.has_callback(),
+            Action::FtTransferCallToMallocCall(action) => action.has_callback(),
+            Action::MallocCall(action) => action.has_callback(),
+            Action::WithdrawFromMallocCall(action) => action.has_callback(),
+            Action::NearTransfer(action) => action.has_callback(),
+            Action::WrapNear(action) => action.has_callback(),
+            Action::UnwrapNear(action) => action.has_callback(),
+            Action::Route(action) => action.has_callback(),
+        }
+    }
+
+    pub fn reclaims_on_failure(&self, action_call: &ActionCall) -> bool {
+        match self {
+            Action::FtTransfer(action) => action.reclaims_on_failure(action_call),
+            Action::FtTransferCallToMallocCall(action) => action.reclaims_on_failure(action_call),
+            Action::MallocCall(action) => action.reclaims_on_failure(action_call),
+            Action::WithdrawFromMallocCall(action) => action.reclaims_on_failure(action_call),
+            Action::NearTransfer(action) => action.reclaims_on_failure(action_call),
+            Action::WrapNear(action) => action.reclaims_on_failure(action_call),
+            Action::UnwrapNear(action) => action.reclaims_on_failure(action_call),
+            Action::Route(action) => action.reclaims_on_failure(action_call),
+        }
+    }
+
+    pub fn get_token_id(&self) -> AccountId {
+        match self {
+            Action::FtTransfer(action) => action.get_token_id(),
+            Action::FtTransferCallToMallocCall(action) => action.get_token_id(),
+            Action::MallocCall(action) => action.get_token_id(),
+            Action::WithdrawFromMallocCall(action) => action.get_token_id(),
+            Action::NearTransfer(action) => action.get_token_id(),
+            Action::WrapNear(action) => action.get_token_id(),
+            Action::UnwrapNear(action) => action.get_token_id(),
+            Action::Route(action) => action.get_token_id(),
+        }
+    }
+
+    pub fn get_malloc_call_id(&self) -> Option<AccountId> {
+        match self {
+            Action::FtTransfer(action) => action.get_malloc_call_id(),
+            Action::FtTransferCallToMallocCall(action) => action.get_malloc_call_id(),
+            Action::MallocCall(action) => action.get_malloc_call_id(),
+            Action::WithdrawFromMallocCall(action) => action.get_malloc_call_id(),
+            Action::NearTransfer(action) => action.get_malloc_call_id(),
+            Action::WrapNear(action) => action.get_malloc_call_id(),
+            Action::UnwrapNear(action) => action.get_malloc_call_id(),
+            Action::Route(action) => action.get_malloc_call_id(),
+        }
+    }
+
+    /// Fill in the parts of the action which default to the malloc call registry. This is done when the action is registered
+    pub(crate) fn set_defaults_from_registry(&mut self, contract: &Contract) -> Result<(), MallocError> {
+        match self {
+            Action::MallocCall(call) => call.set_defaults_from_registry(contract),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn handle_revert(
+        &self,
+        action_call: &ActionCall,
+        caller: &AccountId,
+        prior_promise: Option<u64>,
+    ) -> Option<u64> {
+        match self {
+            Action::FtTransfer(action) => action.handle_revert(action_call, caller, prior_promise),
+            Action::FtTransferCallToMallocCall(action) => {
+                action.handle_revert(action_call, caller, prior_promise)
+            }
+            Action::MallocCall(action) => action.handle_revert(action_call, caller, prior_promise),
+            Action::WithdrawFromMallocCall(action) => {
+                action.handle_revert(action_call, caller, prior_promise)
+            }
+            Action::NearTransfer(action) => action.handle_revert(action_call, caller, prior_promise),
+            Action::WrapNear(action) => action.handle_revert(action_call, caller, prior_promise),
+            Action::UnwrapNear(action) => action.handle_revert(action_call, caller, prior_promise),
+            Action::Route(action) => action.handle_revert(action_call, caller, prior_promise),
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        match self {
+            Action::FtTransfer(action) => action.is_leaf(),
+            Action::FtTransferCallToMallocCall(action) => action.is_leaf(),
+            Action::MallocCall(action) => action.is_leaf(),
+            Action::WithdrawFromMallocCall(action) => action.is_leaf(),
+            Action::NearTransfer(action) => action.is_leaf(),
+            Action::WrapNear(action) => action.is_leaf(),
+            Action::UnwrapNear(action) => action.is_leaf(),
+            Action::Route(action) => action.is_leaf(),
+        }
+    }
+
+    pub fn get_gas_requirement(&self, action_call: &ActionCall) -> Result<Gas, MallocError> {
+        match self {
+            Action::FtTransfer(action) => action.get_gas_requirement(action_call),
+            Action::FtTransferCallToMallocCall(action) => action.get_gas_requirement(action_call),
+            Action::MallocCall(action) => action.get_gas_requirement(action_call),
+            Action::WithdrawFromMallocCall(action) => action.get_gas_requirement(action_call),
+            Action::NearTransfer(action) => action.get_gas_requirement(action_call),
+            Action::WrapNear(action) => action.get_gas_requirement(action_call),
+            Action::UnwrapNear(action) => action.get_gas_requirement(action_call),
+            Action::Route(action) => action.get_gas_requirement(action_call),
+        }
+    }
+
+    /// The gas needed to run the action as a single step of a construction call, including the fees for the step's receipts
+    pub fn get_step_gas(&self, action_call: &ActionCall) -> Result<Gas, MallocError> {
+        Ok(self.get_gas_requirement(action_call)? + STEP_RECEIPT_FEES_GAS)
+    }
+
     // TODO: split up into helper functions
     // TODO: how to make sure all one input token type for a splitter?
     pub fn handle_action(
@@ -177,18 +680,29 @@ impl Action {
         construction_call_id: &ConstructionCallId,
         action_call_id: ActionCallId,
         caller: &AccountId,
-    ) -> Result<(u64, ActionCall), String> {
+        callback_gas: Gas,
+    ) -> Result<(u64, ActionCall), MallocError> {
         // Set the child's status in the splitter call
         action_call.status = ActionCallStatus::Executing {
             block_index_start: env::block_index(),
         };
+        action_call.block_index_start = Some(env::block_index());
         let prom = match self {
+            Action::FtTransfer(ft_transfer_action) => ft_transfer_action.handle(
+                contract,
+                &action_call,
+                construction_call_id,
+                action_call_id,
+                caller,
+                callback_gas,
+            ),
             Action::FtTransferCallToMallocCall(ft_transfer_action) => ft_transfer_action.handle(
                 contract,
                 &action_call,
                 construction_call_id,
                 action_call_id,
                 caller,
+                callback_gas,
             ),
             Action::MallocCall(call) => call.handle(
                 contract,
@@ -196,6 +710,7 @@ impl Action {
                 construction_call_id,
                 action_call_id,
                 caller,
+                callback_gas,
             ),
             Action::WithdrawFromMallocCall(ft_withdraw_action) => ft_withdraw_action.handle(
                 contract,
@@ -203,9 +718,46 @@ impl Action {
                 construction_call_id,
                 action_call_id,
                 caller,
+                callback_gas,
+            ),
+            Action::NearTransfer(near_action) => near_action.handle(
+                contract,
+                &action_call,
+                construction_call_id,
+                action_call_id,
+                caller,
+                callback_gas,
+            ),
+            Action::WrapNear(near_action) => near_action.handle(
+                contract,
+                &action_call,
+                construction_call_id,
+                action_call_id,
+                caller,
+                callback_gas,
+            ),
+            Action::UnwrapNear(near_action) => near_action.handle(
+                contract,
+                &action_call,
+                construction_call_id,
+                action_call_id,
+                caller,
+                callback_gas,
+            ),
+            Action::Route(route) => route.handle(
+                contract,
+                &action_call,
+                construction_call_id,
+                action_call_id,
+                caller,
+                callback_gas,
             ),
         };
         let prom_ret = prom?;
+        // Actions without a callback are done as soon as their promise is scheduled
+        if !self.has_callback() {
+            action_call.set_success();
+        }
         Ok((prom_ret, action_call))
     }
 }
@@ -235,10 +787,10 @@ impl ActionCall {
     pub(crate) fn get_results_from_returned_bytes(
         ret_bytes: Vec<u8>,
         token_id: Option<ValidAccountId>,
-    ) -> Result<Vec<ReturnItem>, String> {
+    ) -> Result<Vec<ReturnItem>, MallocError> {
         let as_u128: Result<String, _> = serde_json::from_slice(&ret_bytes);
         if let Ok(amount_ret) = as_u128 {
-            let token_id = token_id.unwrap();
+            let token_id = token_id.ok_or(MallocError::ReturnTokenIdMissing)?;
             return Ok(vec![ReturnItem {
                 token_id: token_id,
                 amount: amount_ret,
@@ -249,108 +801,202 @@ impl ActionCall {
         if let Ok(return_vec) = as_return_vec {
             return Ok(return_vec);
         }
-        panic!("EXPECTED ONE OF THESE TO WORK")
+        Err(MallocError::ReturnNotParsable)
+    }
+
+    /// Handle an action call which failed. The action call errors and the joins downstream of it
+    /// stop waiting on its tokens, so that the sibling branches keep running.
+    /// Nothing is credited back here: if the action's outgoing transfer failed, its resolve step already credited
+    /// the amount back to the caller. Tokens which reached a malloc call that then failed are reclaimed from it
+    /// by the callback, see Contract::reclaim_failed_action_call
+    pub(crate) fn handle_action_failure_internal(
+        &mut self,
+        contract: &mut Contract,
+        construction_call_id: ConstructionCallId,
+        error: MallocError,
+    ) {
+        let mut construction_call = contract.get_construction_call_unchecked(&construction_call_id);
+        let construction = contract
+            .get_construction(&construction_call.construction_id)
+            .unwrap_or_else(|e| panic!("{}", e));
+        log!(
+            "Action call with index {} failed: {}",
+            self.action_index_in_construction,
+            error
+        );
+        self.set_error(error);
+        construction_call.drop_downstream_inputs(
+            contract,
+            &construction,
+            self.action_index_in_construction,
+        );
+        contract
+            .construction_calls
+            .insert(&construction_call_id, &construction_call);
     }
 
+    /// Pass the action call's returned tokens on to its next actions.
+    /// If the results do not match the shape of the next actions, nothing is passed on and an error is returned.
+    /// The returned tokens are then left with the caller. When the action's sets of next actions are keyed by token id,
+    /// the returned tokens which no set takes are left with the caller instead.
+    /// If the action returns less than its minimum amount of a token, nothing is passed on and an error is returned
     pub(crate) fn handle_action_callback_internal(
         &mut self,
         contract: &mut Contract,
         construction_call_id: ConstructionCallId,
-        caller: AccountId,
         results: Vec<ReturnItem>,
-    ) -> Option<u64> {
+    ) -> Result<(), MallocError> {
         let mut construction_call = contract.get_construction_call_unchecked(&construction_call_id);
-        let construction_res = contract.get_construction(&construction_call.construction_id);
-        // TODO: error handling with the malloc call cores
-        let construction = construction_res.unwrap();
+        let construction = contract.get_construction(&construction_call.construction_id)?;
 
-        // // TODO: error handling with the malloc call cores
-        // let action_call_res = self.action_calls.get(&action_call_id);
-        // let action_call = action_call_res.unwrap();
+        let returned_items = results
+            .iter()
+            .map(|r| {
+                r.amount
+                    .parse::<u128>()
+                    .map(|amount| TokenAmount {
+                        token_id: r.token_id.to_string(),
+                        amount: U128(amount),
+                    })
+                    .map_err(|_| MallocError::ReturnAmountNotParsable {
+                        amount: r.amount.clone(),
+                    })
+            })
+            .collect::<Result<Vec<TokenAmount>, _>>()?;
+        construction.check_min_returned_amounts(self.action_index_in_construction, &returned_items)?;
 
-        // TODO: error handle with malloc call core
-        let next_actions_indices = construction_call
-            .next_actions_indices_in_construction
-            .0
-            .get(self.action_index_in_construction)
-            .unwrap();
+        // Nothing is passed on once the construction call is aborted, so the returned tokens are left with the caller
+        if construction_call.is_aborted {
+            log!("The construction call was aborted, leaving the returned tokens with the caller");
+            self.returned_items = returned_items;
+            self.set_success();
+            return Ok(());
+        }
 
-        // TODO: error handle with malloc call core
+        let out_of_bounds = MallocError::ActionIndexOutOfBounds {
+            index: self.action_index_in_construction,
+            number_of_actions: construction.actions.0.len(),
+        };
+        let next_actions_indices = construction_call
+            .get_next_actions_indices(&construction, self.action_index_in_construction)
+            .ok_or_else(|| out_of_bounds.clone())?;
         let next_actions_splits = construction_call
-            .next_actions_splits
-            .0
-            .get(self.action_index_in_construction)
-            .unwrap();
+            .get_next_actions_splits(&construction, self.action_index_in_construction)
+            .ok_or(out_of_bounds)?;
         if next_actions_indices.0.len() != next_actions_splits.0.len() {
-            // TODO: error handling with the malloc call cores
-            panic!("");
+            return Err(MallocError::SplitsLengthMismatch {
+                expected: next_actions_indices.0.len(),
+                actual: next_actions_splits.0.len(),
+            });
         }
 
-        // TODO: error handle here
-        let amounts: Vec<u128> = results
-            .iter()
-            .map(|r| r.amount.parse::<u128>().unwrap())
-            .collect();
-
-        if amounts.len() != next_actions_indices.0.len() as usize {
-            // TODO: error handling
-            panic!("Expected the returned number of tokens to be the same length as next actions")
-        }
+        let set_items = match construction.get_next_actions_token_ids(self.action_index_in_construction) {
+            None => {
+                if returned_items.len() != next_actions_indices.0.len() as usize {
+                    return Err(MallocError::ReturnLengthMismatch {
+                        expected: next_actions_indices.0.len(),
+                        actual: returned_items.len() as u64,
+                    });
+                }
+                returned_items.iter().cloned().map(Some).collect()
+            }
+            Some(keys) => {
+                let (set_items, unmatched) = Construction::match_returned_items(&keys, &returned_items);
+                for item in unmatched {
+                    log!(
+                        "No set of next actions takes {}, leaving {} with the caller",
+                        item.token_id,
+                        item.amount.0
+                    );
+                }
+                set_items
+            }
+        };
 
-        for i in 0..next_actions_indices.0.len() {
-            construction_call = self.handle_next_split_set(
-                contract,
-                construction_call,
-                next_actions_indices.0.get(i).unwrap(),
-                next_actions_splits.0.get(i).unwrap(),
-                amounts[i as usize],
-            );
+        // All of the splits are computed before any tokens are passed on, so that a split which fails
+        // does not leave some of the returned tokens passed on
+        let next_amounts = set_items
+            .iter()
+            .zip(next_actions_splits.0.iter())
+            .map(|(item, splits)| {
+                item.as_ref()
+                    .map(|item| Construction::get_split_amounts(item.amount.0, &splits))
+                    .transpose()
+            })
+            .collect::<Result<Vec<Option<Vec<u128>>>, _>>()?;
+        for (i, (item, amounts)) in set_items.iter().zip(next_amounts).enumerate() {
+            let next_action_indxs = next_actions_indices.0.get(i as u64).unwrap();
+            match (item, amounts) {
+                (Some(item), Some(amounts)) => self.handle_next_split_set(
+                    contract,
+                    &construction,
+                    &mut construction_call,
+                    next_action_indxs,
+                    amounts,
+                    &item.token_id,
+                ),
+                // No token is returned for the set, so its next actions never get an input from this action
+                _ => construction_call.drop_inputs(contract, &construction, next_action_indxs),
+            }
         }
         contract
             .construction_calls
             .insert(&construction_call_id, &construction_call);
-        None
+        self.returned_items = returned_items;
+        self.set_success();
+        Ok(())
     }
 
     fn handle_next_split_set(
         &mut self,
         contract: &mut Contract,
-        mut construction_call: ConstructionCall,
+        construction: &Construction,
+        construction_call: &mut ConstructionCall,
         next_action_indxs: VectorWrapper<u64>,
-        next_splits: VectorWrapper<U128>,
-        amount: u128,
-    ) -> ConstructionCall {
-        let next_amounts = Construction::get_split_amounts(amount, next_splits);
+        next_amounts: Vec<u128>,
+        token_id: &AccountId,
+    ) {
         for i in 0..next_amounts.len() {
-            let (action_call, action_call_id) = ActionCall::new(
+            let next_action_index = next_action_indxs.0.get(i as u64).unwrap();
+            log!(
+                "Passing the returned tokens onto the action with index {}",
+                next_action_index
+            );
+            construction_call.add_action_input(
                 contract,
+                construction,
+                next_action_index,
+                token_id,
                 next_amounts[i],
-                next_action_indxs.0.get(i as u64).unwrap(),
             );
-            contract.action_calls.insert(&action_call_id, &action_call);
-            log!(
-                "Pushing a action_call with index into construction action calls of {}",
-                action_call.action_index_in_construction
-            );
-            construction_call
-                .next_action_calls_stack
-                .0
-                .push(&construction_call.action_calls.0.len());
-            construction_call.action_calls.0.push(&action_call_id);
         }
-        construction_call
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::test_utils::tests::return_item_eq;
+    use crate::test_utils::tests::{get_context, init_json_construction_call, return_item_eq, swap_action};
+    use crate::CoreFunctionality;
 
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+    use near_sdk::{MockedBlockchain, PromiseResult, RuntimeFeesConfig, VMConfig};
 
     #[test]
-    fn test_getting_result_from_bytes_error() {}
+    fn test_getting_result_from_bytes_error() {
+        let not_parsable = ActionCall::get_results_from_returned_bytes(b"{}".to_vec(), None);
+        assert_eq!(
+            not_parsable.err(),
+            Some(MallocError::ReturnNotParsable)
+        );
+        let missing_token_id = ActionCall::get_results_from_returned_bytes(b"\"10\"".to_vec(), None);
+        assert_eq!(
+            missing_token_id.err(),
+            Some(MallocError::ReturnTokenIdMissing)
+        );
+    }
 
     #[test]
     fn test_getting_result_from_bytes() {
@@ -382,4 +1028,134 @@ mod tests {
         ));
         assert!(return_item_eq(&from_amount[0], &from_ret_items[0]));
     }
+
+    #[test]
+    fn test_callback_matches_returned_tokens_by_token_id() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        init_json_construction_call(
+            &mut contract,
+            "keyed",
+            vec![("swap", swap_action())],
+            json!({
+                "actions": [
+                    { "owner": accounts(0), "name": "swap" },
+                    { "owner": accounts(0), "name": "swap" },
+                    { "owner": accounts(0), "name": "swap" }
+                ],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[1], [2]], [[]], [[]]],
+                "next_actions_splits": [[["1"], ["1"]], [[]], [[]]],
+                "next_actions_token_ids": [[{ "TokenId": "b.localnet" }, { "TokenId": "c.localnet" }], null, null]
+            }),
+            100,
+        );
+        contract.process_next_action_call("keyed".to_string());
+        let construction_call = contract.get_construction_call_unchecked(&"keyed".to_string());
+        let action_call_id = construction_call.action_calls.0.get(0).unwrap();
+
+        // The tokens come back out of order and one of them is not expected by any set of next actions
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                b"[{\"token_id\": \"d.localnet\", \"amount\": \"7\"}, {\"token_id\": \"c.localnet\", \"amount\": \"5\"}]"
+                    .to_vec()
+            )]
+        );
+        contract.handle_action_callback("keyed".to_string(), action_call_id, accounts(0).to_string(), None);
+
+        assert!(contract.get_action_call_unchecked(U64(action_call_id)).is_success());
+        let construction_call = contract.get_construction_call_unchecked(&"keyed".to_string());
+        assert_eq!(construction_call.action_calls.0.len(), 2);
+        let next_action_call = contract.get_action_call_unchecked(U64(construction_call.action_calls.0.get(1).unwrap()));
+        assert_eq!(next_action_call.action_index_in_construction, 2);
+        assert_eq!(next_action_call.amount, 5);
+    }
+
+    /// Swap the caller's tokens and pass the returned tokens on to a second swap, which requires at least 10 of them
+    fn init_min_returned_construction_call(contract: &mut Contract, construction_call_id: &str) -> u64 {
+        init_json_construction_call(
+            contract,
+            construction_call_id,
+            vec![("swap", swap_action())],
+            json!({
+                "actions": [
+                    { "owner": accounts(0), "name": "swap" },
+                    { "owner": accounts(0), "name": "swap" }
+                ],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[1]], [[]]],
+                "next_actions_splits": [[["1"]], [[]]],
+                "min_returned_amounts": [[{ "token_id": "b.localnet", "amount": "10" }], []]
+            }),
+            100,
+        );
+        contract.process_next_action_call(construction_call_id.to_string());
+        let construction_call = contract.get_construction_call_unchecked(&construction_call_id.to_string());
+        construction_call.action_calls.0.get(0).unwrap()
+    }
+
+    #[test]
+    fn test_returned_amount_below_minimum() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let action_call_id = init_min_returned_construction_call(&mut contract, "guarded");
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"5\"".to_vec())]
+        );
+        contract.handle_action_callback(
+            "guarded".to_string(),
+            action_call_id,
+            accounts(0).to_string(),
+            Some(ValidAccountId::try_from("b.localnet").unwrap()),
+        );
+
+        // Nothing is passed on, so the construction call is done with the returned tokens left with the caller
+        assert!(contract.get_construction_call_status("guarded".to_string()).is_none());
+        let summary = contract
+            .get_construction_call_summary("guarded".to_string())
+            .unwrap();
+        assert_eq!(summary.number_of_action_calls, 1);
+        assert_eq!(summary.number_of_errors, 1);
+    }
+
+    #[test]
+    fn test_returned_amount_meets_minimum() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let action_call_id = init_min_returned_construction_call(&mut contract, "guarded");
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"10\"".to_vec())]
+        );
+        contract.handle_action_callback(
+            "guarded".to_string(),
+            action_call_id,
+            accounts(0).to_string(),
+            Some(ValidAccountId::try_from("b.localnet").unwrap()),
+        );
+
+        assert!(contract.get_action_call_unchecked(U64(action_call_id)).is_success());
+        let construction_call = contract.get_construction_call_unchecked(&"guarded".to_string());
+        assert_eq!(construction_call.next_action_calls.to_vec(), vec![1]);
+        let next_action_call = contract.get_action_call_unchecked(U64(construction_call.action_calls.0.get(1).unwrap()));
+        assert_eq!(next_action_call.amount, 10);
+    }
 }
@@ -1,5 +1,6 @@
 use malloc_call_core::ft::{
-    FungibleTokenBalances, TransferType, MALLOC_CALL_CORE_GAS_FOR_FT_TRANSFER_CALL,
+    FungibleTokenBalances, TransferType, MALLOC_CALL_CORE_GAS_FOR_FT_TRANSFER,
+    MALLOC_CALL_CORE_GAS_FOR_FT_TRANSFER_CALL,
     MALLOC_CALL_CORE_GAS_FOR_WITHDRAW_WITH_FT_TRANSFER_CALL,
     MALLOC_CALL_CORE_GAS_FOR_WITHDRAW_TO
 };
@@ -10,7 +11,7 @@ use near_sdk::serde_json::json;
 use near_sdk::{env, log, AccountId, Gas};
 
 use crate::action::ActionCall;
-use crate::errors::PanicError;
+use crate::errors::MallocError;
 use crate::gas::{CALLBACK_GAS, CROSS_CONTRACT_BASE_GAS};
 
 use super::ActionFunctions;
@@ -24,6 +25,14 @@ pub struct FtTransferCallToMallocCall {
     pub token_id: ValidAccountId,
 }
 
+/// Transfer tokens from the caller's balance straight to an account, which need not be a malloc call
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtTransfer {
+    pub receiver_id: ValidAccountId,
+    pub token_id: ValidAccountId,
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct WithdrawFromMallocCall {
@@ -40,7 +49,8 @@ impl ActionFunctions for WithdrawFromMallocCall {
         construction_call_id: &crate::construction::ConstructionCallId,
         action_call_id: crate::action::ActionCallId,
         caller: &AccountId,
-    ) -> Result<u64, PanicError> {
+        callback_gas: Gas,
+    ) -> Result<u64, MallocError> {
         let token_id: AccountId = self.token_id.to_string();
         let malloc_call_id: AccountId = self.malloc_call_id.to_string();
 
@@ -66,7 +76,9 @@ impl ActionFunctions for WithdrawFromMallocCall {
             None,
             transfer_type,
         )
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| MallocError::ArgsNotSerializable {
+            message: e.to_string(),
+        })?;
 
         let prom = env::promise_batch_create(malloc_call_id);
         env::promise_batch_action_function_call(
@@ -89,15 +101,23 @@ impl ActionFunctions for WithdrawFromMallocCall {
             b"handle_action_callback",
             callback_args.as_bytes(),
             0,
-            CALLBACK_GAS,
+            callback_gas,
         );
 
         Ok(callback)
     }
 
-    fn get_gas_requirement(&self, action_call: &ActionCall) -> Result<Gas, PanicError> {
+    fn get_gas_requirement(&self, action_call: &ActionCall) -> Result<Gas, MallocError> {
         Ok(MALLOC_CALL_CORE_GAS_FOR_WITHDRAW_TO + HANDLE_GAS + CALLBACK_GAS + CROSS_CONTRACT_BASE_GAS)
     }
+
+    fn get_token_id(&self) -> AccountId {
+        self.token_id.to_string()
+    }
+
+    fn get_malloc_call_id(&self) -> Option<AccountId> {
+        Some(self.malloc_call_id.to_string())
+    }
 }
 
 impl ActionFunctions for FtTransferCallToMallocCall {
@@ -108,9 +128,11 @@ impl ActionFunctions for FtTransferCallToMallocCall {
         construction_call_id: &crate::construction::ConstructionCallId,
         action_call_id: crate::action::ActionCallId,
         caller: &AccountId,
-    ) -> Result<u64, crate::errors::PanicError> {
+        callback_gas: Gas,
+    ) -> Result<u64, crate::errors::MallocError> {
         let token_id: AccountId = self.token_id.to_string();
         let malloc_call_id: AccountId = self.malloc_call_id.to_string();
+        contract.check_caller_balance(caller, &token_id, action_call.amount)?;
         let prom = contract.balances.internal_ft_transfer_call(
             &token_id,
             malloc_call_id,
@@ -130,12 +152,77 @@ impl ActionFunctions for FtTransferCallToMallocCall {
             b"handle_action_callback",
             callback_args.as_bytes(),
             0,
-            CALLBACK_GAS,
+            callback_gas,
         );
         Ok(callback)
     }
 
-    fn get_gas_requirement(&self, _action_call: &ActionCall) -> Result<Gas, PanicError> {
+    fn get_gas_requirement(&self, _action_call: &ActionCall) -> Result<Gas, MallocError> {
         Ok(MALLOC_CALL_CORE_GAS_FOR_FT_TRANSFER_CALL + CALLBACK_GAS + HANDLE_GAS)
     }
+
+    fn get_token_id(&self) -> AccountId {
+        self.token_id.to_string()
+    }
+
+    fn get_malloc_call_id(&self) -> Option<AccountId> {
+        Some(self.malloc_call_id.to_string())
+    }
+}
+
+impl ActionFunctions for FtTransfer {
+    /// Transfer the action call's amount with malloc-call-core's internal_ft_transfer. The callback follows the transfer's
+    /// resolve step, which returns the amount which was actually delivered
+    fn handle(
+        &self,
+        contract: &mut crate::Contract,
+        action_call: &ActionCall,
+        construction_call_id: &crate::construction::ConstructionCallId,
+        action_call_id: crate::action::ActionCallId,
+        caller: &AccountId,
+        callback_gas: Gas,
+    ) -> Result<u64, MallocError> {
+        let token_id: AccountId = self.token_id.to_string();
+        contract.check_caller_balance(caller, &token_id, action_call.amount)?;
+        let prom = contract.balances.internal_ft_transfer(
+            &token_id,
+            self.receiver_id.to_string(),
+            U128(action_call.amount),
+            caller.clone(),
+            None,
+            None,
+        );
+        let callback = env::promise_batch_then(prom, env::current_account_id());
+        let callback_args = ActionCall::get_callback_args(
+            construction_call_id,
+            &action_call_id,
+            caller,
+            Some(&token_id),
+        );
+        env::promise_batch_action_function_call(
+            callback,
+            b"handle_action_callback",
+            callback_args.as_bytes(),
+            0,
+            callback_gas,
+        );
+        Ok(callback)
+    }
+
+    fn get_gas_requirement(&self, _action_call: &ActionCall) -> Result<Gas, MallocError> {
+        Ok(MALLOC_CALL_CORE_GAS_FOR_FT_TRANSFER + CALLBACK_GAS + HANDLE_GAS)
+    }
+
+    fn get_token_id(&self) -> AccountId {
+        self.token_id.to_string()
+    }
+
+    fn get_malloc_call_id(&self) -> Option<AccountId> {
+        None
+    }
+
+    /// The delivered tokens belong to the receiver
+    fn is_leaf(&self) -> bool {
+        true
+    }
 }
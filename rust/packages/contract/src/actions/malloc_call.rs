@@ -4,9 +4,11 @@ use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::serde_json::json;
 use near_sdk::{env, log, AccountId, Gas};
 
-use crate::errors::PanicError;
-use crate::gas::CALLBACK_GAS;
+use crate::errors::MallocError;
+use crate::gas::{CALLBACK_GAS, RECLAIM_GAS};
 use crate::action::ActionCall;
+use crate::migrations::MallocCallV0;
+use crate::Contract;
 
 use super::ActionFunctions;
 
@@ -20,8 +22,48 @@ pub struct MallocCall {
     malloc_call_id: AccountId,
     token_id: AccountId,
     json_args: String,
-    gas: Gas,
-    attached_amount: near_sdk::json_types::U128,
+    /// Defaults to the gas required by the malloc call's registered metadata
+    gas: Option<Gas>,
+    /// Defaults to the attachment required by the malloc call's registered metadata
+    attached_amount: Option<U128>,
+    /// If set, the malloc call implements malloc-call-core's Revert trait and its revert method
+    /// is called with this much gas when the construction call is aborted
+    revert_gas: Option<Gas>,
+}
+
+impl MallocCall {
+    /// Fill in the gas and the attached amount which are not set from the malloc call's registered metadata.
+    /// The malloc call must be registered
+    pub(crate) fn set_defaults_from_registry(&mut self, contract: &Contract) -> Result<(), MallocError> {
+        let entry = contract.get_registered_malloc_call(&self.malloc_call_id)?;
+        self.gas.get_or_insert(entry.metadata.gas_required);
+        self.attached_amount
+            .get_or_insert(entry.metadata.attachment_required);
+        Ok(())
+    }
+
+    fn get_gas(&self) -> Gas {
+        self.gas.unwrap_or(0)
+    }
+
+    fn get_attached_amount(&self) -> u128 {
+        self.attached_amount.map(|a| a.0).unwrap_or(0)
+    }
+}
+
+impl From<MallocCallV0> for MallocCall {
+    fn from(call: MallocCallV0) -> Self {
+        MallocCall {
+            check_callback: call.check_callback,
+            skip_ft_transfer: call.skip_ft_transfer,
+            malloc_call_id: call.malloc_call_id,
+            token_id: call.token_id,
+            json_args: call.json_args,
+            gas: Some(call.gas),
+            attached_amount: Some(call.attached_amount),
+            revert_gas: None,
+        }
+    }
 }
 
 impl ActionFunctions for MallocCall {
@@ -32,7 +74,10 @@ impl ActionFunctions for MallocCall {
         construction_call_id: &crate::construction::ConstructionCallId,
         action_call_id: crate::action::ActionCallId,
         caller: &AccountId,
-    ) -> Result<u64, crate::errors::PanicError> {
+        callback_gas: Gas,
+    ) -> Result<u64, crate::errors::MallocError> {
+        // Malloc calls which were removed from the registry are not run
+        contract.get_registered_malloc_call(&self.malloc_call_id)?;
         let token_contract_id = self.token_id.clone();
         let call_data = format!(
             "{{\"args\": {}, \"amount\": \"{}\", \"token_id\": \"{}\", \"caller\": \"{}\"}}",
@@ -44,7 +89,8 @@ impl ActionFunctions for MallocCall {
 
         log!("Action call amount: {}", action_call.amount);
 
-        let call_prom = if action_call.amount > 0 && !self.skip_ft_transfer.unwrap_or(false) {
+        let call_prom = if self.reclaims_on_failure(action_call) {
+            contract.check_caller_balance(caller, &self.token_id, action_call.amount)?;
             // TODO: what if the ft_transfer prom fails???
             // See https://github.com/Lev-Stambler/malloc-near-2/issues/27
             let transfer_call_prom = contract.balances.internal_ft_transfer_call(
@@ -60,8 +106,8 @@ impl ActionFunctions for MallocCall {
                 self.malloc_call_id.to_string(),
                 &malloc_call_core::call_method_name(),
                 call_data.as_bytes(),
-                self.attached_amount.into(),
-                self.gas,
+                self.get_attached_amount(),
+                self.get_gas(),
             );
             call_prom
         } else {
@@ -70,8 +116,8 @@ impl ActionFunctions for MallocCall {
                 call_prom,
                 &malloc_call_core::call_method_name(),
                 call_data.as_bytes(),
-                self.attached_amount.into(),
-                self.gas,
+                self.get_attached_amount(),
+                self.get_gas(),
             );
             call_prom
         };
@@ -83,6 +129,12 @@ impl ActionFunctions for MallocCall {
             }
         }
 
+        // The callback reclaims the sent tokens if the malloc call fails
+        let callback_gas = if self.reclaims_on_failure(action_call) {
+            callback_gas + RECLAIM_GAS
+        } else {
+            callback_gas
+        };
         let callback = env::promise_batch_then(call_prom, env::current_account_id());
         let callback_args =
             ActionCall::get_callback_args(construction_call_id, &action_call_id, caller, None);
@@ -91,23 +143,75 @@ impl ActionFunctions for MallocCall {
             b"handle_action_callback",
             callback_args.as_bytes(),
             0,
-            CALLBACK_GAS,
+            callback_gas,
         );
         Ok(callback)
     }
 
-    fn get_gas_requirement(&self, action_call: &ActionCall) -> Result<Gas, PanicError> {
-        let callback_gas = if self.check_callback.unwrap_or(true) {
-            CALLBACK_GAS
+    fn get_gas_requirement(&self, action_call: &ActionCall) -> Result<Gas, MallocError> {
+        let callback_gas = match (self.check_callback.unwrap_or(true), self.reclaims_on_failure(action_call)) {
+            (false, _) => 0,
+            (true, false) => CALLBACK_GAS,
+            (true, true) => CALLBACK_GAS + RECLAIM_GAS,
+        };
+        let ft_transfer_call_gas = if self.reclaims_on_failure(action_call) {
+            malloc_call_core::ft::MALLOC_CALL_CORE_GAS_FOR_FT_TRANSFER_CALL
         } else {
             0
         };
-        let ft_transfer_call_gas =
-            if self.skip_ft_transfer.unwrap_or(false) || action_call.amount == 0 {
-                0
-            } else {
-                malloc_call_core::ft::MALLOC_CALL_CORE_GAS_FOR_FT_TRANSFER_CALL
-            };
-        Ok(callback_gas + self.gas + ft_transfer_call_gas + HANDLE_GAS)
+        Ok(callback_gas + self.get_gas() + ft_transfer_call_gas + HANDLE_GAS)
+    }
+
+    fn has_callback(&self) -> bool {
+        self.check_callback.unwrap_or(true)
+    }
+
+    /// The tokens are sent to the malloc call before it is called, so they stay there if the call fails
+    fn reclaims_on_failure(&self, action_call: &ActionCall) -> bool {
+        action_call.amount > 0 && !self.skip_ft_transfer.unwrap_or(false)
+    }
+
+    fn get_token_id(&self) -> AccountId {
+        self.token_id.clone()
+    }
+
+    fn get_malloc_call_id(&self) -> Option<AccountId> {
+        Some(self.malloc_call_id.clone())
+    }
+
+    fn handle_revert(
+        &self,
+        action_call: &ActionCall,
+        caller: &AccountId,
+        prior_promise: Option<u64>,
+    ) -> Option<u64> {
+        let revert_gas = self.revert_gas?;
+        let returned = json!(action_call.returned_items).to_string();
+        let revert_data = format!(
+            "{{\"call_context\": {{\"args\": {}, \"amount\": \"{}\", \"token_id\": \"{}\", \"caller\": \"{}\", \"returned\": {}}}}}",
+            self.json_args, action_call.amount, self.token_id, caller, returned
+        );
+        let prom = match prior_promise {
+            None => {
+                let prom = env::promise_batch_create(self.malloc_call_id.clone());
+                env::promise_batch_action_function_call(
+                    prom,
+                    &malloc_call_core::revert_method_name(),
+                    revert_data.as_bytes(),
+                    0,
+                    revert_gas,
+                );
+                prom
+            }
+            Some(prior_promise) => env::promise_then(
+                prior_promise,
+                self.malloc_call_id.clone(),
+                &malloc_call_core::revert_method_name(),
+                revert_data.as_bytes(),
+                0,
+                revert_gas,
+            ),
+        };
+        Some(prom)
     }
 }
@@ -1,12 +1,15 @@
-use near_sdk::{AccountId, Gas};
+use near_sdk::{json_types::U128, AccountId, Gas};
 
-use crate::{Contract, construction::ConstructionCallId, errors::PanicError, action::{ActionCall, ActionCallId}};
+use crate::{Contract, construction::ConstructionCallId, errors::MallocError, action::{ActionCall, ActionCallId}};
 
 pub mod ft_calls;
 pub mod malloc_call;
+pub mod near_calls;
+pub mod route;
 
 pub trait ActionFunctions {
 		/// Handle a action
+		/// @param callback_gas - the gas attached to the callback to handle_action_callback
 		/// @returns a result of a promise index
     fn handle(
         &self,
@@ -15,7 +18,66 @@ pub trait ActionFunctions {
         construction_call_id: &ConstructionCallId,
         action_call_id: ActionCallId,
         caller: &AccountId,
-    ) -> Result<u64, PanicError>;
+        callback_gas: Gas,
+    ) -> Result<u64, MallocError>;
 
-		fn get_gas_requirement(&self, action_call: &ActionCall) -> Result<Gas, PanicError>;
+		fn get_gas_requirement(&self, action_call: &ActionCall) -> Result<Gas, MallocError>;
+
+		/// The token which the action spends
+		fn get_token_id(&self) -> AccountId;
+
+		/// The malloc call which the action sends tokens to or withdraws tokens from, if any
+		fn get_malloc_call_id(&self) -> Option<AccountId>;
+
+		/// Call the revert method of a completed action call's malloc call, if the action supports reverting.
+		/// See malloc-call-core's Revert trait
+		/// @param prior_promise - if set, the revert is run after this promise
+		/// @returns the promise index of the revert, or None if the action cannot be reverted
+		fn handle_revert(
+				&self,
+				_action_call: &ActionCall,
+				_caller: &AccountId,
+				_prior_promise: Option<u64>,
+		) -> Option<u64> {
+				None
+		}
+
+		/// Whether the tokens which the action returns have left the caller's balance, as for a transfer to an external account.
+		/// Such an action must be a leaf of the construction, so it cannot have next actions
+		fn is_leaf(&self) -> bool {
+				false
+		}
+
+		/// Whether the caller's tokens are left with the action's malloc call when the action call fails after sending them,
+		/// as no resolve step credits them back. They are then reclaimed from the malloc call
+		fn reclaims_on_failure(&self, _action_call: &ActionCall) -> bool {
+				false
+		}
+
+		/// Whether the action schedules a callback to handle_action_callback.
+		/// If it does not, the action call is finished as soon as it is handled
+		fn has_callback(&self) -> bool {
+				true
+		}
+}
+
+impl Contract {
+    /// Check that the caller holds at least amount of the token before an action takes it from the caller's balance.
+    /// Taking more than the balance panics, which would roll back the record of the failed action call
+    pub(crate) fn check_caller_balance(
+        &self,
+        caller: &AccountId,
+        token_id: &AccountId,
+        amount: u128,
+    ) -> Result<(), MallocError> {
+        let balance = self.balances.get_ft_balance(caller, token_id);
+        if balance < amount {
+            return Err(MallocError::InsufficientBalance {
+                token_id: token_id.clone(),
+                balance: U128(balance),
+                amount: U128(amount),
+            });
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,245 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::{json, Value};
+use near_sdk::{env, AccountId, Gas};
+
+use crate::action::ActionCall;
+use crate::errors::MallocError;
+use crate::gas::{CALLBACK_GAS, RESOLVE_NEAR_GAS, WNEAR_CALL_GAS};
+use crate::Contract;
+
+use super::ActionFunctions;
+
+/// The token id under which native NEAR is held in the caller's internal balance.
+/// Account ids cannot contain uppercase letters, so no token contract can deposit under it
+pub const NEAR_TOKEN_ID: &str = "NEAR";
+
+const HANDLE_GAS: Gas = 2_000_000_000_000;
+
+/// Send NEAR from the caller's internal NEAR balance to an account
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NearTransfer {
+    pub receiver_id: ValidAccountId,
+}
+
+/// Wrap NEAR from the caller's internal NEAR balance into wNEAR, which is credited to the caller's wNEAR balance
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WrapNear {
+    /// Must be the wNEAR contract which is configured in the malloc contract
+    pub wnear_contract_id: ValidAccountId,
+}
+
+/// Unwrap wNEAR from the caller's internal wNEAR balance, which is credited to the caller's NEAR balance
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UnwrapNear {
+    /// Must be the wNEAR contract which is configured in the malloc contract
+    pub wnear_contract_id: ValidAccountId,
+}
+
+/// Check that the action's wNEAR contract is the one configured in the malloc contract
+fn check_wnear_contract_id(
+    contract: &Contract,
+    wnear_contract_id: &ValidAccountId,
+) -> Result<(), MallocError> {
+    match contract.wnear_contract_id.as_ref() {
+        None => Err(MallocError::WnearContractNotConfigured),
+        Some(id) if id != wnear_contract_id.as_ref() => Err(MallocError::WnearContractDoesNotMatch {
+            expected: id.clone(),
+            actual: wnear_contract_id.to_string(),
+        }),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Chain a resolve method of the malloc contract and then the action callback onto the action's promise.
+/// The resolve method returns the amount which the action produced
+fn resolve_then_callback(
+    prom: u64,
+    resolve_method_name: &[u8],
+    resolve_args: Value,
+    callback_args: String,
+    callback_gas: Gas,
+) -> u64 {
+    let resolve = env::promise_then(
+        prom,
+        env::current_account_id(),
+        resolve_method_name,
+        resolve_args.to_string().as_bytes(),
+        0,
+        RESOLVE_NEAR_GAS,
+    );
+    let callback = env::promise_batch_then(resolve, env::current_account_id());
+    env::promise_batch_action_function_call(
+        callback,
+        b"handle_action_callback",
+        callback_args.as_bytes(),
+        0,
+        callback_gas,
+    );
+    callback
+}
+
+impl ActionFunctions for NearTransfer {
+    fn handle(
+        &self,
+        contract: &mut Contract,
+        action_call: &ActionCall,
+        construction_call_id: &crate::construction::ConstructionCallId,
+        action_call_id: crate::action::ActionCallId,
+        caller: &AccountId,
+        callback_gas: Gas,
+    ) -> Result<u64, MallocError> {
+        contract.check_caller_balance(caller, &NEAR_TOKEN_ID.to_string(), action_call.amount)?;
+        contract
+            .balances
+            .subtract_balance(caller, &NEAR_TOKEN_ID.to_string(), action_call.amount);
+        let prom = env::promise_batch_create(self.receiver_id.as_ref());
+        env::promise_batch_action_transfer(prom, action_call.amount);
+        let callback_args = ActionCall::get_callback_args(
+            construction_call_id,
+            &action_call_id,
+            caller,
+            Some(&NEAR_TOKEN_ID.to_string()),
+        );
+        Ok(resolve_then_callback(
+            prom,
+            b"resolve_near_transfer",
+            json!({ "account_id": caller, "amount": U128(action_call.amount) }),
+            callback_args,
+            callback_gas,
+        ))
+    }
+
+    fn get_gas_requirement(&self, _action_call: &ActionCall) -> Result<Gas, MallocError> {
+        Ok(RESOLVE_NEAR_GAS + CALLBACK_GAS + HANDLE_GAS)
+    }
+
+    fn get_token_id(&self) -> AccountId {
+        NEAR_TOKEN_ID.to_string()
+    }
+
+    fn get_malloc_call_id(&self) -> Option<AccountId> {
+        None
+    }
+
+    /// The sent NEAR belongs to the receiver
+    fn is_leaf(&self) -> bool {
+        true
+    }
+}
+
+impl ActionFunctions for WrapNear {
+    fn handle(
+        &self,
+        contract: &mut Contract,
+        action_call: &ActionCall,
+        construction_call_id: &crate::construction::ConstructionCallId,
+        action_call_id: crate::action::ActionCallId,
+        caller: &AccountId,
+        callback_gas: Gas,
+    ) -> Result<u64, MallocError> {
+        check_wnear_contract_id(contract, &self.wnear_contract_id)?;
+        contract.check_caller_balance(caller, &NEAR_TOKEN_ID.to_string(), action_call.amount)?;
+        contract
+            .balances
+            .subtract_balance(caller, &NEAR_TOKEN_ID.to_string(), action_call.amount);
+        let prom = env::promise_batch_create(self.wnear_contract_id.as_ref());
+        env::promise_batch_action_function_call(
+            prom,
+            b"near_deposit",
+            b"{}",
+            action_call.amount,
+            WNEAR_CALL_GAS,
+        );
+        let callback_args = ActionCall::get_callback_args(
+            construction_call_id,
+            &action_call_id,
+            caller,
+            Some(&self.wnear_contract_id.to_string()),
+        );
+        Ok(resolve_then_callback(
+            prom,
+            b"resolve_wrap_near",
+            json!({
+                "account_id": caller,
+                "amount": U128(action_call.amount),
+                "wnear_contract_id": self.wnear_contract_id
+            }),
+            callback_args,
+            callback_gas,
+        ))
+    }
+
+    fn get_gas_requirement(&self, _action_call: &ActionCall) -> Result<Gas, MallocError> {
+        Ok(WNEAR_CALL_GAS + RESOLVE_NEAR_GAS + CALLBACK_GAS + HANDLE_GAS)
+    }
+
+    fn get_token_id(&self) -> AccountId {
+        NEAR_TOKEN_ID.to_string()
+    }
+
+    fn get_malloc_call_id(&self) -> Option<AccountId> {
+        None
+    }
+}
+
+impl ActionFunctions for UnwrapNear {
+    fn handle(
+        &self,
+        contract: &mut Contract,
+        action_call: &ActionCall,
+        construction_call_id: &crate::construction::ConstructionCallId,
+        action_call_id: crate::action::ActionCallId,
+        caller: &AccountId,
+        callback_gas: Gas,
+    ) -> Result<u64, MallocError> {
+        check_wnear_contract_id(contract, &self.wnear_contract_id)?;
+        contract.check_caller_balance(caller, &self.wnear_contract_id.to_string(), action_call.amount)?;
+        contract
+            .balances
+            .subtract_balance(caller, &self.wnear_contract_id.to_string(), action_call.amount);
+        let prom = env::promise_batch_create(self.wnear_contract_id.as_ref());
+        env::promise_batch_action_function_call(
+            prom,
+            b"near_withdraw",
+            json!({ "amount": U128(action_call.amount) })
+                .to_string()
+                .as_bytes(),
+            1,
+            WNEAR_CALL_GAS,
+        );
+        let callback_args = ActionCall::get_callback_args(
+            construction_call_id,
+            &action_call_id,
+            caller,
+            Some(&NEAR_TOKEN_ID.to_string()),
+        );
+        Ok(resolve_then_callback(
+            prom,
+            b"resolve_unwrap_near",
+            json!({
+                "account_id": caller,
+                "amount": U128(action_call.amount),
+                "wnear_contract_id": self.wnear_contract_id
+            }),
+            callback_args,
+            callback_gas,
+        ))
+    }
+
+    fn get_gas_requirement(&self, _action_call: &ActionCall) -> Result<Gas, MallocError> {
+        Ok(WNEAR_CALL_GAS + RESOLVE_NEAR_GAS + CALLBACK_GAS + HANDLE_GAS)
+    }
+
+    fn get_token_id(&self) -> AccountId {
+        self.wnear_contract_id.to_string()
+    }
+
+    fn get_malloc_call_id(&self) -> Option<AccountId> {
+        None
+    }
+}
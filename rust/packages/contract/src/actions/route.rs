@@ -0,0 +1,78 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{AccountId, Gas};
+
+use crate::action::ActionCall;
+use crate::errors::MallocError;
+
+use super::ActionFunctions;
+
+/// A predicate on the amount which reaches a route
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum AmountPredicate {
+    AtLeast(U128),
+    LessThan(U128),
+}
+
+impl AmountPredicate {
+    pub fn holds(&self, amount: u128) -> bool {
+        match self {
+            AmountPredicate::AtLeast(min) => amount >= min.0,
+            AmountPredicate::LessThan(max) => amount < max.0,
+        }
+    }
+}
+
+/// Pass the incoming amount on to one of the route's branches, which are its sets of next actions.
+/// The predicates are checked in order and the amount goes to the branch of the first one which holds,
+/// or to the last branch if none holds. So a route has one more set of next actions than it has predicates.
+/// A route makes no calls, it is resolved as soon as its input arrives
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Route {
+    pub token_id: ValidAccountId,
+    pub predicates: Vec<AmountPredicate>,
+}
+
+impl Route {
+    /// Get the index of the branch which the amount is passed on to
+    pub fn pick_branch(&self, amount: u128) -> u64 {
+        self.predicates
+            .iter()
+            .position(|predicate| predicate.holds(amount))
+            .unwrap_or(self.predicates.len()) as u64
+    }
+
+    pub fn get_number_of_branches(&self) -> u64 {
+        self.predicates.len() as u64 + 1
+    }
+}
+
+impl ActionFunctions for Route {
+    /// Routes are never scheduled as a step, see ConstructionCall::run_route
+    fn handle(
+        &self,
+        _contract: &mut crate::Contract,
+        _action_call: &ActionCall,
+        _construction_call_id: &crate::construction::ConstructionCallId,
+        _action_call_id: crate::action::ActionCallId,
+        _caller: &AccountId,
+        _callback_gas: Gas,
+    ) -> Result<u64, MallocError> {
+        Err(MallocError::RouteNotRunnable)
+    }
+
+    fn get_gas_requirement(&self, _action_call: &ActionCall) -> Result<Gas, MallocError> {
+        Ok(0)
+    }
+
+    fn get_token_id(&self) -> AccountId {
+        self.token_id.to_string()
+    }
+
+    fn get_malloc_call_id(&self) -> Option<AccountId> {
+        None
+    }
+}
@@ -1,179 +1,1484 @@
+use crate::actions::route::Route;
+use crate::actions::ActionFunctions;
 use crate::action::{
-    ActionCall, ActionId, NextActionsIndicesForAction, NextActionsSplitsForAction,
+    Action, ActionCall, ActionCallStatus, ActionId, NextActionsIndicesForAction,
+    NextActionsSplitsForAction, TokenAmount,
 };
-use crate::malloc_utils::GenericId;
-use crate::malloc_utils::U256;
+use crate::gas::{CROSS_CONTRACT_BASE_GAS, SELF_DRIVE_RESERVE_GAS};
+use crate::splits::SplitSpec;
+use crate::malloc_utils::{insert_into_account_set, remove_from_account_set, GenericId};
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::json_types::U128;
+use near_sdk::json_types::{U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{collections::Vector, env, AccountId};
+use near_sdk::{env, log, AccountId, Gas, StorageUsage};
 
 pub type ConstructionCallId = String;
 
-/// A Construction is the collection of actions. It can be used to form the call DAG
+/// The number of blocks after which an executing action call can be marked as timed out if its construction
+/// does not set a timeout
+pub const DEFAULT_ACTION_CALL_TIMEOUT_BLOCKS: u64 = 1_000;
+
+/// A Construction is the collection of actions along with the routing topology which forms the call DAG.
+/// The topology is stored once so that calling a construction only needs its id and an amount
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Construction {
     pub actions: VectorWrapper<ActionId>,
+    /// The indices of the actions which are called first
+    pub initial_action_indices: VectorWrapper<u64>,
+    /// The splits of the input amount between the initial actions
+    pub initial_splits: SplitSpec,
+    /// For each action, the sets of next actions which each of the action's returned tokens are passed to
+    pub next_actions_indices: NextActionsIndicesForConstruction,
+    /// For each action, the splits for each set of next actions
+    pub next_actions_splits: NextActionsSplitsForConstruction,
+    /// For each action, the keys which its returned tokens are matched to its sets of next actions by.
+    /// Defaults to matching the returned tokens to the sets of next actions by position
+    pub next_actions_token_ids: Option<NextActionsTokenIdsForConstruction>,
+    /// For each action, the minimum amount of each token which it must return. An action call which returns less
+    /// fails and passes nothing on, leaving its returned tokens with the caller
+    pub min_returned_amounts: Option<MinReturnedAmountsForConstruction>,
+    /// The order in which the action calls are run. Defaults to depth first
+    pub scheduling: Option<SchedulingMode>,
+    /// The number of blocks after which an executing action call can be marked as timed out.
+    /// Defaults to DEFAULT_ACTION_CALL_TIMEOUT_BLOCKS
+    pub action_call_timeout_blocks: Option<u64>,
+}
+
+/// The order in which the pending action calls of a construction call are run
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum SchedulingMode {
+    /// Depth first: the most recently scheduled action call is run first
+    Dfs,
+    /// Breadth first: action calls are run level by level in the order in which they are scheduled
+    Bfs,
+    /// The action call with the lowest action index in the construction is run first
+    Priority,
 }
 
 pub type ConstructionId = GenericId;
 
 pub type NextActionsIndicesForConstruction = VectorWrapper<NextActionsIndicesForAction>;
 pub type NextActionsSplitsForConstruction = VectorWrapper<NextActionsSplitsForAction>;
+/// None matches the action's returned tokens to its sets of next actions by position
+pub type NextActionsTokenIdsForAction = Option<Vec<NextActionsKey>>;
+pub type NextActionsTokenIdsForConstruction = VectorWrapper<NextActionsTokenIdsForAction>;
+pub type MinReturnedAmountsForConstruction = VectorWrapper<Vec<TokenAmount>>;
+
+/// The key of a set of next actions when an action's returned tokens are matched by token id
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum NextActionsKey {
+    TokenId(AccountId),
+    /// Takes a returned token which none of the action's other sets of next actions are keyed by
+    CatchAll,
+}
+
+impl ClearStorage for VectorWrapper<NextActionsTokenIdsForAction> {
+    fn clear_storage(&mut self) {
+        self.0.clear();
+    }
+}
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug)]
 #[serde(crate = "near_sdk::serde")]
 /// ConstructionCalls contain all the dynamic data when calling a construction
-/// They are unique per construction call, so once a user is done calling a construction,
-/// They should delete the construction call
+/// They are unique per construction call. Once no action calls are left to run and all the action calls are finished,
+/// the construction call is garbage collected and only a ConstructionCallSummary is kept
 pub struct ConstructionCall {
     pub caller: AccountId,
     pub construction_id: ConstructionId,
-    // TODO: ideally we want to have a queue not a stack (so we have BFS not DFS). But, Vector only supports O(1) stack ops.
-    // See https://github.com/Lev-Stambler/malloc-near-2/issues/16
-    /// A vector which indexes into the action call vector
-    /// Indicates which action_calls should be called next
-    pub next_action_calls_stack: VectorWrapper<u64>,
+    /// A deque which indexes into the action call vector
+    /// Indicates which action_calls should be called next. The order in which they are taken
+    /// off of the deque depends on the scheduling mode
+    pub next_action_calls: DequeWrapper<u64>,
+    pub scheduling_mode: SchedulingMode,
     /// A vector which contains the action call id's for all the actions which the
     /// construction call already interacted with (either pushing it onto the stack, executing it, or handling a action's return
     pub action_calls: VectorWrapper<ActionCallId>,
 
-    // TODO: move into separate lookup table with id ref: See https://github.com/Lev-Stambler/malloc-near-2/issues/17
-    pub next_actions_indices_in_construction: NextActionsIndicesForConstruction,
-    pub next_actions_splits: NextActionsSplitsForConstruction,
+    /// If true, the callback of each action call schedules the next steps itself until the stack
+    /// is empty or the prepaid gas is used up. See run_construction_call
+    pub is_self_driving: bool,
+
+    /// Overrides the construction's next actions indices for this call only
+    pub next_actions_indices_override: Option<NextActionsIndicesForConstruction>,
+    /// Overrides the construction's next actions splits for this call only
+    pub next_actions_splits_override: Option<NextActionsSplitsForConstruction>,
+
+    /// For each action in the construction, the number of incoming edges in the call's topology, counting the initial actions.
+    /// Actions with more than one incoming edge are joins
+    pub action_number_of_inputs: VectorWrapper<u64>,
+    /// For each action which is a join, the index into action_calls of its action call once it received its first input
+    pub join_action_calls: VectorWrapper<Option<u64>>,
+
+    /// Set once the caller aborts the construction call. No new action calls are scheduled after an abort
+    pub is_aborted: bool,
+}
+
+/// A compact record of a construction call which is kept after the construction call is garbage collected
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConstructionCallSummary {
+    pub caller: AccountId,
+    pub construction_id: ConstructionId,
+    pub number_of_action_calls: u64,
+    pub number_of_errors: u64,
+    pub block_index_finished: u64,
+}
+
+/// The progress of a single action call within a construction call
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ActionCallProgress {
+    pub action_call_id: U64,
+    pub action_index_in_construction: u64,
+    pub action_id: ActionId,
+    /// The amount which the action call received
+    pub amount: U128,
+    pub status: ActionCallStatus,
+    /// The tokens returned by the action call once it succeeded
+    pub returned_items: Vec<TokenAmount>,
+    /// The branch which a route action call passed its input on to
+    pub route_branch: Option<u64>,
+    pub block_index_start: Option<U64>,
+    pub block_index_end: Option<U64>,
+}
+
+/// The status of a construction call along with the progress of each of its action calls
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConstructionCallStatus {
+    pub caller: AccountId,
+    pub construction_id: ConstructionId,
+    pub action_calls: Vec<ActionCallProgress>,
+    /// The number of action calls which are scheduled and waiting to be run
+    pub number_scheduled: u64,
+    /// The number of joins which are waiting on some of their inputs
+    pub number_waiting_inputs: u64,
+    pub number_executing: u64,
+    pub number_succeeded: u64,
+    pub number_errored: u64,
+    pub number_reverted: u64,
+}
+
+/// A registered construction along with its metadata
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConstructionInfo {
+    pub id: ConstructionId,
+    pub number_of_actions: u64,
+    /// The distinct tokens which the construction's actions spend
+    pub token_ids: Vec<AccountId>,
 }
 
-use crate::errors::panic_errors::{self, NUMB_OF_SPLITS_DOES_NOT_EQUAL_NUMB_AMOUNTS};
-use crate::{errors::PanicError, vector_wrapper::VectorWrapper, ActionCallId, Contract};
+/// The gas needed for a single action call when running a construction
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ActionCallGasEstimate {
+    pub action_index_in_construction: u64,
+    pub action_id: ActionId,
+    pub gas: U64,
+}
+
+/// The gas needed to run a whole construction
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConstructionGasEstimate {
+    /// The total gas, including the gas for the call which starts running the construction
+    pub total: U64,
+    /// The gas for each action call, in the order in which they are reached in the construction's DAG
+    pub action_calls: Vec<ActionCallGasEstimate>,
+}
+
+/// The maximum number of action calls which are walked when estimating gas. Reaching it means the
+/// construction is either huge or has a cycle
+const MAX_ESTIMATED_ACTION_CALLS: usize = 256;
+
+use crate::deque_wrapper::DequeWrapper;
+use crate::events::MallocEvent;
+use crate::vector_wrapper::ClearStorage;
+use crate::{errors::MallocError, vector_wrapper::VectorWrapper, ActionCallId, Contract};
 
 impl Construction {
-    /// Convert a vector of splits and a given amount to a Vec of amount values.
-    /// All of the amounts are rounded down except the last one. So, if there is any remainder, it will be summed to the last output
-    /// Smaller than the input amount
-    pub fn get_split_amounts(amount: u128, splits: VectorWrapper<U128>) -> Vec<u128> {
-        let mut amounts = vec![];
-
-        // TODO: to u256
-        let mut split_sum: U256 = U256::from(0);
-        for i in 0..splits.0.len() {
-            split_sum += U256::from(splits.0.get(i).unwrap().0);
-        }
-
-        for i in 0..splits.0.len() {
-            let transfer_amount_u256: U256 =
-                U256::from(splits.0.get(i).unwrap().0) * U256::from(amount) / split_sum;
-            let transfer_amount = transfer_amount_u256.as_u128();
-            // let frac = (splits.0.get(i).unwrap() as f64) / (split_sum as f64);
-            // let transfer_amount_float = frac * amount as f64;
-            // let transfer_amount = transfer_amount_float.floor() as u128;
-            amounts.push(transfer_amount);
-        }
-        let unused: u128 = amount - amounts.iter().sum::<u128>();
-        assert_eq!(
-            amounts.len() as u64,
-            splits.0.len(),
-            "{}",
-            NUMB_OF_SPLITS_DOES_NOT_EQUAL_NUMB_AMOUNTS.to_owned()
-        );
-        if amounts.len() > 1 {
-            amounts[splits.0.len() as usize - 1] += unused;
+    pub fn get_action_call_timeout_blocks(&self) -> u64 {
+        self.action_call_timeout_blocks
+            .unwrap_or(DEFAULT_ACTION_CALL_TIMEOUT_BLOCKS)
+    }
+
+    /// Convert a split specification and a given amount to a Vec of amount values, see SplitSpec::get_amounts
+    pub fn get_split_amounts(amount: u128, splits: &SplitSpec) -> Result<Vec<u128>, MallocError> {
+        let amounts = splits.get_amounts(amount)?;
+        if amounts.len() as u64 != splits.len() {
+            return Err(MallocError::SplitsDoNotMatchAmounts);
         }
-        amounts
+        Ok(amounts)
+    }
+
+    /// Get the keys of the sets of next actions for the action at action_index, if its returned tokens
+    /// are matched by token id
+    pub fn get_next_actions_token_ids(&self, action_index: u64) -> NextActionsTokenIdsForAction {
+        self.next_actions_token_ids
+            .as_ref()
+            .and_then(|token_ids| token_ids.0.get(action_index))
+            .flatten()
+    }
+
+    /// Match the returned items to the sets of next actions by their keys. The amounts of a token which is
+    /// returned more than once are summed. A set takes at most one token, so the catch-all set only takes the
+    /// first token which no other set is keyed by
+    /// @returns the token which each set takes, if any, and the returned tokens which no set takes
+    pub fn match_returned_items(
+        keys: &[NextActionsKey],
+        returned_items: &[TokenAmount],
+    ) -> (Vec<Option<TokenAmount>>, Vec<TokenAmount>) {
+        let mut summed_items: Vec<TokenAmount> = vec![];
+        for item in returned_items {
+            match summed_items.iter_mut().find(|s| s.token_id == item.token_id) {
+                Some(summed) => summed.amount = U128(summed.amount.0 + item.amount.0),
+                None => summed_items.push(item.clone()),
+            }
+        }
+
+        let mut set_items: Vec<Option<TokenAmount>> = vec![None; keys.len()];
+        let mut unmatched = vec![];
+        let catch_all = keys.iter().position(|key| *key == NextActionsKey::CatchAll);
+        for item in summed_items {
+            let set_index = keys
+                .iter()
+                .position(|key| *key == NextActionsKey::TokenId(item.token_id.clone()))
+                .or(catch_all.filter(|i| set_items[*i].is_none()));
+            match set_index {
+                Some(i) => set_items[i] = Some(item),
+                None => unmatched.push(item),
+            }
+        }
+        (set_items, unmatched)
+    }
+
+    /// Check that the returned items hold at least the minimum amount of each token which the action at
+    /// action_index must return. The amounts of a token which is returned more than once are summed
+    pub fn check_min_returned_amounts(
+        &self,
+        action_index: u64,
+        returned_items: &[TokenAmount],
+    ) -> Result<(), MallocError> {
+        let min_returned_amounts = match self
+            .min_returned_amounts
+            .as_ref()
+            .and_then(|min_amounts| min_amounts.0.get(action_index))
+        {
+            None => return Ok(()),
+            Some(min_amounts) => min_amounts,
+        };
+        for min_amount in min_returned_amounts {
+            let amount: u128 = returned_items
+                .iter()
+                .filter(|item| item.token_id == min_amount.token_id)
+                .map(|item| item.amount.0)
+                .sum();
+            if amount < min_amount.amount.0 {
+                return Err(MallocError::ReturnedAmountBelowMinimum {
+                    token_id: min_amount.token_id.clone(),
+                    min_amount: min_amount.amount,
+                    amount: U128(amount),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the keys of each action which matches its returned tokens by token id line up with its
+    /// sets of next actions, and that no two of the action's sets share a key
+    pub fn validate_next_actions_token_ids(
+        &self,
+        next_actions_indices: &NextActionsIndicesForConstruction,
+    ) -> Result<(), MallocError> {
+        let next_actions_token_ids = match &self.next_actions_token_ids {
+            None => return Ok(()),
+            Some(token_ids) => token_ids,
+        };
+        if next_actions_token_ids.0.len() != next_actions_indices.0.len() {
+            return Err(MallocError::NextActionsLengthMismatch {
+                expected: next_actions_indices.0.len(),
+                actual: next_actions_token_ids.0.len(),
+            });
+        }
+        for (action_index, (keys, index_sets)) in next_actions_token_ids
+            .0
+            .iter()
+            .zip(next_actions_indices.0.iter())
+            .enumerate()
+        {
+            let keys = match keys {
+                None => continue,
+                Some(keys) => keys,
+            };
+            if keys.len() as u64 != index_sets.0.len() {
+                return Err(MallocError::TokenIdsLengthMismatch {
+                    action_index: action_index as u64,
+                    expected: index_sets.0.len(),
+                    actual: keys.len() as u64,
+                });
+            }
+            if keys.iter().enumerate().any(|(i, key)| keys[..i].contains(key)) {
+                return Err(MallocError::DuplicateNextActionsKey {
+                    action_index: action_index as u64,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Construction {
+    /// Check that the routing topology is well formed. That is, all the indices are in bounds,
+    /// the number of indices and splits match, the splits do not sum to zero, and the topology has no cycles
+    pub fn validate_topology(
+        numb_actions: u64,
+        initial_action_indices: &[u64],
+        initial_splits: &SplitSpec,
+        next_actions_indices: &NextActionsIndicesForConstruction,
+        next_actions_splits: &NextActionsSplitsForConstruction,
+    ) -> Result<(), MallocError> {
+        if initial_action_indices.is_empty() {
+            return Err(MallocError::NoInitialActions);
+        }
+        if initial_action_indices.len() as u64 != initial_splits.len() {
+            return Err(MallocError::SplitsLengthMismatch {
+                expected: initial_action_indices.len() as u64,
+                actual: initial_splits.len(),
+            });
+        }
+        Self::validate_indices_in_bounds(initial_action_indices.iter().copied(), numb_actions)?;
+        initial_splits.validate()?;
+
+        for actual in [next_actions_indices.0.len(), next_actions_splits.0.len()] {
+            if actual != numb_actions {
+                return Err(MallocError::NextActionsLengthMismatch {
+                    expected: numb_actions,
+                    actual,
+                });
+            }
+        }
+
+        let mut children: Vec<Vec<u64>> = Vec::with_capacity(numb_actions as usize);
+        for action_index in 0..numb_actions {
+            let index_sets = next_actions_indices.0.get(action_index).unwrap();
+            let split_sets = next_actions_splits.0.get(action_index).unwrap();
+            if index_sets.0.len() != split_sets.0.len() {
+                return Err(MallocError::SplitsLengthMismatch {
+                    expected: index_sets.0.len(),
+                    actual: split_sets.0.len(),
+                });
+            }
+
+            let mut action_children = vec![];
+            for set_index in 0..index_sets.0.len() {
+                let indices = index_sets.0.get(set_index).unwrap();
+                let splits = split_sets.0.get(set_index).unwrap();
+                if indices.0.len() != splits.len() {
+                    return Err(MallocError::SplitsLengthMismatch {
+                        expected: indices.0.len(),
+                        actual: splits.len(),
+                    });
+                }
+                // An empty set of next actions leaves the returned tokens with the caller
+                if indices.0.is_empty() {
+                    continue;
+                }
+                splits.validate()?;
+                Self::validate_indices_in_bounds(indices.0.iter(), numb_actions)?;
+                action_children.extend(indices.0.iter());
+            }
+            children.push(action_children);
+        }
+
+        Self::validate_acyclic(&children)
+    }
+
+    /// Get the number of incoming edges of each action, counting each initial action index as an edge
+    pub fn get_number_of_inputs(
+        numb_actions: u64,
+        initial_action_indices: &[u64],
+        next_actions_indices: &[Vec<Vec<u64>>],
+    ) -> Vec<u64> {
+        let mut number_of_inputs = vec![0; numb_actions as usize];
+        for action_index in initial_action_indices.iter() {
+            number_of_inputs[*action_index as usize] += 1;
+        }
+        for next_index in next_actions_indices.iter().flatten().flatten() {
+            number_of_inputs[*next_index as usize] += 1;
+        }
+        number_of_inputs
+    }
+
+    /// Read the next actions indices out of storage, so that they can be worked with in memory
+    pub fn next_actions_indices_to_vec(
+        next_actions_indices: &NextActionsIndicesForConstruction,
+    ) -> Vec<Vec<Vec<u64>>> {
+        next_actions_indices
+            .0
+            .iter()
+            .map(|sets| sets.0.iter().map(|set| set.0.to_vec()).collect())
+            .collect()
+    }
+
+    fn validate_indices_in_bounds(
+        indices: impl Iterator<Item = u64>,
+        numb_actions: u64,
+    ) -> Result<(), MallocError> {
+        for index in indices {
+            if index >= numb_actions {
+                return Err(MallocError::ActionIndexOutOfBounds {
+                    index,
+                    number_of_actions: numb_actions,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the graph with the given children for each node has no cycles using an iterative depth first search
+    fn validate_acyclic(children: &[Vec<u64>]) -> Result<(), MallocError> {
+        const UNVISITED: u8 = 0;
+        const IN_PROGRESS: u8 = 1;
+        const DONE: u8 = 2;
+        let mut state = vec![UNVISITED; children.len()];
+
+        for root in 0..children.len() {
+            if state[root] != UNVISITED {
+                continue;
+            }
+            // The stack holds the node and the index of the next child to visit
+            let mut stack: Vec<(usize, usize)> = vec![(root, 0)];
+            state[root] = IN_PROGRESS;
+            while let Some((node, child_index)) = stack.pop() {
+                if child_index == children[node].len() {
+                    state[node] = DONE;
+                    continue;
+                }
+                stack.push((node, child_index + 1));
+                let child = children[node][child_index] as usize;
+                match state[child] {
+                    IN_PROGRESS => return Err(MallocError::ConstructionHasCycle),
+                    UNVISITED => {
+                        state[child] = IN_PROGRESS;
+                        stack.push((child, 0));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
     }
 }
 
 impl ConstructionCall {
-    /// Creates a new construction call and also places all elements from action_call_ids into the new stack
+    /// Creates a new construction call and also places all elements from action_call_ids into the new stack.
+    /// Any of the initial actions, initial splits or the next actions topology which are not supplied are taken from the construction
     pub fn new(
         contract: &mut Contract,
         caller: AccountId,
         construction_id: ConstructionId,
         construction_call_id: &ConstructionCallId,
         amount: u128,
-        initial_action_indices: Vec<u64>,
-        initial_splits: VectorWrapper<U128>,
-        next_actions_indices: NextActionsIndicesForConstruction,
-        next_actions_splits: NextActionsSplitsForConstruction,
-    ) -> Result<ConstructionCall, PanicError> {
-        // Ensure the construction call id is not already registered
-        assert!(
-            contract
-                .construction_calls
-                .get(&construction_call_id)
-                .is_none(),
-            "{}",
-            panic_errors::CONSTRUCTION_CALL_ID_ALREADY_USED
-        );
-
-        // Ensure the construction actually exists
-        let _construction = contract.get_construction(&construction_id)?;
+        initial_action_indices: Option<Vec<u64>>,
+        initial_splits: Option<SplitSpec>,
+        next_actions_indices: Option<NextActionsIndicesForConstruction>,
+        next_actions_splits: Option<NextActionsSplitsForConstruction>,
+    ) -> Result<ConstructionCall, MallocError> {
+        // Ensure the construction call id is not already registered, nor used by a finished construction call
+        if contract.construction_calls.get(construction_call_id).is_some()
+            || contract.construction_call_summaries.get(construction_call_id).is_some()
+        {
+            return Err(MallocError::ConstructionCallIdAlreadyUsed {
+                construction_call_id: construction_call_id.clone(),
+            });
+        }
+
+        // Ensure the construction actually exists and pin the call to its version
+        let construction_id = contract.resolve_construction_id(&construction_id)?;
+        let construction = contract.get_construction(&construction_id)?;
+        let initial_action_indices =
+            initial_action_indices.unwrap_or_else(|| construction.initial_action_indices.0.to_vec());
+        let initial_splits = initial_splits
+            .as_ref()
+            .unwrap_or(&construction.initial_splits);
+        Construction::validate_topology(
+            construction.actions.0.len(),
+            &initial_action_indices,
+            initial_splits,
+            next_actions_indices
+                .as_ref()
+                .unwrap_or(&construction.next_actions_indices),
+            next_actions_splits
+                .as_ref()
+                .unwrap_or(&construction.next_actions_splits),
+        )?;
+        contract.validate_action_next_actions(
+            &construction,
+            next_actions_indices
+                .as_ref()
+                .unwrap_or(&construction.next_actions_indices),
+        )?;
+        construction.validate_next_actions_token_ids(
+            next_actions_indices
+                .as_ref()
+                .unwrap_or(&construction.next_actions_indices),
+        )?;
+
+        let numb_actions = construction.actions.0.len();
+        let number_of_inputs = Construction::get_number_of_inputs(
+            numb_actions,
+            &initial_action_indices,
+            &Construction::next_actions_indices_to_vec(
+                next_actions_indices
+                    .as_ref()
+                    .unwrap_or(&construction.next_actions_indices),
+            ),
+        );
 
         // Create the vectors necessary for the construction call
         let vect_prefix_str_action_stack = format!("constcall-stack-{}", construction_call_id);
-        let vect_prefix_action_call_stack = vect_prefix_str_action_stack.as_bytes();
+        let action_call_ids_prefix = format!("{}-actions", construction_call_id);
+        let number_of_inputs_prefix = format!("{}-inputs", construction_call_id);
+        let join_action_calls_prefix = format!("{}-joins", construction_call_id);
 
-        let initial_amounts = Construction::get_split_amounts(amount, initial_splits);
-        let init_action_calls = ActionCall::action_calls_from_construction_indices(
-            contract,
-            initial_action_indices,
-            initial_amounts,
-        )
-        .unwrap_or_else(|e| panic!("{}", e));
+        let mut construction_call = ConstructionCall {
+            caller,
+            construction_id,
+            action_calls: VectorWrapper::new(action_call_ids_prefix.as_bytes()),
+            next_action_calls: DequeWrapper::new(vect_prefix_str_action_stack.as_bytes()),
+            scheduling_mode: construction.scheduling.unwrap_or(SchedulingMode::Dfs),
+            is_self_driving: false,
+            next_actions_indices_override: next_actions_indices,
+            next_actions_splits_override: next_actions_splits,
+            action_number_of_inputs: VectorWrapper::from_vec(
+                number_of_inputs,
+                number_of_inputs_prefix.as_bytes(),
+            ),
+            join_action_calls: VectorWrapper::from_vec(
+                vec![None; numb_actions as usize],
+                join_action_calls_prefix.as_bytes(),
+            ),
+            is_aborted: false,
+        };
 
-        let action_call_ids_prefix = format!("{}-actions", construction_call_id);
-        let action_call_ids =
-            VectorWrapper::from_vec(init_action_calls, action_call_ids_prefix.as_bytes());
+        // Pass the initial amounts to the initial actions, which schedules them
+        let initial_amounts = Construction::get_split_amounts(amount, initial_splits)?;
+        for (action_index, initial_amount) in initial_action_indices.into_iter().zip(initial_amounts) {
+            let action_id = construction.actions.0.get(action_index).unwrap();
+            let token_id = contract
+                .actions
+                .get(&action_id)
+                .ok_or(MallocError::ActionNotFound { action_id })?
+                .get_token_id();
+            construction_call.add_action_input(
+                contract,
+                &construction,
+                action_index,
+                &token_id,
+                initial_amount,
+            );
+        }
+
+        Ok(construction_call)
+    }
+
+    /// Whether the action at action_index has more than one incoming edge
+    fn is_join(&self, action_index: u64) -> bool {
+        self.action_number_of_inputs.0.get(action_index).unwrap_or(0) > 1
+    }
 
-        // Create the call stack and push the initial calls onto it
-        let mut action_call_stack = VectorWrapper(Vector::new(vect_prefix_action_call_stack));
-        for i in 0..action_call_ids.0.len() {
-            action_call_stack.0.push(&i);
+    /// Pass an amount of a token on to the action at action_index. An action with a single incoming edge
+    /// gets a new action call which is scheduled right away. A join collects the inputs from all of its incoming edges
+    /// into one action call, which is scheduled once all of them resolve
+    pub(crate) fn add_action_input(
+        &mut self,
+        contract: &mut Contract,
+        construction: &Construction,
+        action_index: u64,
+        token_id: &AccountId,
+        amount: u128,
+    ) {
+        if self.is_aborted {
+            return;
+        }
+        if !self.is_join(action_index) {
+            let (action_call, action_call_id) = ActionCall::new(contract, amount, action_index);
+            let action_call_index = self.action_calls.0.len();
+            self.action_calls.0.push(&action_call_id);
+            self.schedule_action_call(
+                contract,
+                construction,
+                action_call_index,
+                action_call_id,
+                action_call,
+                token_id,
+            );
+            return;
         }
+        let (action_call_index, action_call_id, mut join) =
+            self.get_or_create_join(contract, action_index);
+        join.add_join_input(token_id, amount);
+        self.resolve_join_if_ready(contract, construction, action_call_index, action_call_id, join);
+    }
 
-        Ok(ConstructionCall {
-            caller,
-            construction_id,
-            action_calls: action_call_ids,
-            next_action_calls_stack: action_call_stack,
-            next_actions_indices_in_construction: next_actions_indices,
-            next_actions_splits,
-        })
+    /// Notify the joins downstream of an action which will not pass on any tokens, either because it failed
+    /// or because it has no callback, that the inputs from it will never arrive
+    pub(crate) fn drop_downstream_inputs(
+        &mut self,
+        contract: &mut Contract,
+        construction: &Construction,
+        action_index: u64,
+    ) {
+        if self.is_aborted {
+            return;
+        }
+        let next_actions_indices = match self.get_next_actions_indices(construction, action_index) {
+            None => return,
+            Some(indices) => indices,
+        };
+        for indices in next_actions_indices.0.iter() {
+            self.drop_inputs(contract, construction, indices);
+        }
+    }
+
+    /// Notify the actions at the given indices that the input from one of their incoming edges will never arrive
+    pub(crate) fn drop_inputs(
+        &mut self,
+        contract: &mut Contract,
+        construction: &Construction,
+        indices: VectorWrapper<u64>,
+    ) {
+        for next_index in indices.0.iter() {
+            if !self.is_join(next_index) {
+                // The action is never called, so neither are its next actions
+                self.drop_downstream_inputs(contract, construction, next_index);
+                continue;
+            }
+            let (action_call_index, action_call_id, mut join) =
+                self.get_or_create_join(contract, next_index);
+            join.add_failed_join_input();
+            self.resolve_join_if_ready(contract, construction, action_call_index, action_call_id, join);
+        }
+    }
+
+    /// Schedule the action call to be run. A route is not run as an action call, it is resolved right away
+    fn schedule_action_call(
+        &mut self,
+        contract: &mut Contract,
+        construction: &Construction,
+        action_call_index: u64,
+        action_call_id: ActionCallId,
+        action_call: ActionCall,
+        token_id: &AccountId,
+    ) {
+        let action_id = construction
+            .actions
+            .0
+            .get(action_call.action_index_in_construction)
+            .unwrap();
+        match contract.actions.get(&action_id) {
+            Some(Action::Route(route)) => {
+                self.run_route(contract, construction, action_call_id, action_call, &route, token_id)
+            }
+            _ => {
+                contract.action_calls.insert(&action_call_id, &action_call);
+                self.next_action_calls.push_back(&action_call_index);
+            }
+        }
+    }
+
+    /// Pass the route's input on to the branch which its predicates pick. The other branches never receive
+    /// any tokens, so the joins downstream of them stop waiting on them.
+    /// If the route received another token than the one it takes, the route errors and nothing is passed on
+    /// @param received_token_id - the token of the route's input
+    fn run_route(
+        &mut self,
+        contract: &mut Contract,
+        construction: &Construction,
+        action_call_id: ActionCallId,
+        mut action_call: ActionCall,
+        route: &Route,
+        received_token_id: &AccountId,
+    ) {
+        let action_index = action_call.action_index_in_construction;
+        let branch = route.pick_branch(action_call.amount);
+        let token_id = route.get_token_id();
+        if &token_id != received_token_id {
+            action_call.set_error(MallocError::RouteTokenIdDoesNotMatch {
+                action_index,
+                expected: token_id,
+                actual: received_token_id.clone(),
+            });
+            contract.action_calls.insert(&action_call_id, &action_call);
+            self.drop_downstream_inputs(contract, construction, action_index);
+            return;
+        }
+        let index_sets = self
+            .get_next_actions_indices(construction, action_index)
+            .unwrap();
+        let splits = self
+            .get_next_actions_splits(construction, action_index)
+            .and_then(|split_sets| split_sets.0.get(branch))
+            .unwrap();
+        let amounts = match Construction::get_split_amounts(action_call.amount, &splits) {
+            Ok(amounts) => amounts,
+            Err(error) => {
+                action_call.set_error(error);
+                contract.action_calls.insert(&action_call_id, &action_call);
+                self.drop_downstream_inputs(contract, construction, action_index);
+                return;
+            }
+        };
+        log!("The route with index {} took the branch {}", action_index, branch);
+        action_call.route_branch = Some(branch);
+        action_call.returned_items = vec![TokenAmount {
+            token_id: token_id.clone(),
+            amount: U128(action_call.amount),
+        }];
+        action_call.set_success();
+        contract.action_calls.insert(&action_call_id, &action_call);
+        for (set_index, indices) in index_sets.0.iter().enumerate() {
+            if set_index as u64 == branch {
+                for (next_index, amount) in indices.0.iter().zip(amounts.iter()) {
+                    self.add_action_input(contract, construction, next_index, &token_id, *amount);
+                }
+            } else {
+                self.drop_inputs(contract, construction, indices);
+            }
+        }
+    }
+
+    /// Get the action call of the join at action_index, creating it if it did not receive any inputs yet
+    /// @returns the index into action_calls, the id and the action call of the join
+    fn get_or_create_join(
+        &mut self,
+        contract: &mut Contract,
+        action_index: u64,
+    ) -> (u64, ActionCallId, ActionCall) {
+        if let Some(action_call_index) = self.join_action_calls.0.get(action_index).flatten() {
+            let action_call_id = self.action_calls.0.get(action_call_index).unwrap();
+            let join = contract
+                .action_calls
+                .get(&action_call_id)
+                .unwrap_or_else(|| panic!("{}", MallocError::ActionCallNotFound { action_call_id }));
+            return (action_call_index, action_call_id, join);
+        }
+        let expected_number_inputs = self.action_number_of_inputs.0.get(action_index).unwrap();
+        let (join, action_call_id) = ActionCall::new_join(contract, expected_number_inputs, action_index);
+        let action_call_index = self.action_calls.0.len();
+        self.action_calls.0.push(&action_call_id);
+        self.join_action_calls
+            .0
+            .replace(action_index, &Some(action_call_index));
+        (action_call_index, action_call_id, join)
+    }
+
+    /// Store the join and, once all of its incoming edges resolved, either schedule it or, if none
+    /// of them passed on tokens, error it and drop the inputs downstream of it
+    fn resolve_join_if_ready(
+        &mut self,
+        contract: &mut Contract,
+        construction: &Construction,
+        action_call_index: u64,
+        action_call_id: ActionCallId,
+        mut join: ActionCall,
+    ) {
+        if !join.has_all_inputs() {
+            contract.action_calls.insert(&action_call_id, &join);
+            return;
+        }
+        let action_index = join.action_index_in_construction;
+        let action_id = construction.actions.0.get(action_index).unwrap();
+        let token_id = contract
+            .actions
+            .get(&action_id)
+            .unwrap_or_else(|| panic!("{}", MallocError::ActionNotFound { action_id }))
+            .get_token_id();
+        let should_run = join.resolve_join(&token_id);
+        contract.action_calls.insert(&action_call_id, &join);
+        if should_run {
+            self.schedule_action_call(
+                contract,
+                construction,
+                action_call_index,
+                action_call_id,
+                join,
+                &token_id,
+            );
+        } else {
+            self.drop_downstream_inputs(contract, construction, action_index);
+        }
+    }
+
+    /// Get the position in next_action_calls of the action call which should be run next
+    fn next_action_call_position(&self, contract: &Contract) -> Option<u64> {
+        if self.next_action_calls.is_empty() {
+            return None;
+        }
+        match self.scheduling_mode {
+            SchedulingMode::Dfs => Some(self.next_action_calls.len() - 1),
+            SchedulingMode::Bfs => Some(0),
+            SchedulingMode::Priority => self
+                .next_action_calls
+                .iter()
+                .enumerate()
+                .min_by_key(|(position, action_call_index)| {
+                    let action_call_id = self.action_calls.0.get(*action_call_index).unwrap();
+                    let action_call = contract.action_calls.get(&action_call_id).unwrap();
+                    (action_call.action_index_in_construction, *position)
+                })
+                .map(|(position, _)| position as u64),
+        }
+    }
+
+    /// Get the index into action_calls of the action call which should be run next
+    pub fn peek_next_action_call(&self, contract: &Contract) -> Option<u64> {
+        self.next_action_call_position(contract)
+            .and_then(|position| self.next_action_calls.get(position))
+    }
+
+    /// Take the action call which should be run next off of next_action_calls.
+    /// Depth and breadth first scheduling take from the ends of the deque in constant time
+    /// @returns the index into action_calls of the action call
+    pub fn pop_next_action_call(&mut self, contract: &Contract) -> Option<u64> {
+        match self.scheduling_mode {
+            SchedulingMode::Dfs => self.next_action_calls.pop_back(),
+            SchedulingMode::Bfs => self.next_action_calls.pop_front(),
+            SchedulingMode::Priority => self
+                .next_action_call_position(contract)
+                .and_then(|position| self.next_action_calls.remove(position)),
+        }
+    }
+
+    /// Get the sets of next actions for the action at action_index, preferring the call's override to the construction's topology
+    pub fn get_next_actions_indices(
+        &self,
+        construction: &Construction,
+        action_index: u64,
+    ) -> Option<NextActionsIndicesForAction> {
+        self.next_actions_indices_override
+            .as_ref()
+            .unwrap_or(&construction.next_actions_indices)
+            .0
+            .get(action_index)
+    }
+
+    /// Get the splits for the sets of next actions for the action at action_index,
+    /// preferring the call's override to the construction's topology
+    pub fn get_next_actions_splits(
+        &self,
+        construction: &Construction,
+        action_index: u64,
+    ) -> Option<NextActionsSplitsForAction> {
+        self.next_actions_splits_override
+            .as_ref()
+            .unwrap_or(&construction.next_actions_splits)
+            .0
+            .get(action_index)
     }
 }
 
 impl Contract {
-    pub fn get_construction(&self, id: &ConstructionId) -> Result<Construction, PanicError> {
+    /// Get the construction. If the id has no version, the latest version of the construction is returned
+    pub fn get_construction(&self, id: &ConstructionId) -> Result<Construction, MallocError> {
         self.constructions
-            .get(&id)
-            .ok_or(panic_errors::CONSTRUCTION_NOT_FOUND.to_string())
+            .get(&self.resolve_construction_id(id)?)
+            .ok_or(MallocError::ConstructionNotFound {
+                construction_id: id.clone(),
+            })
+    }
+
+    /// Get the id of the exact construction version which the id refers to
+    pub(crate) fn resolve_construction_id(
+        &self,
+        id: &ConstructionId,
+    ) -> Result<ConstructionId, MallocError> {
+        id.resolve_version(&self.construction_versions)
+            .ok_or(MallocError::ConstructionNotFound {
+                construction_id: id.clone(),
+            })
+    }
+
+    /// Replace the action ids in the construction which have no version with the id of the action's latest version
+    pub(crate) fn pin_construction_actions(
+        &self,
+        construction: &mut Construction,
+    ) -> Result<(), MallocError> {
+        for i in 0..construction.actions.0.len() {
+            let action_id = construction.actions.0.get(i).unwrap();
+            if action_id.version.is_none() {
+                construction
+                    .actions
+                    .0
+                    .replace(i, &self.resolve_action_id(&action_id)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every action referenced by the construction exists and that its topology is well formed
+    pub(crate) fn validate_construction(&self, construction: &Construction) -> Result<(), MallocError> {
+        for action_id in construction.actions.0.iter() {
+            self.get_action(&action_id)?;
+        }
+        Construction::validate_topology(
+            construction.actions.0.len(),
+            &construction.initial_action_indices.0.to_vec(),
+            &construction.initial_splits,
+            &construction.next_actions_indices,
+            &construction.next_actions_splits,
+        )?;
+        construction.validate_next_actions_token_ids(&construction.next_actions_indices)?;
+        if let Some(min_returned_amounts) = &construction.min_returned_amounts {
+            if min_returned_amounts.0.len() != construction.actions.0.len() {
+                return Err(MallocError::NextActionsLengthMismatch {
+                    expected: construction.actions.0.len(),
+                    actual: min_returned_amounts.0.len(),
+                });
+            }
+        }
+        self.validate_action_next_actions(construction, &construction.next_actions_indices)
+    }
+
+    /// Check that the leaf actions of the construction, such as transfers to external accounts, have no next actions
+    /// and that each route has a set of next actions per branch
+    pub(crate) fn validate_action_next_actions(
+        &self,
+        construction: &Construction,
+        next_actions_indices: &NextActionsIndicesForConstruction,
+    ) -> Result<(), MallocError> {
+        for (action_index, (action_id, index_sets)) in construction
+            .actions
+            .0
+            .iter()
+            .zip(next_actions_indices.0.iter())
+            .enumerate()
+        {
+            let action = self.get_action(&action_id)?;
+            if action.is_leaf() && index_sets.0.iter().any(|indices| !indices.0.is_empty()) {
+                return Err(MallocError::LeafActionHasNextActions {
+                    action_index: action_index as u64,
+                });
+            }
+            if let Action::Route(route) = action {
+                if index_sets.0.len() != route.get_number_of_branches() {
+                    return Err(MallocError::RouteBranchesMismatch {
+                        action_index: action_index as u64,
+                        expected: route.get_number_of_branches(),
+                        actual: index_sets.0.len(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Estimate the gas needed to run the construction by walking its DAG from the initial action indices.
+    /// Every action call after the initial ones is assumed to receive a non-zero amount, so the estimate is an upper bound
+    pub(crate) fn estimate_construction_gas(
+        &self,
+        construction_id: &ConstructionId,
+        amount: u128,
+        initial_action_indices: Option<Vec<u64>>,
+        initial_splits: Option<SplitSpec>,
+        next_actions_indices: Option<Vec<Vec<Vec<u64>>>>,
+    ) -> Result<ConstructionGasEstimate, MallocError> {
+        let construction = self.get_construction(construction_id)?;
+        let initial_action_indices =
+            initial_action_indices.unwrap_or_else(|| construction.initial_action_indices.0.to_vec());
+        let next_actions_indices = next_actions_indices.unwrap_or_else(|| {
+            Construction::next_actions_indices_to_vec(&construction.next_actions_indices)
+        });
+        let initial_splits = initial_splits.unwrap_or(construction.initial_splits);
+        if initial_action_indices.len() as u64 != initial_splits.len() {
+            return Err(MallocError::SplitsLengthMismatch {
+                expected: initial_action_indices.len() as u64,
+                actual: initial_splits.len(),
+            });
+        }
+        let numb_actions = construction.actions.0.len();
+        if next_actions_indices.len() as u64 != numb_actions {
+            return Err(MallocError::NextActionsLengthMismatch {
+                expected: numb_actions,
+                actual: next_actions_indices.len() as u64,
+            });
+        }
+        let children: Vec<Vec<u64>> = next_actions_indices
+            .iter()
+            .map(|sets| sets.iter().flatten().copied().collect())
+            .collect();
+        Construction::validate_indices_in_bounds(children.iter().flatten().copied(), numb_actions)?;
+        Construction::validate_indices_in_bounds(
+            initial_action_indices.iter().copied(),
+            numb_actions,
+        )?;
+        Construction::validate_acyclic(&children)?;
+        let initial_amounts = Construction::get_split_amounts(amount, &initial_splits)?;
+
+        // A join is only called once, after all of its incoming edges are reached
+        let number_of_inputs = Construction::get_number_of_inputs(
+            numb_actions,
+            &initial_action_indices,
+            &next_actions_indices,
+        );
+        let mut join_inputs: Vec<(u64, u128)> = vec![(0, 0); numb_actions as usize];
+        let mut to_visit: VecDeque<(u64, u128)> = VecDeque::new();
+        let mut reach_action = |to_visit: &mut VecDeque<(u64, u128)>, action_index: u64, amount: u128| {
+            let i = action_index as usize;
+            if number_of_inputs[i] <= 1 {
+                to_visit.push_back((action_index, amount));
+                return;
+            }
+            join_inputs[i] = (join_inputs[i].0 + 1, join_inputs[i].1 + amount);
+            if join_inputs[i].0 == number_of_inputs[i] {
+                to_visit.push_back((action_index, join_inputs[i].1));
+            }
+        };
+        for (action_index, amount) in initial_action_indices.into_iter().zip(initial_amounts) {
+            reach_action(&mut to_visit, action_index, amount);
+        }
+        let mut action_calls = vec![];
+        let mut total: Gas = CROSS_CONTRACT_BASE_GAS + SELF_DRIVE_RESERVE_GAS;
+
+        // Cache the actions as the same action can be reached many times
+        let mut actions: HashMap<u64, (ActionId, Action)> = HashMap::new();
+
+        while let Some((action_index, amount)) = to_visit.pop_front() {
+            if action_calls.len() >= MAX_ESTIMATED_ACTION_CALLS {
+                return Err(MallocError::TooManyActionCallsToEstimate {
+                    max_action_calls: MAX_ESTIMATED_ACTION_CALLS as u64,
+                });
+            }
+            let (action_id, action) = match actions.entry(action_index) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let action_id = construction.actions.0.get(action_index).ok_or(
+                        MallocError::ActionIndexOutOfBounds {
+                            index: action_index,
+                            number_of_actions: numb_actions,
+                        },
+                    )?;
+                    let action = self
+                        .actions
+                        .get(&action_id)
+                        .ok_or_else(|| MallocError::ActionNotFound {
+                            action_id: action_id.clone(),
+                        })?;
+                    entry.insert((action_id, action))
+                }
+            };
+
+            let gas = action.get_step_gas(&ActionCall::new_unregistered(amount, action_index))?;
+            total += gas;
+            action_calls.push(ActionCallGasEstimate {
+                action_index_in_construction: action_index,
+                action_id: action_id.clone(),
+                gas: U64(gas),
+            });
+            for next_index in children[action_index as usize].iter() {
+                reach_action(&mut to_visit, *next_index, 1);
+            }
+        }
+
+        Ok(ConstructionGasEstimate {
+            total: U64(total),
+            action_calls,
+        })
+    }
+
+    /// Get the construction's metadata. The id must have a version
+    pub(crate) fn get_construction_info(&self, id: &ConstructionId) -> Option<ConstructionInfo> {
+        let construction = self.constructions.get(id)?;
+        let mut token_ids: Vec<AccountId> = vec![];
+        for action_id in construction.actions.0.iter() {
+            if let Some(action) = self.actions.get(&action_id) {
+                let token_id = action.get_token_id();
+                if !token_ids.contains(&token_id) {
+                    token_ids.push(token_id);
+                }
+            }
+        }
+        Some(ConstructionInfo {
+            id: id.clone(),
+            number_of_actions: construction.actions.0.len(),
+            token_ids,
+        })
+    }
+
+    /// Get the status of the construction call and the progress of each of its action calls
+    pub(crate) fn get_construction_call_status_internal(
+        &self,
+        id: &ConstructionCallId,
+    ) -> Option<ConstructionCallStatus> {
+        let construction_call = self.construction_calls.get(id)?;
+        let construction = self.get_construction(&construction_call.construction_id).ok()?;
+        let mut status = ConstructionCallStatus {
+            caller: construction_call.caller.clone(),
+            construction_id: construction_call.construction_id.clone(),
+            action_calls: Vec::with_capacity(construction_call.action_calls.0.len() as usize),
+            number_scheduled: 0,
+            number_waiting_inputs: 0,
+            number_executing: 0,
+            number_succeeded: 0,
+            number_errored: 0,
+            number_reverted: 0,
+        };
+        for action_call_id in construction_call.action_calls.0.iter() {
+            let action_call = self.action_calls.get(&action_call_id)?;
+            match action_call.get_status() {
+                ActionCallStatus::WaitingCall => status.number_scheduled += 1,
+                ActionCallStatus::WaitingInputs => status.number_waiting_inputs += 1,
+                ActionCallStatus::Executing { .. } => status.number_executing += 1,
+                ActionCallStatus::Success => status.number_succeeded += 1,
+                ActionCallStatus::Error { .. } => status.number_errored += 1,
+                ActionCallStatus::Reverted => status.number_reverted += 1,
+            }
+            status.action_calls.push(ActionCallProgress {
+                action_call_id: U64(action_call_id),
+                action_index_in_construction: action_call.action_index_in_construction,
+                action_id: construction
+                    .actions
+                    .0
+                    .get(action_call.action_index_in_construction)?,
+                amount: U128(action_call.amount),
+                status: action_call.get_status().clone(),
+                returned_items: action_call.returned_items.clone(),
+                route_branch: action_call.route_branch,
+                block_index_start: action_call.block_index_start.map(U64),
+                block_index_end: action_call.block_index_end.map(U64),
+            });
+        }
+        Some(status)
+    }
+
+    /// Abort the construction call. The action calls which are scheduled or waiting on inputs are marked as errors,
+    /// and the action calls which already succeeded are reverted in the reverse order of their completion.
+    /// Action calls which are still executing are left to finish, but their returned tokens are no longer passed on
+    /// and they are not reverted
+    /// @returns the last promise of the chain of reverts, if any action call was reverted
+    pub(crate) fn abort_construction_call_internal(
+        &mut self,
+        id: &ConstructionCallId,
+        caller: &AccountId,
+    ) -> Result<Option<u64>, MallocError> {
+        let mut construction_call =
+            self.construction_calls
+                .get(id)
+                .ok_or(MallocError::ConstructionCallNotFound {
+                    construction_call_id: id.clone(),
+                })?;
+        if &construction_call.caller != caller {
+            return Err(MallocError::CallerDoesNotOwnConstructionCall {
+                caller: caller.clone(),
+                owner: construction_call.caller,
+            });
+        }
+        if construction_call.is_aborted {
+            return Err(MallocError::ConstructionCallAlreadyAborted);
+        }
+        let construction = self.get_construction(&construction_call.construction_id)?;
+        construction_call.is_aborted = true;
+        construction_call.next_action_calls.clear();
+
+        let mut succeeded = vec![];
+        for action_call_id in construction_call.action_calls.0.iter() {
+            let mut action_call = match self.action_calls.get(&action_call_id) {
+                None => continue,
+                Some(action_call) => action_call,
+            };
+            match action_call.get_status() {
+                ActionCallStatus::WaitingCall | ActionCallStatus::WaitingInputs => {
+                    action_call.set_error(MallocError::ConstructionCallAborted);
+                    self.action_calls.insert(&action_call_id, &action_call);
+                    MallocEvent::emit_action_call_finished(id, action_call_id, &action_call);
+                }
+                ActionCallStatus::Success => succeeded.push((action_call_id, action_call)),
+                _ => (),
+            }
+        }
+
+        // Revert the most recently completed action calls first
+        succeeded.sort_by_key(|(action_call_id, action_call)| {
+            (action_call.block_index_end, *action_call_id)
+        });
+        let mut prior_promise = None;
+        for (action_call_id, mut action_call) in succeeded.into_iter().rev() {
+            let action_id = construction
+                .actions
+                .0
+                .get(action_call.action_index_in_construction)
+                .ok_or(MallocError::ActionIndexOutOfBounds {
+                    index: action_call.action_index_in_construction,
+                    number_of_actions: construction.actions.0.len(),
+                })?;
+            let action = self.get_action(&action_id)?;
+            // Action calls which cannot be reverted keep their success status
+            if let Some(promise) = action.handle_revert(&action_call, caller, prior_promise) {
+                prior_promise = Some(promise);
+                action_call.set_reverted();
+                self.action_calls.insert(&action_call_id, &action_call);
+            }
+        }
+
+        self.construction_calls.insert(id, &construction_call);
+        log!("Aborted construction call {}", id);
+        self.try_collect_construction_call(id);
+        Ok(prior_promise)
+    }
+
+    /// Record the construction call as active for its caller
+    pub(crate) fn add_caller_construction_call(
+        &mut self,
+        caller: &AccountId,
+        id: &ConstructionCallId,
+    ) {
+        insert_into_account_set(
+            &mut self.caller_construction_calls,
+            caller,
+            "caller-construction-calls",
+            id,
+        );
+    }
+
+    /// Remove the construction call from its caller's active construction calls
+    pub(crate) fn remove_caller_construction_call(
+        &mut self,
+        caller: &AccountId,
+        id: &ConstructionCallId,
+    ) {
+        remove_from_account_set(&mut self.caller_construction_calls, caller, id);
+    }
+
+    /// Get the number of construction calls which still reference the construction
+    pub(crate) fn get_live_construction_calls(&self, id: &ConstructionId) -> u64 {
+        self.live_construction_calls.get(id).unwrap_or(0)
+    }
+
+    pub(crate) fn incr_live_construction_calls(&mut self, id: &ConstructionId) {
+        let count = self.get_live_construction_calls(id);
+        self.live_construction_calls.insert(id, &(count + 1));
+    }
+
+    pub(crate) fn decr_live_construction_calls(&mut self, id: &ConstructionId) {
+        let count = self.get_live_construction_calls(id);
+        if count <= 1 {
+            self.live_construction_calls.remove(id);
+        } else {
+            self.live_construction_calls.insert(id, &(count - 1));
+        }
+    }
+
+    /// Get the number of registered constructions which use the action
+    pub(crate) fn get_action_references(&self, id: &ActionId) -> u64 {
+        self.action_references.get(id).unwrap_or(0)
+    }
+
+    /// Count a reference to each of the construction's actions. An action which the construction uses
+    /// more than once is only counted once
+    pub(crate) fn incr_action_references(&mut self, construction: &Construction) {
+        for action_id in Self::get_distinct_action_ids(construction) {
+            let count = self.get_action_references(&action_id);
+            self.action_references.insert(&action_id, &(count + 1));
+        }
+    }
+
+    /// Release the construction's references to its actions
+    /// @returns the ids of the actions which no construction references anymore
+    pub(crate) fn decr_action_references(&mut self, construction: &Construction) -> Vec<ActionId> {
+        let mut unreferenced = vec![];
+        for action_id in Self::get_distinct_action_ids(construction) {
+            let count = self.get_action_references(&action_id);
+            if count <= 1 {
+                self.action_references.remove(&action_id);
+                unreferenced.push(action_id);
+            } else {
+                self.action_references.insert(&action_id, &(count - 1));
+            }
+        }
+        unreferenced
+    }
+
+    fn get_distinct_action_ids(construction: &Construction) -> Vec<ActionId> {
+        let mut action_ids: Vec<ActionId> = vec![];
+        for action_id in construction.actions.0.iter() {
+            if !action_ids.contains(&action_id) {
+                action_ids.push(action_id);
+            }
+        }
+        action_ids
+    }
+
+    /// Garbage collect the construction call if it is done. A construction call is done once its stack is
+    /// empty and none of its action calls are still waiting or executing.
+    /// The bulky state is deleted and replaced by a ConstructionCallSummary
+    /// @returns true if the construction call was collected
+    pub(crate) fn try_collect_construction_call(&mut self, id: &ConstructionCallId) -> bool {
+        let mut construction_call = match self.construction_calls.get(id) {
+            None => return false,
+            Some(call) => call,
+        };
+        if !construction_call.next_action_calls.is_empty() {
+            return false;
+        }
+
+        let mut action_calls = Vec::with_capacity(construction_call.action_calls.0.len() as usize);
+        for action_call_id in construction_call.action_calls.0.iter() {
+            match self.action_calls.get(&action_call_id) {
+                Some(action_call) if !action_call.is_finished() => return false,
+                action_call => action_calls.push((action_call_id, action_call)),
+            }
+        }
+
+        let storage_before = env::storage_usage();
+        let mut number_of_errors = 0;
+        for (action_call_id, action_call) in action_calls.iter() {
+            if action_call.as_ref().map(|a| a.is_error()).unwrap_or(false) {
+                number_of_errors += 1;
+            }
+            self.action_calls.remove(action_call_id);
+        }
+
+        let summary = ConstructionCallSummary {
+            caller: construction_call.caller.clone(),
+            construction_id: construction_call.construction_id.clone(),
+            number_of_action_calls: construction_call.action_calls.0.len(),
+            number_of_errors,
+            block_index_finished: env::block_index(),
+        };
+
+        construction_call.action_calls.clear_storage();
+        construction_call.next_action_calls.clear_storage();
+        construction_call.action_number_of_inputs.clear_storage();
+        construction_call.join_action_calls.clear_storage();
+        if let Some(next_actions_indices) = construction_call.next_actions_indices_override.as_mut() {
+            next_actions_indices.clear_storage();
+        }
+        if let Some(next_actions_splits) = construction_call.next_actions_splits_override.as_mut() {
+            next_actions_splits.clear_storage();
+        }
+        self.construction_calls.remove(id);
+        self.remove_caller_construction_call(&summary.caller, id);
+        self.decr_live_construction_calls(&summary.construction_id);
+        self.construction_call_summaries.insert(id, &summary);
+
+        log!(
+            "Collected construction call {}, freeing {} bytes",
+            id,
+            storage_before.saturating_sub(env::storage_usage())
+        );
+        true
+    }
+
+    /// Delete the construction and clear its storage. If the id has no version, every version of the construction
+    /// is deleted. If delete_actions is true, the actions within the construction which are owned by the caller
+    /// are deleted as well, unless other constructions still use them
+    /// @returns the amount of storage which was freed by the deletion
+    pub(crate) fn delete_construction_internal(
+        &mut self,
+        id: &ConstructionId,
+        caller: &AccountId,
+        delete_actions: bool,
+    ) -> Result<StorageUsage, MallocError> {
+        if &id.owner != caller {
+            return Err(MallocError::CallerDoesNotOwnConstruction {
+                caller: caller.clone(),
+                owner: id.owner.clone(),
+            });
+        }
+        let ids: Vec<ConstructionId> = match id.version {
+            Some(_) => vec![id.clone()],
+            None => {
+                let latest = self.resolve_construction_id(id)?.version.unwrap();
+                (0..=latest)
+                    .map(|version| id.with_version(version))
+                    .filter(|id| self.constructions.get(id).is_some())
+                    .collect()
+            }
+        };
+        // Nothing is deleted if any of the versions is still in use
+        let mut constructions = Vec::with_capacity(ids.len());
+        for id in ids.iter() {
+            constructions.push(self.get_construction(id)?);
+            let number_of_live_calls = self.get_live_construction_calls(id);
+            if number_of_live_calls > 0 {
+                return Err(MallocError::ConstructionHasLiveCalls {
+                    number_of_live_calls,
+                });
+            }
+        }
+
+        let storage_before = env::storage_usage();
+        for (id, construction) in ids.iter().zip(constructions) {
+            self.delete_construction_version(id, construction, caller, delete_actions);
+        }
+        let constructions = &self.constructions;
+        id.repoint_latest_version(&mut self.construction_versions, |id| {
+            constructions.get(id).is_some()
+        });
+        Ok(storage_before.saturating_sub(env::storage_usage()))
+    }
+
+    fn delete_construction_version(
+        &mut self,
+        id: &ConstructionId,
+        mut construction: Construction,
+        caller: &AccountId,
+        delete_actions: bool,
+    ) {
+        let unreferenced_action_ids = self.decr_action_references(&construction);
+        if delete_actions {
+            for action_id in construction.actions.0.iter() {
+                if &action_id.owner != caller {
+                    continue;
+                }
+                if !unreferenced_action_ids.contains(&action_id) {
+                    log!("Keeping the action {} as other constructions use it", action_id.name);
+                    continue;
+                }
+                self.actions.remove(&action_id);
+                remove_from_account_set(&mut self.owner_actions, caller, &action_id);
+                let actions = &self.actions;
+                action_id.repoint_latest_version(&mut self.action_versions, |id| actions.get(id).is_some());
+            }
+        }
+        construction.actions.0.clear();
+        construction.initial_action_indices.clear_storage();
+        construction.next_actions_indices.clear_storage();
+        construction.next_actions_splits.clear_storage();
+        if let Some(next_actions_token_ids) = construction.next_actions_token_ids.as_mut() {
+            next_actions_token_ids.clear_storage();
+        }
+        if let Some(min_returned_amounts) = construction.min_returned_amounts.as_mut() {
+            min_returned_amounts.clear_storage();
+        }
+        self.constructions.remove(id);
+        remove_from_account_set(&mut self.owner_constructions, caller, id);
     }
 }
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
-    const INIT_ACCOUNT_BAL: u128 = 10_000;
-
     use std::convert::TryFrom;
 
-    use crate::actions::ft_calls::FtTransferCallToMallocCall;
-    use crate::malloc_utils::GenericId;
+    use crate::action::Action;
+    use crate::actions::ft_calls::FtTransfer;
+    use crate::test_utils::tests::{
+        get_context, init_json_construction_call, register_json_actions, register_json_construction, swap_action,
+    };
+    use crate::CoreFunctionality;
 
     use super::*;
+    use malloc_call_core::ft::FungibleTokenHandlers;
     use near_sdk::json_types::ValidAccountId;
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::serde_json::{self, json, Value};
+    use near_sdk::test_utils::accounts;
     use near_sdk::testing_env;
-    use near_sdk::MockedBlockchain;
-
-    // mock the context for testing, notice "signer_account_id" that was accessed above from env::
-    fn get_context(predecessor_account_id: ValidAccountId) -> VMContextBuilder {
-        let mut builder = VMContextBuilder::new();
-        builder
-            .current_account_id(accounts(0))
-            .signer_account_id(predecessor_account_id.clone())
-            .predecessor_account_id(predecessor_account_id)
-            .account_balance(INIT_ACCOUNT_BAL);
-        builder
-    }
+    use near_sdk::{MockedBlockchain, PromiseResult, RuntimeFeesConfig, VMConfig};
 
     #[test]
     fn test_get_split_amount_with_leftover() {
@@ -182,8 +1487,9 @@ mod tests {
 
         let ret = Construction::get_split_amounts(
             100,
-            VectorWrapper::from_vec(vec![U128(10), U128(10), U128(10)], "1".as_bytes()),
-        );
+            &SplitSpec::Weights(vec![U128(10), U128(10), U128(10)]),
+        )
+        .unwrap();
         assert_eq!(ret, vec![33, 33, 34]);
     }
 
@@ -194,8 +1500,520 @@ mod tests {
 
         let ret = Construction::get_split_amounts(
             1_000_000,
-            VectorWrapper::from_vec(vec![U128(10), U128(40), U128(50)], "1".as_bytes()),
-        );
+            &SplitSpec::Weights(vec![U128(10), U128(40), U128(50)]),
+        )
+        .unwrap();
         assert_eq!(ret, vec![100_000, 400_000, 500_000]);
     }
+
+    fn get_custom_split_amounts(amount: u128, spec: &str) -> Result<Vec<u128>, MallocError> {
+        let splits: SplitSpec = serde_json::from_str(spec).unwrap();
+        splits.validate()?;
+        Construction::get_split_amounts(amount, &splits)
+    }
+
+    #[test]
+    fn test_get_split_amount_custom() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+
+        // Fixed amounts come out first and the basis points are of what is left
+        let ret = get_custom_split_amounts(
+            1_100,
+            r#"{"splits": [{"Fixed": "100"}, {"BasisPoints": 2500}, {"BasisPoints": 7500}]}"#,
+        );
+        assert_eq!(ret, Ok(vec![100, 250, 750]));
+
+        let ret = get_custom_split_amounts(
+            1_000,
+            r#"{"splits": [{"Fixed": "100"}, {"BasisPoints": 5000}, "AllRemaining"]}"#,
+        );
+        assert_eq!(ret, Ok(vec![100, 450, 450]));
+
+        let ret = get_custom_split_amounts(
+            101,
+            r#"{"splits": [{"Fixed": "1"}, {"Weight": "1"}, {"Weight": "2"}]}"#,
+        );
+        assert_eq!(ret, Ok(vec![1, 33, 67]));
+
+        let ret = get_custom_split_amounts(50, r#"{"splits": [{"Fixed": "100"}, "AllRemaining"]}"#);
+        assert_eq!(
+            ret,
+            Err(MallocError::FixedSplitsExceedAmount {
+                fixed: U128(100),
+                amount: U128(50)
+            })
+        );
+    }
+
+    #[test]
+    fn test_get_split_amount_dust_policies() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+
+        let splits = r#"[{"Weight": "3"}, {"Weight": "3"}, {"Weight": "4"}]"#;
+        let with_policy = |policy: &str| {
+            format!(r#"{{"splits": {}, "dust_policy": "{}"}}"#, splits, policy)
+        };
+        assert_eq!(get_custom_split_amounts(19, &with_policy("LastOutput")), Ok(vec![5, 5, 9]));
+        assert_eq!(get_custom_split_amounts(19, &with_policy("FirstOutput")), Ok(vec![7, 5, 7]));
+        assert_eq!(
+            get_custom_split_amounts(19, &with_policy("LargestRemainder")),
+            Ok(vec![6, 6, 7])
+        );
+        // The dust stays with the caller
+        assert_eq!(
+            get_custom_split_amounts(19, &with_policy("RefundToCaller")),
+            Ok(vec![5, 5, 7])
+        );
+    }
+
+    #[test]
+    fn test_validate_split_spec() {
+        let validate = |spec: &str| serde_json::from_str::<SplitSpec>(spec).unwrap().validate();
+        assert_eq!(
+            validate(r#"{"splits": [{"BasisPoints": 6000}, {"BasisPoints": 6000}]}"#),
+            Err(MallocError::BasisPointsExceedTotal {
+                total_basis_points: 12_000
+            })
+        );
+        assert_eq!(
+            validate(r#"{"splits": ["AllRemaining", "AllRemaining"]}"#),
+            Err(MallocError::MultipleAllRemainingSplits)
+        );
+        assert_eq!(
+            validate(r#"{"splits": ["AllRemaining", {"Weight": "1"}]}"#),
+            Err(MallocError::AllRemainingWithWeights)
+        );
+        assert_eq!(
+            validate(r#"{"splits": [{"Fixed": "10"}, {"BasisPoints": 5000}]}"#),
+            Err(MallocError::SplitsDoNotCoverRemainder {
+                total_basis_points: 5_000
+            })
+        );
+        assert_eq!(validate(r#"["0", "0"]"#), Err(MallocError::SplitsSumToZero));
+        assert_eq!(validate(r#"["1", "2"]"#), Ok(()));
+    }
+
+    #[test]
+    fn test_match_returned_items() {
+        let token_amount = |token_id: &str, amount: u128| TokenAmount {
+            token_id: token_id.to_string(),
+            amount: U128(amount),
+        };
+        let keys: Vec<NextActionsKey> =
+            serde_json::from_str(r#"[{"TokenId": "b.near"}, "CatchAll", {"TokenId": "a.near"}]"#).unwrap();
+        let returned_items = vec![
+            token_amount("a.near", 10),
+            token_amount("c.near", 5),
+            token_amount("a.near", 20),
+            token_amount("d.near", 1),
+        ];
+        let (set_items, unmatched) = Construction::match_returned_items(&keys, &returned_items);
+        assert_eq!(
+            set_items,
+            vec![None, Some(token_amount("c.near", 5)), Some(token_amount("a.near", 30))]
+        );
+        assert_eq!(unmatched, vec![token_amount("d.near", 1)]);
+
+        // Without a catch-all set, the unexpected tokens are not taken
+        let (set_items, unmatched) = Construction::match_returned_items(&keys[2..], &returned_items);
+        assert_eq!(set_items, vec![Some(token_amount("a.near", 30))]);
+        assert_eq!(unmatched, vec![token_amount("c.near", 5), token_amount("d.near", 1)]);
+    }
+
+    #[test]
+    fn test_check_min_returned_amounts() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        register_json_actions(&mut contract, vec![("action1", swap_action()), ("action2", swap_action())]);
+        let construction: Construction = serde_json::from_value(json!({
+            "actions": [
+                { "owner": accounts(0), "name": "action1" },
+                { "owner": accounts(0), "name": "action2" }
+            ],
+            "initial_action_indices": [0],
+            "initial_splits": ["1"],
+            "next_actions_indices": [[[1]], [[]]],
+            "next_actions_splits": [[["1"]], [[]]],
+            "min_returned_amounts": [[{ "token_id": "a.near", "amount": "10" }], []]
+        }))
+        .unwrap();
+        assert!(contract.validate_construction(&construction).is_ok());
+        let token_amount = |token_id: &str, amount: u128| TokenAmount {
+            token_id: token_id.to_string(),
+            amount: U128(amount),
+        };
+
+        // The amounts of a token returned more than once are summed
+        assert_eq!(
+            construction.check_min_returned_amounts(0, &[token_amount("a.near", 4), token_amount("a.near", 6)]),
+            Ok(())
+        );
+        assert_eq!(
+            construction.check_min_returned_amounts(0, &[token_amount("a.near", 4), token_amount("b.near", 6)]),
+            Err(MallocError::ReturnedAmountBelowMinimum {
+                token_id: "a.near".to_string(),
+                min_amount: U128(10),
+                amount: U128(4)
+            })
+        );
+        assert_eq!(construction.check_min_returned_amounts(1, &[]), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_next_actions_token_ids() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        register_json_actions(&mut contract, vec![("action1", swap_action()), ("action2", swap_action())]);
+        let mut construction = json!({
+            "actions": [
+                { "owner": accounts(0), "name": "action1" },
+                { "owner": accounts(0), "name": "action2" }
+            ],
+            "initial_action_indices": [0],
+            "initial_splits": ["1"],
+            "next_actions_indices": [[[1], []], [[]]],
+            "next_actions_splits": [[["1"], []], [[]]]
+        });
+        let validate = |contract: &Contract, construction: &Value| {
+            contract.validate_construction(&serde_json::from_value(construction.clone()).unwrap())
+        };
+
+        construction["next_actions_token_ids"] = json!([[{ "TokenId": "a.near" }, "CatchAll"], null]);
+        assert!(validate(&contract, &construction).is_ok());
+
+        construction["next_actions_token_ids"] = json!([["CatchAll"], null]);
+        assert_eq!(
+            validate(&contract, &construction).unwrap_err(),
+            MallocError::TokenIdsLengthMismatch {
+                action_index: 0,
+                expected: 2,
+                actual: 1
+            }
+        );
+
+        construction["next_actions_token_ids"] = json!([["CatchAll", "CatchAll"], null]);
+        assert_eq!(
+            validate(&contract, &construction).unwrap_err(),
+            MallocError::DuplicateNextActionsKey { action_index: 0 }
+        );
+    }
+
+    #[test]
+    fn test_validate_construction() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        register_json_actions(&mut contract, vec![("action1", swap_action()), ("action2", swap_action())]);
+        let construction: Construction = serde_json::from_value(json!({
+            "actions": [
+                { "owner": accounts(0), "name": "action1" },
+                { "owner": accounts(0), "name": "action2" }
+            ],
+            "initial_action_indices": [0],
+            "initial_splits": ["1"],
+            "next_actions_indices": [[[1]], [[]]],
+            "next_actions_splits": [[["1"]], [[]]]
+        }))
+        .unwrap();
+        assert!(contract.validate_construction(&construction).is_ok());
+    }
+
+    #[test]
+    fn test_validate_leaf_actions() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let transfer = Action::FtTransfer(FtTransfer {
+            receiver_id: accounts(3),
+            token_id: ValidAccountId::try_from("wrapp.localnet".to_string()).unwrap(),
+        });
+        register_json_actions(&mut contract, vec![("action1", swap_action()), ("action2", transfer.clone())]);
+        let construction: Construction = serde_json::from_value(json!({
+            "actions": [
+                { "owner": accounts(0), "name": "action1" },
+                { "owner": accounts(0), "name": "action2" }
+            ],
+            "initial_action_indices": [0],
+            "initial_splits": ["1"],
+            "next_actions_indices": [[[1]], [[]]],
+            "next_actions_splits": [[["1"]], [[]]]
+        }))
+        .unwrap();
+        assert!(contract.validate_construction(&construction).is_ok());
+
+        // A transfer cannot pass its delivered tokens on
+        register_json_actions(&mut contract, vec![("action1", transfer)]);
+        assert_eq!(
+            contract.validate_construction(&construction).unwrap_err(),
+            MallocError::LeafActionHasNextActions { action_index: 0 }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The action with the given id was not found")]
+    fn test_register_construction_missing_action() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        register_json_construction(
+            &mut contract,
+            "construction",
+            vec![("action1", swap_action())],
+            json!({
+                "actions": [
+                    { "owner": accounts(0), "name": "action1" },
+                    { "owner": accounts(0), "name": "not registered" }
+                ],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[]], [[]]],
+                "next_actions_splits": [[[]], [[]]]
+            }),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The action index 2 is out of bounds for a construction with 2 actions")]
+    fn test_register_construction_index_out_of_bounds() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        register_json_construction(
+            &mut contract,
+            "construction",
+            vec![("action1", swap_action()), ("action2", swap_action())],
+            json!({
+                "actions": [
+                    { "owner": accounts(0), "name": "action1" },
+                    { "owner": accounts(0), "name": "action2" }
+                ],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[2]], [[]]],
+                "next_actions_splits": [[["1"]], [[]]]
+            }),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The construction's next actions form a cycle")]
+    fn test_register_construction_cycle() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        register_json_construction(
+            &mut contract,
+            "construction",
+            vec![("action1", swap_action()), ("action2", swap_action())],
+            json!({
+                "actions": [
+                    { "owner": accounts(0), "name": "action1" },
+                    { "owner": accounts(0), "name": "action2" }
+                ],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[1]], [[0]]],
+                "next_actions_splits": [[["1"]], [["1"]]]
+            }),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The splits for a set of actions must not sum to zero")]
+    fn test_register_construction_zero_splits() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        register_json_construction(
+            &mut contract,
+            "construction",
+            vec![("action1", swap_action()), ("action2", swap_action())],
+            json!({
+                "actions": [
+                    { "owner": accounts(0), "name": "action1" },
+                    { "owner": accounts(0), "name": "action2" }
+                ],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[1]], [[]]],
+                "next_actions_splits": [[["0"]], [[]]]
+            }),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The number of splits does not match the number of actions")]
+    fn test_register_construction_mismatched_splits() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        register_json_construction(
+            &mut contract,
+            "construction",
+            vec![("action1", swap_action()), ("action2", swap_action())],
+            json!({
+                "actions": [
+                    { "owner": accounts(0), "name": "action1" },
+                    { "owner": accounts(0), "name": "action2" }
+                ],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[1]], [[]]],
+                "next_actions_splits": [[["1", "1"]], [[]]]
+            }),
+        );
+    }
+
+    #[test]
+    fn test_route_takes_branch() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        // Route the amount to a malloc call if it is at least 50, otherwise leave it with the caller
+        let route: Action = serde_json::from_value(json!({
+            "Route": { "token_id": "wrapp.localnet", "predicates": [{ "AtLeast": "50" }] }
+        }))
+        .unwrap();
+        init_json_construction_call(
+            &mut contract,
+            "routed",
+            vec![("route", route), ("swap", swap_action())],
+            json!({
+                "actions": [
+                    { "owner": accounts(0), "name": "route" },
+                    { "owner": accounts(0), "name": "swap" }
+                ],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[1], []], [[]]],
+                "next_actions_splits": [[["1"], []], [[]]]
+            }),
+            60,
+        );
+
+        // The route is resolved right away and only the swap is left to run
+        let construction_call = contract.get_construction_call_unchecked(&"routed".to_string());
+        assert_eq!(construction_call.action_calls.0.len(), 2);
+        assert_eq!(construction_call.next_action_calls.to_vec(), vec![1]);
+        let route = contract.get_action_call_unchecked(U64(construction_call.action_calls.0.get(0).unwrap()));
+        assert!(route.is_success());
+        assert_eq!(route.route_branch, Some(0));
+        let swap = contract.get_action_call_unchecked(U64(construction_call.action_calls.0.get(1).unwrap()));
+        assert_eq!(swap.amount, 60);
+    }
+
+    #[test]
+    fn test_route_takes_fallback_branch() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let route: Action = serde_json::from_value(json!({
+            "Route": { "token_id": "wrapp.localnet", "predicates": [{ "AtLeast": "50" }] }
+        }))
+        .unwrap();
+        init_json_construction_call(
+            &mut contract,
+            "routed",
+            vec![("route", route), ("swap", swap_action())],
+            json!({
+                "actions": [
+                    { "owner": accounts(0), "name": "route" },
+                    { "owner": accounts(0), "name": "swap" }
+                ],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[1], []], [[]]],
+                "next_actions_splits": [[["1"], []], [[]]]
+            }),
+            10,
+        );
+
+        // The fallback branch leaves the amount with the caller, so the construction call is done right away
+        assert!(contract.get_construction_call_status("routed".to_string()).is_none());
+        let summary = contract
+            .get_construction_call_summary("routed".to_string())
+            .unwrap();
+        assert_eq!(summary.number_of_errors, 0);
+        assert_eq!(
+            contract.get_ft_balance(accounts(0), ValidAccountId::try_from("wrapp.localnet").unwrap()),
+            U128(100)
+        );
+    }
+
+    #[test]
+    fn test_route_receives_other_token() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let route: Action = serde_json::from_value(json!({
+            "Route": { "token_id": "wrapp.localnet", "predicates": [{ "AtLeast": "50" }] }
+        }))
+        .unwrap();
+        init_json_construction_call(
+            &mut contract,
+            "routed",
+            vec![("swap", swap_action()), ("route", route)],
+            json!({
+                "actions": [
+                    { "owner": accounts(0), "name": "swap" },
+                    { "owner": accounts(0), "name": "route" }
+                ],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[1]], [[], []]],
+                "next_actions_splits": [[["1"]], [[], []]]
+            }),
+            100,
+        );
+        contract.process_next_action_call("routed".to_string());
+        let construction_call = contract.get_construction_call_unchecked(&"routed".to_string());
+        let action_call_id = construction_call.action_calls.0.get(0).unwrap();
+
+        // The swap returns another token than the one which the route takes
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                b"[{\"token_id\": \"b.localnet\", \"amount\": \"60\"}]".to_vec()
+            )]
+        );
+        contract.handle_action_callback("routed".to_string(), action_call_id, accounts(0).to_string(), None);
+
+        // The route errors instead of passing the tokens on as wrapp.localnet, which ends the construction call
+        let summary = contract
+            .get_construction_call_summary("routed".to_string())
+            .unwrap();
+        assert_eq!(summary.number_of_action_calls, 2);
+        assert_eq!(summary.number_of_errors, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "A route must have one more set of next actions than predicates")]
+    fn test_route_branches_mismatch() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let route: Action = serde_json::from_value(json!({
+            "Route": { "token_id": "wrapp.localnet", "predicates": [{ "AtLeast": "50" }] }
+        }))
+        .unwrap();
+        register_json_construction(
+            &mut contract,
+            "routed",
+            vec![("route", route)],
+            json!({
+                "actions": [{ "owner": accounts(0), "name": "route" }],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[]]],
+                "next_actions_splits": [[[]]]
+            }),
+        );
+    }
 }
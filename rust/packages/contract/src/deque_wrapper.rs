@@ -0,0 +1,238 @@
+use core::fmt;
+use std::{fmt::Debug, marker::PhantomData};
+
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::LookupMap,
+    serde::{de::Visitor, ser::SerializeSeq, Deserialize, Deserializer, Serialize},
+    IntoStorageKey,
+};
+
+use crate::vector_wrapper::{get_random_bytes_prefix, ClearStorage};
+
+/// The index of the first element of a new deque. Starting in the middle of the index space
+/// lets the deque grow from both ends
+const START_INDEX: u64 = u64::MAX / 2;
+
+/// A persistent double ended queue. The elements are stored in a LookupMap and indexed from head to tail,
+/// so pushing and popping from either end is O(1)
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct DequeWrapper<T> {
+    /// The index of the first element
+    head: u64,
+    /// The index one past the last element
+    tail: u64,
+    elements: LookupMap<u64, T>,
+}
+
+impl<T: BorshSerialize + BorshDeserialize> DequeWrapper<T> {
+    pub fn new<S: IntoStorageKey>(prefix: S) -> Self {
+        DequeWrapper {
+            head: START_INDEX,
+            tail: START_INDEX,
+            elements: LookupMap::new(prefix),
+        }
+    }
+
+    pub fn from_vec<S: IntoStorageKey>(v: Vec<T>, prefix: S) -> Self {
+        let mut deque = Self::new(prefix);
+        v.iter().for_each(|i| deque.push_back(i));
+        deque
+    }
+
+    pub fn len(&self) -> u64 {
+        self.tail - self.head
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    /// Get the element at the given position, counting from the front
+    pub fn get(&self, position: u64) -> Option<T> {
+        if position >= self.len() {
+            return None;
+        }
+        self.elements.get(&(self.head + position))
+    }
+
+    pub fn push_back(&mut self, element: &T) {
+        self.elements.insert(&self.tail, element);
+        self.tail += 1;
+    }
+
+    pub fn push_front(&mut self, element: &T) {
+        self.head -= 1;
+        self.elements.insert(&self.head, element);
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        self.tail -= 1;
+        self.elements.remove(&self.tail)
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let element = self.elements.remove(&self.head);
+        self.head += 1;
+        element
+    }
+
+    /// Remove the element at the given position while keeping the order of the other elements.
+    /// The elements in front of the removed one are shifted back by one
+    pub fn remove(&mut self, position: u64) -> Option<T> {
+        if position >= self.len() {
+            return None;
+        }
+        let removed = self.get(position);
+        for i in (self.head..self.head + position).rev() {
+            let element = self.elements.get(&i).unwrap();
+            self.elements.insert(&(i + 1), &element);
+        }
+        self.elements.remove(&self.head);
+        self.head += 1;
+        removed
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (self.head..self.tail).map(move |i| self.elements.get(&i).unwrap())
+    }
+
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().collect()
+    }
+
+    pub fn clear(&mut self) {
+        for i in self.head..self.tail {
+            self.elements.remove(&i);
+        }
+        self.head = START_INDEX;
+        self.tail = START_INDEX;
+    }
+}
+
+impl ClearStorage for DequeWrapper<u64> {
+    fn clear_storage(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T> Serialize for DequeWrapper<T>
+where
+    T: Serialize + BorshDeserialize + BorshSerialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: near_sdk::serde::Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(self.len() as usize))?;
+        for element in self.iter() {
+            seq.serialize_element(&element)?;
+        }
+        seq.end()
+    }
+}
+
+pub struct DequeWrapperVisitor<T> {
+    marker: PhantomData<fn() -> DequeWrapper<T>>,
+}
+
+impl<'de, T> Visitor<'de> for DequeWrapperVisitor<T>
+where
+    T: Deserialize<'de> + BorshDeserialize + BorshSerialize,
+{
+    type Value = DequeWrapper<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: near_sdk::serde::de::SeqAccess<'de>,
+    {
+        let mut deque: DequeWrapper<T> = DequeWrapper::new(get_random_bytes_prefix());
+        while let Some(v) = seq.next_element()? {
+            deque.push_back(&v);
+        }
+        Ok(deque)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for DequeWrapper<T>
+where
+    T: Deserialize<'de> + BorshDeserialize + BorshSerialize,
+{
+    fn deserialize<D>(deserializer: D) -> Result<DequeWrapper<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(DequeWrapperVisitor {
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<T: PartialEq + BorshSerialize + BorshDeserialize> PartialEq for DequeWrapper<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl<T: BorshSerialize + BorshDeserialize + Debug> Debug for DequeWrapper<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use crate::test_utils::tests::get_context;
+    use near_sdk::MockedBlockchain;
+    use near_sdk::{serde_json, test_utils::accounts, testing_env};
+
+    use super::DequeWrapper;
+
+    #[test]
+    fn test_push_and_pop() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut d = DequeWrapper::<u64>::new("deque".as_bytes());
+        d.push_back(&1);
+        d.push_back(&2);
+        d.push_front(&0);
+        assert_eq!(d.to_vec(), vec![0, 1, 2]);
+        assert_eq!(d.pop_front(), Some(0));
+        assert_eq!(d.pop_back(), Some(2));
+        assert_eq!(d.pop_back(), Some(1));
+        assert_eq!(d.pop_front(), None);
+        assert!(d.is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut d = DequeWrapper::from_vec(vec![0u64, 1, 2, 3], "deque".as_bytes());
+        assert_eq!(d.remove(2), Some(2));
+        assert_eq!(d.to_vec(), vec![0, 1, 3]);
+        assert_eq!(d.remove(0), Some(0));
+        assert_eq!(d.remove(5), None);
+        assert_eq!(d.to_vec(), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_serialize_and_deserialize() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let d = DequeWrapper::from_vec(vec![10u64, 11], "deque".as_bytes());
+        assert_eq!(serde_json::to_string(&d).unwrap(), "[10,11]");
+        let deserialized: DequeWrapper<u64> = serde_json::from_str("[10, 11]").unwrap();
+        assert_eq!(deserialized, d);
+    }
+}
@@ -1,59 +1,370 @@
-pub type PanicError = String;
+use std::fmt;
 
-pub mod panic_errors {
-    // Malloc Call panic_errors
-    pub const MALLOC_CALL_FAILED: &str = "The malloc call failed";
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::{self, json};
+use near_sdk::json_types::U128;
+use near_sdk::AccountId;
 
+use crate::action::ActionId;
+use crate::construction::{ConstructionCallId, ConstructionId};
+
+/// The errors of the malloc contract. Each error has a stable numeric code, see MallocError::code.
+/// Errors are serialized to JSON, as {"code": ..., "message": ..., "error": {"kind": ..., ...fields}}, in panic messages
+/// and as {"kind": ..., ...fields} in the status of failed action calls, so that clients can branch on the kind of the error
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde", tag = "kind")]
+pub enum MallocError {
     // Not found errors
-    pub const NODE_CALL_NOT_FOUND: &str = "The action call was not found";
-    pub const CONSTRUCTION_CALL_SPLITTER_CALL_NOT_FOUND: &str = "The splitter call for the given splitter call id was not found within the construction call";
-    pub const CONSTRUCTION_CALL_ID_NOT_FOUND: &str = "Construction Call ID does not exist";
-    pub const SPLITTER_OWNER_NOT_FOUND: &str = "Splitter owner not found";
-    pub const CONSTRUCTION_CALL_NOT_FOUND: &str = "The construction call was not found";
-    pub const CONSTRUCTION_NOT_FOUND: &str =
-        "The construction with the given id not found in the owner's construction collection";
-    pub const SPLITTER_NOT_FOUND: &str =
-        "The Splitter with the given id not found in the owner's splitter collection";
-    pub const CONSTRUCTION_OWNER_NOT_FOUND: &str = "Construction owner not found";
-    pub const SPLITTER_NOT_FOUND_IN_CONSTRUCTION: &str =
-        "The supplied splitter index is not found in the construction's splitter list";
-    pub const NEXT_SPLITTER_SET_NOT_FOUND_PER_SPLITTER: &str = "The next splitter set for a splitter was not found for the construction with the given index";
-    pub const NEXT_SPLITTER_SET_NOT_FOUND_PER_CHILD: &str =
-        "The next splitter set for a child of a splitter was not found for the construction";
+    ActionNotFound { action_id: ActionId },
+    ActionCallNotFound { action_call_id: u64 },
+    ConstructionNotFound { construction_id: ConstructionId },
+    ConstructionCallNotFound { construction_call_id: ConstructionCallId },
+    ActionIndexOutOfBounds { index: u64, number_of_actions: u64 },
 
     // Unauthorized errors
-    pub const CALLER_DOES_NOT_OWN_CONSTRUCTION: &str = "The caller does not own the construction";
-
-    // Parsing panic_errors
-    pub const FAILED_TO_PARSE_NUMBER: &str = "Failed to parse a number from the string";
-    pub const FAILED_TO_PARSE_SPLITTER: &str = "Failed to parse the splitter string";
-
-    // Funding panic_errors
-    pub const CALLEE_DID_NOT_DEPOSIT_SUFFICIENT_FUNDS: &str =
-        "The callee did not deposit sufficient funds";
-
-    // ID Registration errors
-    pub const CONSTRUCTION_CALL_ID_ALREADY_USED: &str =
-        "The given construction call id has already been registered";
-    pub const NODE_CALL_ID_ALREADY_USED: &str =
-        "The given action call id has already been registered";
-
-    // Malformed panic_errors: panic_errors corresponding to splitters and constructions with incorrect shapes and/or inputs
-    pub const NUMB_OF_NODES_NOT_EQUAL_TO_NUMB_NAMES: &str =
-        "The number of actions does not equal to the number of names";
-    pub const CONSTRUCTION_CALL_SPLITTER_STACK_EMPTY: &str =
-        "The splitter stack for the construction call is empty";
-    pub const NUMBER_OF_SPLITTERS_DID_NOT_MATCH_RETURN: &str =
-        "The number of splitters for the next set of inputs does not match the call's return";
-    pub const FT_CONTRACT_ID_NOT_MATCH: &str = "The returned fungible token contract type and the required fungible token type do not match";
-    pub const NO_CHILDREN_SPECIFIED: &str = "At least one child must be specified";
-    pub const MORE_USED_THAN_ALLOWED: &str = "More currency was used than specified by the call";
-    pub const NUMB_NODES_DNE_NUMB_SPLITS: &str =
-        "The number of endpoints specified does not match the number of splits";
-    pub const NUMB_OF_SPLITTER_IDXS_DID_NOT_MATCH_SPLITTERS: &str =
-        "The number of splitter indixes does not match the number of splitters";
+    CallerDoesNotOwnConstruction { caller: AccountId, owner: AccountId },
+    CallerDoesNotOwnConstructionCall { caller: AccountId, owner: AccountId },
+
+    // State errors
+    ConstructionHasLiveCalls { number_of_live_calls: u64 },
+    ConstructionCallIdAlreadyUsed { construction_call_id: ConstructionCallId },
+    ConstructionCallStackEmpty,
+    ConstructionCallAborted,
+    ConstructionCallAlreadyAborted,
+    ActionCallNotExecuting,
+    ActionCallNotTimedOut { block_index_timeout: u64 },
+
+    // Malformed constructions and inputs
+    NumberOfActionsDoesNotMatchNames { number_of_actions: u64, number_of_names: u64 },
+    NoInitialActions,
+    SplitsLengthMismatch { expected: u64, actual: u64 },
+    NextActionsLengthMismatch { expected: u64, actual: u64 },
+    SplitsSumToZero,
+    ConstructionHasCycle,
+    TooManyActionCallsToEstimate { max_action_calls: u64 },
+    LeafActionHasNextActions { action_index: u64 },
+
+    // Action call errors
+    ActionCallFailed,
+    ActionCallTimedOut,
+    ReturnNotParsable,
+    ReturnAmountNotParsable { amount: String },
+    ReturnTokenIdMissing,
+    ReturnLengthMismatch { expected: u64, actual: u64 },
+    JoinReceivedNoInputs,
+    ReclaimBalanceNotParsable,
+    ArgsNotSerializable { message: String },
+
+    // Native NEAR errors
+    NoNearAttached,
+    WnearContractNotConfigured,
+    WnearContractDoesNotMatch { expected: AccountId, actual: AccountId },
 
     // Assertion errors for code proving
-    pub const NUMB_OF_SPLITS_DOES_NOT_EQUAL_NUMB_AMOUNTS: &str =
-        "Unexpected: the number of splits does not equal the number of amounts";
+    SplitsDoNotMatchAmounts,
+
+    // Appended after the first release, in the order in which they were introduced. Failed action calls store
+    // their error with Borsh, so new errors are only ever appended to keep the stored discriminants stable
+    CallerIsNotContractOwner { caller: AccountId, owner: AccountId },
+    ContractPaused,
+    ContractNotInitialized,
+    UnknownStateVersion { version: u32, current_version: u32 },
+    MallocCallNotRegistered { malloc_call_id: AccountId },
+    MallocCallMetadataNotParsable,
+    BasisPointsExceedTotal { total_basis_points: u32 },
+    MultipleAllRemainingSplits,
+    AllRemainingWithWeights,
+    SplitsDoNotCoverRemainder { total_basis_points: u32 },
+    FixedSplitsExceedAmount { fixed: U128, amount: U128 },
+    RouteBranchesMismatch { action_index: u64, expected: u64, actual: u64 },
+    RouteNotRunnable,
+    TokenIdsLengthMismatch { action_index: u64, expected: u64, actual: u64 },
+    DuplicateNextActionsKey { action_index: u64 },
+    ReturnedAmountBelowMinimum { token_id: AccountId, min_amount: U128, amount: U128 },
+    InsufficientStorageDeposit { required: U128, attached: U128 },
+    InsufficientBalance { token_id: AccountId, balance: U128, amount: U128 },
+    RouteTokenIdDoesNotMatch { action_index: u64, expected: AccountId, actual: AccountId },
+}
+
+impl MallocError {
+    /// The stable numeric code of the error. Codes are grouped by the hundreds: 1xx not found, 2xx unauthorized,
+    /// 3xx state, 4xx malformed constructions, 5xx action calls, 6xx native NEAR and 9xx internal errors
+    pub fn code(&self) -> u32 {
+        match self {
+            MallocError::ActionNotFound { .. } => 100,
+            MallocError::ActionCallNotFound { .. } => 101,
+            MallocError::ConstructionNotFound { .. } => 102,
+            MallocError::ConstructionCallNotFound { .. } => 103,
+            MallocError::ActionIndexOutOfBounds { .. } => 104,
+            MallocError::MallocCallNotRegistered { .. } => 105,
+            MallocError::CallerDoesNotOwnConstruction { .. } => 200,
+            MallocError::CallerDoesNotOwnConstructionCall { .. } => 201,
+            MallocError::CallerIsNotContractOwner { .. } => 202,
+            MallocError::ConstructionHasLiveCalls { .. } => 300,
+            MallocError::ConstructionCallIdAlreadyUsed { .. } => 301,
+            MallocError::ConstructionCallStackEmpty => 302,
+            MallocError::ConstructionCallAborted => 303,
+            MallocError::ConstructionCallAlreadyAborted => 304,
+            MallocError::ActionCallNotExecuting => 305,
+            MallocError::ActionCallNotTimedOut { .. } => 306,
+            MallocError::ContractPaused => 307,
+            MallocError::ContractNotInitialized => 308,
+            MallocError::UnknownStateVersion { .. } => 309,
+            MallocError::NumberOfActionsDoesNotMatchNames { .. } => 400,
+            MallocError::NoInitialActions => 401,
+            MallocError::SplitsLengthMismatch { .. } => 402,
+            MallocError::NextActionsLengthMismatch { .. } => 403,
+            MallocError::SplitsSumToZero => 404,
+            MallocError::ConstructionHasCycle => 405,
+            MallocError::TooManyActionCallsToEstimate { .. } => 406,
+            MallocError::LeafActionHasNextActions { .. } => 407,
+            MallocError::BasisPointsExceedTotal { .. } => 408,
+            MallocError::MultipleAllRemainingSplits => 409,
+            MallocError::AllRemainingWithWeights => 410,
+            MallocError::SplitsDoNotCoverRemainder { .. } => 411,
+            MallocError::RouteBranchesMismatch { .. } => 412,
+            MallocError::TokenIdsLengthMismatch { .. } => 413,
+            MallocError::DuplicateNextActionsKey { .. } => 414,
+            MallocError::ActionCallFailed => 500,
+            MallocError::ActionCallTimedOut => 501,
+            MallocError::ReturnNotParsable => 502,
+            MallocError::ReturnAmountNotParsable { .. } => 503,
+            MallocError::ReturnTokenIdMissing => 504,
+            MallocError::ReturnLengthMismatch { .. } => 505,
+            MallocError::JoinReceivedNoInputs => 506,
+            MallocError::ReclaimBalanceNotParsable => 507,
+            MallocError::ArgsNotSerializable { .. } => 508,
+            MallocError::MallocCallMetadataNotParsable => 509,
+            MallocError::FixedSplitsExceedAmount { .. } => 510,
+            MallocError::RouteNotRunnable => 511,
+            MallocError::ReturnedAmountBelowMinimum { .. } => 512,
+            MallocError::NoNearAttached => 600,
+            MallocError::WnearContractNotConfigured => 601,
+            MallocError::WnearContractDoesNotMatch { .. } => 602,
+            MallocError::SplitsDoNotMatchAmounts => 900,
+            MallocError::InsufficientStorageDeposit { .. } => 603,
+            MallocError::InsufficientBalance { .. } => 513,
+            MallocError::RouteTokenIdDoesNotMatch { .. } => 514,
+        }
+    }
+
+    /// A human readable description of the error
+    pub fn message(&self) -> String {
+        match self {
+            MallocError::ActionNotFound { action_id } => format!(
+                "The action with the given id was not found: {}'s {}",
+                action_id.owner, action_id.name
+            ),
+            MallocError::ActionCallNotFound { action_call_id } => {
+                format!("The action call {} was not found", action_call_id)
+            }
+            MallocError::ConstructionNotFound { construction_id } => format!(
+                "The construction with the given id was not found: {}'s {}",
+                construction_id.owner, construction_id.name
+            ),
+            MallocError::ConstructionCallNotFound { construction_call_id } => {
+                format!("The construction call {} was not found", construction_call_id)
+            }
+            MallocError::ActionIndexOutOfBounds {
+                index,
+                number_of_actions,
+            } => format!(
+                "The action index {} is out of bounds for a construction with {} actions",
+                index, number_of_actions
+            ),
+            MallocError::MallocCallNotRegistered { malloc_call_id } => format!(
+                "The malloc call is not registered in the malloc call registry: {}",
+                malloc_call_id
+            ),
+            MallocError::CallerDoesNotOwnConstruction { caller, owner } => format!(
+                "The caller does not own the construction: {} is not {}",
+                caller, owner
+            ),
+            MallocError::CallerDoesNotOwnConstructionCall { caller, owner } => format!(
+                "The caller does not own the construction call: {} is not {}",
+                caller, owner
+            ),
+            MallocError::CallerIsNotContractOwner { caller, owner } => format!(
+                "Only the contract's owner can call this method: {} is not {}",
+                caller, owner
+            ),
+            MallocError::ConstructionHasLiveCalls {
+                number_of_live_calls,
+            } => format!(
+                "The construction cannot be deleted while construction calls still reference it: {} live construction calls",
+                number_of_live_calls
+            ),
+            MallocError::ConstructionCallIdAlreadyUsed {
+                construction_call_id,
+            } => format!(
+                "The given construction call id has already been registered: {}",
+                construction_call_id
+            ),
+            MallocError::ConstructionCallStackEmpty => {
+                "The construction call has no action calls scheduled".to_string()
+            }
+            MallocError::ConstructionCallAborted => "The construction call was aborted".to_string(),
+            MallocError::ConstructionCallAlreadyAborted => {
+                "The construction call was already aborted".to_string()
+            }
+            MallocError::ActionCallNotExecuting => "The action call is not executing".to_string(),
+            MallocError::ActionCallNotTimedOut {
+                block_index_timeout,
+            } => format!(
+                "The action call's timeout has not passed since it started executing, it times out at block {}",
+                block_index_timeout
+            ),
+            MallocError::ContractPaused => {
+                "The contract is paused, only withdrawals and exits are allowed".to_string()
+            }
+            MallocError::ContractNotInitialized => "The contract's state is not initialized".to_string(),
+            MallocError::UnknownStateVersion {
+                version,
+                current_version,
+            } => format!(
+                "The stored state has the layout version {}, which cannot be migrated to the current version {}",
+                version, current_version
+            ),
+            MallocError::NumberOfActionsDoesNotMatchNames {
+                number_of_actions,
+                number_of_names,
+            } => format!(
+                "The number of actions does not equal to the number of names: {} actions and {} names",
+                number_of_actions, number_of_names
+            ),
+            MallocError::NoInitialActions => "At least one initial action must be specified".to_string(),
+            MallocError::SplitsLengthMismatch { expected, actual } => format!(
+                "The number of splits does not match the number of actions: expected {}, got {}",
+                expected, actual
+            ),
+            MallocError::NextActionsLengthMismatch { expected, actual } => format!(
+                "The number of next action sets does not match the number of actions in the construction: expected {}, got {}",
+                expected, actual
+            ),
+            MallocError::SplitsSumToZero => {
+                "The splits for a set of actions must not sum to zero".to_string()
+            }
+            MallocError::ConstructionHasCycle => {
+                "The construction's next actions form a cycle".to_string()
+            }
+            MallocError::TooManyActionCallsToEstimate { max_action_calls } => format!(
+                "The construction has more than {} action calls to estimate, it may contain a cycle",
+                max_action_calls
+            ),
+            MallocError::LeafActionHasNextActions { action_index } => format!(
+                "An action which sends tokens out of the caller's balance cannot have next actions: action {}",
+                action_index
+            ),
+            MallocError::BasisPointsExceedTotal { total_basis_points } => format!(
+                "The basis points of a split sum to more than 10000: {}",
+                total_basis_points
+            ),
+            MallocError::MultipleAllRemainingSplits => {
+                "A split can have at most one AllRemaining output".to_string()
+            }
+            MallocError::AllRemainingWithWeights => {
+                "A split cannot have both an AllRemaining output and weighted outputs".to_string()
+            }
+            MallocError::SplitsDoNotCoverRemainder { total_basis_points } => format!(
+                "A split without weighted or AllRemaining outputs must have basis points which sum to 10000: {}",
+                total_basis_points
+            ),
+            MallocError::RouteBranchesMismatch {
+                action_index,
+                expected,
+                actual,
+            } => format!(
+                "A route must have one more set of next actions than predicates: action {} expects {} sets, got {}",
+                action_index, expected, actual
+            ),
+            MallocError::TokenIdsLengthMismatch {
+                action_index,
+                expected,
+                actual,
+            } => format!(
+                "The number of token ids does not match the number of next action sets: action {} expects {}, got {}",
+                action_index, expected, actual
+            ),
+            MallocError::DuplicateNextActionsKey { action_index } => format!(
+                "Two next action sets cannot be keyed by the same token id or both catch all: action {}",
+                action_index
+            ),
+            MallocError::ActionCallFailed => "The action call's promise failed".to_string(),
+            MallocError::ActionCallTimedOut => "The action call timed out".to_string(),
+            MallocError::ReturnNotParsable => {
+                "The action call's return could not be parsed as an amount or a list of return items"
+                    .to_string()
+            }
+            MallocError::ReturnAmountNotParsable { amount } => {
+                format!("A returned amount could not be parsed: {}", amount)
+            }
+            MallocError::ReturnTokenIdMissing => {
+                "The action call returned an amount but no token return id was given".to_string()
+            }
+            MallocError::ReturnLengthMismatch { expected, actual } => format!(
+                "The number of next action sets does not match the number of returned tokens: expected {}, got {}",
+                expected, actual
+            ),
+            MallocError::JoinReceivedNoInputs => {
+                "None of the join's incoming edges passed on any tokens".to_string()
+            }
+            MallocError::ReclaimBalanceNotParsable => {
+                "The malloc call's balance could not be parsed from its get_ft_balance".to_string()
+            }
+            MallocError::ArgsNotSerializable { message } => {
+                format!("The call's args could not be serialized: {}", message)
+            }
+            MallocError::MallocCallMetadataNotParsable => {
+                "The malloc call's metadata could not be parsed".to_string()
+            }
+            MallocError::FixedSplitsExceedAmount { fixed, amount } => format!(
+                "The fixed amounts of a split sum to more than the amount being split: {} is more than {}",
+                fixed.0, amount.0
+            ),
+            MallocError::RouteNotRunnable => {
+                "A route is resolved when its input arrives and cannot be run as an action call".to_string()
+            }
+            MallocError::ReturnedAmountBelowMinimum {
+                token_id,
+                min_amount,
+                amount,
+            } => format!(
+                "The action call returned less of {} than its minimum: {} is less than {}",
+                token_id, amount.0, min_amount.0
+            ),
+            MallocError::NoNearAttached => "Some NEAR must be attached to deposit".to_string(),
+            MallocError::WnearContractNotConfigured => "No wNEAR contract is configured".to_string(),
+            MallocError::WnearContractDoesNotMatch { expected, actual } => format!(
+                "The action's wNEAR contract does not match the configured wNEAR contract: expected {}, got {}",
+                expected, actual
+            ),
+            MallocError::SplitsDoNotMatchAmounts => {
+                "Unexpected: the number of splits does not equal the number of amounts".to_string()
+            }
+            MallocError::InsufficientStorageDeposit { required, attached } => format!(
+                "The attached deposit does not cover the storage used: {} is required, {} was attached",
+                required.0, attached.0
+            ),
+            MallocError::InsufficientBalance { token_id, balance, amount } => format!(
+                "The caller's balance of {} is too low: {} is needed, {} is held",
+                token_id, amount.0, balance.0
+            ),
+            MallocError::RouteTokenIdDoesNotMatch { action_index, expected, actual } => format!(
+                "The route with index {} takes {}, but it received {}",
+                action_index, expected, actual
+            ),
+        }
+    }
+}
+
+impl fmt::Display for MallocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report = json!({
+            "code": self.code(),
+            "message": self.message(),
+            "error": self,
+        });
+        write!(f, "{}", serde_json::to_string(&report).map_err(|_| fmt::Error)?)
+    }
 }
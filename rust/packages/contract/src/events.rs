@@ -0,0 +1,97 @@
+use malloc_call_core::events::emit_event;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::AccountId;
+
+use crate::action::{ActionCall, ActionCallId, ActionCallStatus, ActionId, TokenAmount};
+use crate::construction::{ConstructionCallId, ConstructionId};
+use crate::errors::MallocError;
+use crate::malloc_call_registry::MallocCallRegistryEntry;
+
+pub const EVENT_STANDARD: &str = "malloc";
+pub const EVENT_VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConstructionCallInitData {
+    pub construction_call_id: ConstructionCallId,
+    pub construction_id: ConstructionId,
+    pub caller: AccountId,
+    pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ActionCallStartData {
+    pub construction_call_id: ConstructionCallId,
+    pub action_call_id: U64,
+    pub action_index: u64,
+    pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ActionCallSuccessData {
+    pub construction_call_id: ConstructionCallId,
+    pub action_call_id: U64,
+    pub action_index: u64,
+    /// The tokens which the action call returned and passed on to its next actions
+    pub returned_items: Vec<TokenAmount>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ActionCallFailureData {
+    pub construction_call_id: ConstructionCallId,
+    pub action_call_id: U64,
+    pub action_index: u64,
+    pub error: MallocError,
+}
+
+/// The events of the malloc contract, which are logged in the NEP-297 format so that indexers
+/// can rebuild the history of the registered constructions and their calls
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde", tag = "event", content = "data", rename_all = "snake_case")]
+pub enum MallocEvent {
+    ActionRegister(Vec<ActionId>),
+    ConstructionRegister(Vec<ConstructionId>),
+    ConstructionCallInit(Vec<ConstructionCallInitData>),
+    ActionCallStart(Vec<ActionCallStartData>),
+    ActionCallSuccess(Vec<ActionCallSuccessData>),
+    ActionCallFailure(Vec<ActionCallFailureData>),
+    /// A malloc call was registered, or its metadata or trust level changed
+    MallocCallRegister(Vec<MallocCallRegistryEntry>),
+}
+
+impl MallocEvent {
+    pub fn emit(&self) {
+        emit_event(EVENT_STANDARD, EVENT_VERSION, self);
+    }
+
+    /// Emit the success or failure event of a finished action call. Nothing is emitted for an unfinished action call
+    pub(crate) fn emit_action_call_finished(
+        construction_call_id: &ConstructionCallId,
+        action_call_id: ActionCallId,
+        action_call: &ActionCall,
+    ) {
+        let action_index = action_call.action_index_in_construction;
+        let event = match action_call.get_status() {
+            ActionCallStatus::Success => MallocEvent::ActionCallSuccess(vec![ActionCallSuccessData {
+                construction_call_id: construction_call_id.clone(),
+                action_call_id: U64(action_call_id),
+                action_index,
+                returned_items: action_call.returned_items.clone(),
+            }]),
+            ActionCallStatus::Error { error, .. } => {
+                MallocEvent::ActionCallFailure(vec![ActionCallFailureData {
+                    construction_call_id: construction_call_id.clone(),
+                    action_call_id: U64(action_call_id),
+                    action_index,
+                    error: error.clone(),
+                }])
+            }
+            _ => return,
+        };
+        event.emit();
+    }
+}
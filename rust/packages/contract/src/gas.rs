@@ -1,5 +1,25 @@
+use malloc_call_core::ft::MALLOC_CALL_CORE_GAS_FOR_WITHDRAW_TO;
 use near_sdk::Gas;
 
 // This can be brought down probs?
 pub const CALLBACK_GAS: Gas = 5_000_000_000_000 * 5;
 pub const CROSS_CONTRACT_BASE_GAS: Gas = 20_000_000_000_000;
+/// The fees burnt when creating the receipts for a single action call, i.e. the action's promises and its callback
+pub const STEP_RECEIPT_FEES_GAS: Gas = 40_000_000_000_000;
+/// The gas kept aside to finish the current function call when scheduling steps of a self-driving construction call
+pub const SELF_DRIVE_RESERVE_GAS: Gas = 10_000_000_000_000;
+/// The gas for querying a malloc call's get_ft_balance view
+pub const GET_FT_BALANCE_GAS: Gas = 5_000_000_000_000;
+/// The gas for resolve_reclaim_balance, which withdraws the queried balance from the malloc call
+pub const RESOLVE_RECLAIM_GAS: Gas =
+    MALLOC_CALL_CORE_GAS_FOR_WITHDRAW_TO + CROSS_CONTRACT_BASE_GAS + CALLBACK_GAS;
+/// The gas for reclaiming a caller's tokens from a malloc call, which queries the balance and then withdraws it
+pub const RECLAIM_GAS: Gas = GET_FT_BALANCE_GAS + RESOLVE_RECLAIM_GAS;
+/// The gas for a wNEAR contract's near_deposit or near_withdraw
+pub const WNEAR_CALL_GAS: Gas = 10_000_000_000_000;
+/// The gas for the resolve step of a NEAR transfer, wrap or unwrap
+pub const RESOLVE_NEAR_GAS: Gas = 10_000_000_000_000;
+/// The gas for querying a malloc call's metadata view
+pub const MALLOC_CALL_METADATA_GAS: Gas = 5_000_000_000_000;
+/// The gas for resolve_register_malloc_call, which caches the queried metadata
+pub const RESOLVE_REGISTER_MALLOC_CALL_GAS: Gas = 10_000_000_000_000;
@@ -12,121 +12,254 @@
  */
 
 use construction::{
-    Construction, ConstructionCall, ConstructionCallId, ConstructionId,
+    Construction, ConstructionCall, ConstructionCallId, ConstructionCallStatus, ConstructionCallSummary,
+    ConstructionGasEstimate, ConstructionId, ConstructionInfo,
     NextActionsIndicesForConstruction, NextActionsSplitsForConstruction,
 };
-use malloc_call_core::ft::{FungibleTokenBalances, FungibleTokenHandlers};
-use malloc_call_core::{MallocCallFT, ReturnItem};
+use malloc_call_core::ft::{
+    FungibleTokenBalances, FungibleTokenHandlers, OnTransferOpts, TransferType,
+    MALLOC_CALL_CORE_GAS_FOR_WITHDRAW_TO,
+};
+use malloc_call_core::MallocCallFT;
 // To conserve gas, efficient serialization is achieved through Borsh (http://borsh.io/)
-use action::{Action, ActionCall, ActionCallId, ActionId};
+use action::{Action, ActionCall, ActionCallId, ActionId, ActionInfo};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::UnorderedMap;
+use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet};
 use near_sdk::json_types::{ValidAccountId, U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
-    env, log, near_bindgen, serde, serde_json, setup_alloc, utils, AccountId, Gas, PanicOnDefault,
+    env, log, near_bindgen, serde, serde_json::{self, json}, setup_alloc, utils, assert_one_yocto, AccountId, Balance, Gas,
+    PanicOnDefault, Promise, StorageUsage,
 };
-use vector_wrapper::VectorWrapper;
+use actions::near_calls::NEAR_TOKEN_ID;
+use malloc_call_registry::{MallocCallRegistryEntry, TrustLevel};
+use malloc_utils::{insert_into_account_set, paginate_account_set};
+use splits::SplitSpec;
 
-use crate::errors::panic_errors;
+use crate::errors::MallocError;
+use crate::events::{ConstructionCallInitData, MallocEvent};
 
 mod action;
 mod actions;
 mod construction;
+mod deque_wrapper;
 pub mod errors;
+pub mod events;
 mod gas;
+mod malloc_call_registry;
 mod malloc_utils;
+mod migrations;
+mod splits;
 mod test_utils;
 mod vector_wrapper;
 
 setup_alloc!();
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault, MallocCallFT)]
+#[malloc_call_ft(deposit_guard = "assert_deposit_allowed")]
 /// The Contract's state
 pub struct Contract {
-    /// A store for all the constructions. Constructions are meant to be immutable objects
-    // TODO: enforce immutability: https://github.com/Lev-Stambler/malloc-near-2/issues/18
+    /// A store for all the constructions, keyed by their versioned id. Constructions are immutable,
+    /// registering under an existing name creates a new version
     constructions: UnorderedMap<ConstructionId, Construction>,
+    /// The latest registered version for each unversioned construction id
+    construction_versions: LookupMap<ConstructionId, u64>,
+    /// The versioned ids of the constructions registered by each owner
+    owner_constructions: LookupMap<AccountId, UnorderedSet<ConstructionId>>,
+    /// The number of construction calls which currently reference a construction.
+    /// A construction cannot be deleted while this count is non-zero
+    live_construction_calls: LookupMap<ConstructionId, u64>,
     /// A store of all the construction calls. Construction calls are mutable and ephemeral objects
     /// They should only live as long as a single call to a construction
     construction_calls: UnorderedMap<ConstructionCallId, ConstructionCall>,
+    /// The construction calls started by each caller which were not garbage collected yet
+    caller_construction_calls: LookupMap<AccountId, UnorderedSet<ConstructionCallId>>,
+    /// Compact records of the construction calls which finished and were garbage collected
+    construction_call_summaries: LookupMap<ConstructionCallId, ConstructionCallSummary>,
     /// A store of all the action calls. Action calls are mutable and ephemeral objects
     /// They should only live as long as a single call to a construction. They get deleted
     /// When a construction call gets deleted
     action_calls: UnorderedMap<ActionCallId, ActionCall>,
-    /// A store for all the actions. Actions are meant to be immutable objects
-    // TODO: enforce immutability: https://github.com/Lev-Stambler/malloc-near-2/issues/18
+    /// A store for all the actions, keyed by their versioned id. Actions are immutable,
+    /// registering under an existing name creates a new version
     actions: UnorderedMap<ActionId, Action>,
+    /// The latest registered version for each unversioned action id
+    action_versions: LookupMap<ActionId, u64>,
+    /// The versioned ids of the actions registered by each owner
+    owner_actions: LookupMap<AccountId, UnorderedSet<ActionId>>,
+    /// The number of registered constructions which use each action.
+    /// An action is only deleted along with a construction once no other construction uses it
+    action_references: LookupMap<ActionId, u64>,
     /// Balances keeps track of all the users' balances. See malloc-call-core's documentation for more information
     balances: FungibleTokenBalances,
     /// Keeps track of the next action call id so that action call id's can all be unique and need not be supplied by the caller
     next_action_call_id: ActionCallId,
     /// The current contract's ID. This field is needed for MallocCallFT
     malloc_contract_id: AccountId,
+    /// The wNEAR contract which the WrapNear and UnwrapNear actions go through
+    wnear_contract_id: Option<AccountId>,
+    /// The malloc calls which MallocCall actions can call, along with their cached metadata
+    malloc_call_registry: UnorderedMap<AccountId, MallocCallRegistryEntry>,
+    /// The storage stake which each owner deposited for the actions and constructions they registered
+    storage_deposits: LookupMap<AccountId, Balance>,
+    /// The account which can pause the contract and hand over its ownership
+    owner_id: AccountId,
+    /// While paused, no new construction calls or deposits are accepted and no action calls are started.
+    /// Withdrawals, aborts and reclaims keep working so that users can exit, and the tokens which action calls
+    /// in flight send back are still credited
+    is_paused: bool,
 }
 
 pub trait CoreFunctionality {
     fn register_actions(&mut self, action_names: Vec<String>, actions: Vec<Action>);
     fn register_construction(&mut self, construction_name: String, construction: Construction);
+    #[allow(clippy::too_many_arguments)]
     fn init_construction(
         &mut self,
         construction_call_id: ConstructionCallId,
         construction_id: ConstructionId,
         amount: U128,
-        initial_action_indices: Vec<u64>,
-        initial_splits: VectorWrapper<U128>,
-        next_actions_indices: NextActionsIndicesForConstruction,
-        next_actions_splits: NextActionsSplitsForConstruction,
+        initial_action_indices: Option<Vec<u64>>,
+        initial_splits: Option<SplitSpec>,
+        next_actions_indices: Option<NextActionsIndicesForConstruction>,
+        next_actions_splits: Option<NextActionsSplitsForConstruction>,
     );
-    fn delete_construction(&mut self, construction_id: ConstructionId);
+    fn delete_construction(&mut self, construction_id: ConstructionId, delete_actions: Option<bool>);
     fn process_next_action_call(&mut self, construction_call_id: ConstructionCallId);
+    fn run_construction_call(&mut self, construction_call_id: ConstructionCallId);
+    fn abort_construction_call(&mut self, construction_call_id: ConstructionCallId);
+    fn reclaim_timed_out_action_call(
+        &mut self,
+        construction_call_id: ConstructionCallId,
+        action_call_id: U64,
+    );
 }
 
 #[near_bindgen]
 impl CoreFunctionality for Contract {
-    fn delete_construction(&mut self, construction_id: ConstructionId) {
-        todo!();
-        // self.delete_construction_internal(construction_id, env::predecessor_account_id())
-        //     .unwrap_or_else(|e| panic!(e))
+    /// Delete a construction owned by the caller. If the construction id has no version, every version is deleted.
+    /// If delete_actions is true, all the actions in the construction which are owned by the caller are deleted as well.
+    /// The storage stake freed by the deletion is refunded to the caller, up to the storage deposits the caller paid
+    fn delete_construction(&mut self, construction_id: ConstructionId, delete_actions: Option<bool>) {
+        let caller = env::predecessor_account_id();
+        let storage_freed = self
+            .delete_construction_internal(
+                &construction_id,
+                &caller,
+                delete_actions.unwrap_or(false),
+            )
+            .unwrap_or_else(|e| panic!("{}", e));
+        let refund = self.release_storage_deposit(&caller, storage_freed);
+        if refund > 0 {
+            Promise::new(caller).transfer(refund);
+        }
+    }
+
+    /// Abort a construction call owned by the caller. Nothing more is scheduled for the construction call
+    /// and the malloc calls which already completed are asked to revert, most recent first
+    fn abort_construction_call(&mut self, construction_call_id: ConstructionCallId) {
+        let caller = env::predecessor_account_id();
+        self.abort_construction_call_internal(&construction_call_id, &caller)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Mark an action call which is stuck executing as timed out, once its construction's timeout passed.
+    /// This can be called by the caller or by any keeper. The tokens which the caller left with the action's malloc call
+    /// are then reclaimed by querying the malloc call's get_ft_balance and withdrawing the balance back to the caller,
+    /// up to the amount which the timed out action call sent. The caller's other tokens at the malloc call are left alone
+    fn reclaim_timed_out_action_call(
+        &mut self,
+        construction_call_id: ConstructionCallId,
+        action_call_id: U64,
+    ) {
+        let (action, caller, amount) = self
+            .timeout_action_call_internal(&construction_call_id, action_call_id.into())
+            .unwrap_or_else(|e| panic!("{}", e));
+        let malloc_call_id = match action.get_malloc_call_id() {
+            Some(malloc_call_id) => malloc_call_id,
+            None => return,
+        };
+        self.reclaim_from_malloc_call(malloc_call_id, caller, action.get_token_id(), amount);
     }
 
+    /// Register actions under the caller's account. The attached deposit must cover the storage they use,
+    /// the rest of it is refunded
+    #[payable]
     fn register_actions(&mut self, action_names: Vec<String>, actions: Vec<Action>) {
         assert_eq!(
             action_names.len(),
             actions.len(),
             "{}",
-            panic_errors::NUMB_OF_NODES_NOT_EQUAL_TO_NUMB_NAMES
+            MallocError::NumberOfActionsDoesNotMatchNames {
+                number_of_actions: actions.len() as u64,
+                number_of_names: action_names.len() as u64,
+            }
         );
 
-        let owner = Some(env::predecessor_account_id());
-        for i in 0..action_names.len() {
-            self.actions.insert(
-                &ActionId::new(action_names[i].clone(), owner.clone()),
-                &actions[i],
-            );
+        let storage_before = env::storage_usage();
+        let owner = env::predecessor_account_id();
+        let mut ids = Vec::with_capacity(action_names.len());
+        for (action_name, mut action) in action_names.into_iter().zip(actions) {
+            action
+                .set_defaults_from_registry(self)
+                .unwrap_or_else(|e| panic!("{}", e));
+            let id = ActionId::new(action_name, Some(owner.clone())).next_version(&mut self.action_versions);
+            self.actions.insert(&id, &action);
+            insert_into_account_set(&mut self.owner_actions, &id.owner, "owner-actions", &id);
+            ids.push(id);
         }
+        self.charge_storage_deposit(&owner, storage_before);
+        MallocEvent::ActionRegister(ids).emit();
     }
 
-    fn register_construction(&mut self, construction_name: String, construction: Construction) {
-        self.constructions
-            .insert(&ConstructionId::new(construction_name, None), &construction);
+    /// Register a construction under the caller's account.
+    /// The construction's actions must already be registered and its topology must be well formed.
+    /// Action ids without a version are pinned to the latest version of the action, so the construction
+    /// keeps calling the same actions when new versions of them are registered.
+    /// The attached deposit must cover the storage the construction uses, the rest of it is refunded
+    #[payable]
+    fn register_construction(&mut self, construction_name: String, mut construction: Construction) {
+        let storage_before = env::storage_usage();
+        let owner = env::predecessor_account_id();
+        self.pin_construction_actions(&mut construction)
+            .unwrap_or_else(|e| panic!("{}", e));
+        self.validate_construction(&construction)
+            .unwrap_or_else(|e| panic!("{}", e));
+        let id = ConstructionId::new(construction_name, Some(owner.clone()))
+            .next_version(&mut self.construction_versions);
+        self.constructions.insert(&id, &construction);
+        self.incr_action_references(&construction);
+        insert_into_account_set(
+            &mut self.owner_constructions,
+            &id.owner,
+            "owner-constructions",
+            &id,
+        );
+        self.charge_storage_deposit(&owner, storage_before);
+        MallocEvent::ConstructionRegister(vec![id]).emit();
     }
 
+    /// Start a construction call. The initial actions, initial splits and next actions topology default
+    /// to the ones registered with the construction, but any of them can be overridden for this call.
+    /// If the construction id has no version, the call is pinned to the construction's latest version.
+    /// The construction call id cannot be one which a live or finished construction call already uses
+    #[allow(clippy::too_many_arguments)]
     fn init_construction(
         &mut self,
         construction_call_id: ConstructionCallId,
         construction_id: ConstructionId,
         amount: U128,
-        initial_action_indices: Vec<u64>,
-        initial_splits: VectorWrapper<U128>,
-        next_actions_indices: NextActionsIndicesForConstruction,
-        next_actions_splits: NextActionsSplitsForConstruction,
+        initial_action_indices: Option<Vec<u64>>,
+        initial_splits: Option<SplitSpec>,
+        next_actions_indices: Option<NextActionsIndicesForConstruction>,
+        next_actions_splits: Option<NextActionsSplitsForConstruction>,
     ) {
+        self.assert_not_paused();
         let caller = env::predecessor_account_id();
 
         let construction = self
             .get_construction(&construction_id)
-            .unwrap_or_else(|e| panic!(e));
+            .unwrap_or_else(|e| panic!("{}", e));
 
         let construction_call = ConstructionCall::new(
             self,
@@ -139,16 +272,48 @@ impl CoreFunctionality for Contract {
             next_actions_indices,
             next_actions_splits,
         )
-        .unwrap_or_else(|e| panic!(e));
+        .unwrap_or_else(|e| panic!("{}", e));
 
+        self.incr_live_construction_calls(&construction_call.construction_id);
+        self.add_caller_construction_call(&construction_call.caller, &construction_call_id);
         self.construction_calls
             .insert(&construction_call_id, &construction_call);
+        MallocEvent::ConstructionCallInit(vec![ConstructionCallInitData {
+            construction_call_id: construction_call_id.clone(),
+            construction_id: construction_call.construction_id,
+            caller: construction_call.caller,
+            amount,
+        }])
+        .emit();
+        // Routes are resolved as soon as their input arrives, which may leave nothing to run
+        self.try_collect_construction_call(&construction_call_id);
     }
 
     fn process_next_action_call(&mut self, construction_call_id: ConstructionCallId) {
-        self._run_step(construction_call_id);
+        self.assert_not_paused();
+        // The failed action call is recorded, so the error is logged rather than rolled back
+        if let Err(e) = self._run_step(construction_call_id) {
+            log!("The action call could not be run: {}", e);
+        }
         log!("Gas used: {}", env::used_gas());
     }
+
+    /// Run the construction call without needing a transaction per action call.
+    /// As many action calls as fit in the prepaid gas are started, and each of their callbacks
+    /// schedules the next action calls with the gas left over, until the stack is empty or the gas is used up.
+    /// If the gas is used up, run_construction_call can be called again to continue
+    fn run_construction_call(&mut self, construction_call_id: ConstructionCallId) {
+        self.assert_not_paused();
+        let mut construction_call = self.get_construction_call_unchecked(&construction_call_id);
+        construction_call.is_self_driving = true;
+        self.construction_calls
+            .insert(&construction_call_id, &construction_call);
+
+        match self._run_steps(construction_call_id, Self::get_self_drive_gas_budget()) {
+            Ok(steps) => log!("Started {} action calls. Gas used: {}", steps, env::used_gas()),
+            Err(e) => log!("Stopped running the construction call: {}", e),
+        }
+    }
 }
 
 #[near_bindgen]
@@ -159,8 +324,98 @@ impl Contract {
 
     pub fn get_construction_call_unchecked(&self, id: &ConstructionCallId) -> ConstructionCall {
         self.construction_calls
-            .get(&id)
-            .unwrap_or_else(|| panic!(panic_errors::CONSTRUCTION_NOT_FOUND))
+            .get(id)
+            .unwrap_or_else(|| {
+                panic!(
+                    "{}",
+                    MallocError::ConstructionCallNotFound {
+                        construction_call_id: id.clone(),
+                    }
+                )
+            })
+    }
+
+    /// Get the total gas and the gas per action call needed to run a construction with the given inputs.
+    /// Inputs which are not supplied default to the construction's registered topology.
+    /// The inputs are plain vectors rather than VectorWrappers, which would write to storage and fail in a view call.
+    /// The total can be used as the prepaid gas for run_construction_call
+    pub fn get_construction_gas_estimate(
+        &self,
+        construction_id: ConstructionId,
+        amount: U128,
+        initial_action_indices: Option<Vec<u64>>,
+        initial_splits: Option<SplitSpec>,
+        next_actions_indices: Option<Vec<Vec<Vec<u64>>>>,
+    ) -> ConstructionGasEstimate {
+        self.estimate_construction_gas(
+            &construction_id,
+            amount.0,
+            initial_action_indices,
+            initial_splits,
+            next_actions_indices,
+        )
+        .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Get the summary of a construction call which finished and was garbage collected
+    pub fn get_construction_call_summary(
+        &self,
+        id: ConstructionCallId,
+    ) -> Option<ConstructionCallSummary> {
+        self.construction_call_summaries.get(&id)
+    }
+
+    /// Get the status of a construction call along with the progress of each of its action calls.
+    /// Returns None if the construction call does not exist or was garbage collected, in which case
+    /// get_construction_call_summary can be used instead
+    pub fn get_construction_call_status(
+        &self,
+        id: ConstructionCallId,
+    ) -> Option<ConstructionCallStatus> {
+        self.get_construction_call_status_internal(&id)
+    }
+
+    /// List the construction calls started by the caller which were not garbage collected yet
+    pub fn get_active_construction_calls(
+        &self,
+        caller: AccountId,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+    ) -> Vec<ConstructionCallId> {
+        paginate_account_set(&self.caller_construction_calls, &caller, from_index, limit)
+    }
+
+    /// List the constructions registered by the owner, with each version of a construction listed separately
+    pub fn get_constructions_by_owner(
+        &self,
+        owner: AccountId,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+    ) -> Vec<ConstructionInfo> {
+        paginate_account_set(&self.owner_constructions, &owner, from_index, limit)
+            .iter()
+            .filter_map(|id| self.get_construction_info(id))
+            .collect()
+    }
+
+    /// List the actions registered by the owner, with each version of an action listed separately
+    pub fn get_actions_by_owner(
+        &self,
+        owner: AccountId,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+    ) -> Vec<ActionInfo> {
+        paginate_account_set(&self.owner_actions, &owner, from_index, limit)
+            .into_iter()
+            .filter_map(|id| {
+                let action = self.actions.get(&id)?;
+                Some(ActionInfo {
+                    token_id: action.get_token_id(),
+                    id,
+                    action,
+                })
+            })
+            .collect()
     }
 }
 
@@ -171,8 +426,414 @@ impl Contract {
     }
 }
 
+impl Contract {
+    /// Withdraw the caller's balance of the token at the malloc call back to the caller's balance in this contract,
+    /// up to amount. The balance is queried first, see resolve_reclaim_balance
+    fn reclaim_from_malloc_call(
+        &self,
+        malloc_call_id: AccountId,
+        caller: AccountId,
+        token_id: AccountId,
+        amount: u128,
+    ) {
+        Promise::new(malloc_call_id.clone())
+            .function_call(
+                b"get_ft_balance".to_vec(),
+                json!({ "account_id": caller, "token_id": token_id })
+                    .to_string()
+                    .into_bytes(),
+                0,
+                gas::GET_FT_BALANCE_GAS,
+            )
+            .then(Promise::new(env::current_account_id()).function_call(
+                b"resolve_reclaim_balance".to_vec(),
+                json!({
+                    "malloc_call_id": malloc_call_id,
+                    "caller": caller,
+                    "token_id": token_id,
+                    "amount": U128(amount)
+                })
+                .to_string()
+                .into_bytes(),
+                0,
+                gas::RESOLVE_RECLAIM_GAS,
+            ));
+    }
+
+    /// Reclaim the tokens which a failed action call sent to its malloc call. The transfer into the malloc call
+    /// goes through before the malloc call is called, so no resolve step credits them back when the call fails
+    fn reclaim_failed_action_call(
+        &self,
+        construction_call_id: &ConstructionCallId,
+        action_call: &ActionCall,
+        caller: &AccountId,
+    ) {
+        let construction_call = self.get_construction_call_unchecked(construction_call_id);
+        let action = match self
+            .get_construction(&construction_call.construction_id)
+            .and_then(|construction| self.get_action_call_action(&construction, action_call))
+        {
+            Ok(action) => action,
+            Err(e) => {
+                log!("Cannot reclaim the tokens of the failed action call: {}", e);
+                return;
+            }
+        };
+        if !action.reclaims_on_failure(action_call) {
+            return;
+        }
+        if let Some(malloc_call_id) = action.get_malloc_call_id() {
+            self.reclaim_from_malloc_call(
+                malloc_call_id,
+                caller.clone(),
+                action.get_token_id(),
+                action_call.amount,
+            );
+        }
+    }
+
+    /// Resolve a NEAR transfer, wrap or unwrap which spent amount of spent_token_id from the account's balance.
+    /// On success, the amount of received_token_id is credited to the account. On failure, the spent amount is credited back
+    /// @returns the amount which the action produced
+    fn resolve_near_action(
+        &mut self,
+        account_id: &AccountId,
+        spent_token_id: &AccountId,
+        received_token_id: Option<&AccountId>,
+        amount: U128,
+    ) -> U128 {
+        if !utils::is_promise_success() {
+            log!("The action failed, crediting back {} of {}", amount.0, spent_token_id);
+            self.balances
+                .increase_balance(account_id, spent_token_id, amount.0);
+            return U128(0);
+        }
+        if let Some(received_token_id) = received_token_id {
+            self.balances
+                .increase_balance(account_id, received_token_id, amount.0);
+        }
+        amount
+    }
+
+    fn assert_owner(&self) {
+        let caller = env::predecessor_account_id();
+        if caller != self.owner_id {
+            panic!(
+                "{}",
+                MallocError::CallerIsNotContractOwner {
+                    caller,
+                    owner: self.owner_id.clone()
+                }
+            );
+        }
+    }
+
+    /// Panic if the contract is paused
+    fn assert_not_paused(&self) {
+        if self.is_paused {
+            panic!("{}", MallocError::ContractPaused);
+        }
+    }
+
+    /// The deposit guard of ft_on_transfer. While paused, deposits which the sender credits to themselves are rejected.
+    /// Tokens which are sent for another account, such as the tokens which malloc calls return or which are reclaimed
+    /// from them, are still accepted so that the action calls in flight when the contract was paused can finish
+    fn assert_deposit_allowed(&self, sender_id: &str, msg: &str) {
+        let credits_other_account = serde_json::from_str::<OnTransferOpts>(msg)
+            .map(|opts| opts.sender_id != sender_id)
+            .unwrap_or(false);
+        if !credits_other_account {
+            self.assert_not_paused();
+        }
+    }
+
+    /// Charge the owner for the storage used since storage_before out of the attached deposit,
+    /// and refund what is left of the deposit
+    fn charge_storage_deposit(&mut self, owner: &AccountId, storage_before: StorageUsage) {
+        // The owner's deposit entry is written first so that its own storage is charged too
+        let deposit = self.storage_deposits.get(owner).unwrap_or(0);
+        self.storage_deposits.insert(owner, &deposit);
+        let storage_used = env::storage_usage().saturating_sub(storage_before);
+        let required = Balance::from(storage_used) * env::storage_byte_cost();
+        let attached = env::attached_deposit();
+        if attached < required {
+            panic!(
+                "{}",
+                MallocError::InsufficientStorageDeposit {
+                    required: U128(required),
+                    attached: U128(attached),
+                }
+            );
+        }
+        self.storage_deposits.insert(owner, &(deposit + required));
+        if attached > required {
+            Promise::new(env::predecessor_account_id()).transfer(attached - required);
+        }
+    }
+
+    /// Take the stake of the freed storage out of the owner's storage deposits. Only what the owner deposited
+    /// can be refunded, so storage which the contract paid for is never paid out
+    /// @returns the amount to refund to the owner
+    fn release_storage_deposit(&mut self, owner: &AccountId, storage_freed: StorageUsage) -> Balance {
+        let deposit = self.storage_deposits.get(owner).unwrap_or(0);
+        let refund = std::cmp::min(Balance::from(storage_freed) * env::storage_byte_cost(), deposit);
+        if deposit == refund {
+            self.storage_deposits.remove(owner);
+        } else {
+            self.storage_deposits.insert(owner, &(deposit - refund));
+        }
+        refund
+    }
+
+    /// The gas which can be spent on the next steps of a self-driving construction call
+    fn get_self_drive_gas_budget() -> Gas {
+        (env::prepaid_gas() - env::used_gas()).saturating_sub(gas::SELF_DRIVE_RESERVE_GAS)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Pause the contract in an incident. Only the owner can pause
+    pub fn pause(&mut self) {
+        self.assert_owner();
+        self.is_paused = true;
+        log!("The contract was paused by {}", self.owner_id);
+    }
+
+    pub fn unpause(&mut self) {
+        self.assert_owner();
+        self.is_paused = false;
+        log!("The contract was unpaused by {}", self.owner_id);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    /// Hand the ownership of the contract over to new_owner_id. Only the current owner can do this
+    pub fn set_owner(&mut self, new_owner_id: ValidAccountId) {
+        self.assert_owner();
+        log!("The owner of the contract changed from {} to {}", self.owner_id, new_owner_id);
+        self.owner_id = new_owner_id.into();
+    }
+
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Pull the malloc call's metadata and cache it in the malloc call registry. Only the owner can register
+    /// a malloc call or refresh its cached metadata, as the registry is stored at the contract's expense.
+    /// Newly registered malloc calls are unverified
+    pub fn register_malloc_call(&mut self, malloc_call_id: ValidAccountId) -> Promise {
+        self.assert_owner();
+        Promise::new(malloc_call_id.to_string())
+            .function_call(b"metadata".to_vec(), b"{}".to_vec(), 0, gas::MALLOC_CALL_METADATA_GAS)
+            .then(Promise::new(env::current_account_id()).function_call(
+                b"resolve_register_malloc_call".to_vec(),
+                json!({ "malloc_call_id": malloc_call_id })
+                    .to_string()
+                    .into_bytes(),
+                0,
+                gas::RESOLVE_REGISTER_MALLOC_CALL_GAS,
+            ))
+    }
+
+    #[private]
+    pub fn resolve_register_malloc_call(
+        &mut self,
+        malloc_call_id: AccountId,
+    ) -> MallocCallRegistryEntry {
+        let metadata = utils::promise_result_as_success()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_else(|| panic!("{}", MallocError::MallocCallMetadataNotParsable));
+        let entry = self.cache_malloc_call_metadata(&malloc_call_id, metadata);
+        MallocEvent::MallocCallRegister(vec![entry.clone()]).emit();
+        entry
+    }
+
+    /// Mark a registered malloc call as verified or unverified. Only the owner can do this
+    pub fn set_malloc_call_trust_level(
+        &mut self,
+        malloc_call_id: ValidAccountId,
+        trust_level: TrustLevel,
+    ) {
+        self.assert_owner();
+        let mut entry = self
+            .get_registered_malloc_call(malloc_call_id.as_ref())
+            .unwrap_or_else(|e| panic!("{}", e));
+        entry.trust_level = trust_level;
+        self.malloc_call_registry.insert(malloc_call_id.as_ref(), &entry);
+        MallocEvent::MallocCallRegister(vec![entry]).emit();
+    }
+
+    /// Remove a malloc call from the registry. The MallocCall actions which call it refuse to run
+    /// until it is registered again. Only the owner can do this
+    pub fn remove_malloc_call(&mut self, malloc_call_id: ValidAccountId) {
+        self.assert_owner();
+        self.malloc_call_registry.remove(malloc_call_id.as_ref());
+        log!("The malloc call {} was removed from the registry", malloc_call_id);
+    }
+
+    pub fn get_malloc_call_registry_entry(
+        &self,
+        malloc_call_id: ValidAccountId,
+    ) -> Option<MallocCallRegistryEntry> {
+        self.malloc_call_registry.get(malloc_call_id.as_ref())
+    }
+
+    /// List the registered malloc calls along with their cached metadata and trust levels
+    pub fn get_registered_malloc_calls(
+        &self,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+    ) -> Vec<MallocCallRegistryEntry> {
+        self.malloc_call_registry
+            .values()
+            .skip(from_index.map(u64::from).unwrap_or(0) as usize)
+            .take(limit.map(u64::from).unwrap_or(u64::MAX) as usize)
+            .collect()
+    }
+}
+
 #[near_bindgen]
 impl Contract {
+    /// Credit the attached NEAR to the internal NEAR balance of account_id, or of the caller if it is not given
+    #[payable]
+    pub fn deposit_near(&mut self, account_id: Option<ValidAccountId>) {
+        self.assert_not_paused();
+        let amount = env::attached_deposit();
+        assert!(amount > 0, "{}", MallocError::NoNearAttached);
+        let account_id = account_id
+            .map(|a| a.into())
+            .unwrap_or_else(env::predecessor_account_id);
+        self.balances
+            .deposit(&account_id, &NEAR_TOKEN_ID.to_string(), amount);
+    }
+
+    /// Send NEAR from the caller's internal NEAR balance to the recipient, or to the caller if it is not given.
+    /// If the transfer fails, the NEAR is credited back. Withdrawals stay open while the contract is paused
+    #[payable]
+    pub fn withdraw_near(&mut self, amount: U128, recipient: Option<ValidAccountId>) -> Promise {
+        assert_one_yocto();
+        let caller = env::predecessor_account_id();
+        let token_id = NEAR_TOKEN_ID.to_string();
+        self.check_caller_balance(&caller, &token_id, amount.0)
+            .unwrap_or_else(|e| panic!("{}", e));
+        self.balances.subtract_balance(&caller, &token_id, amount.0);
+        let recipient = recipient
+            .map(|a| a.into())
+            .unwrap_or_else(|| caller.clone());
+        Promise::new(recipient)
+            .transfer(amount.0)
+            .then(Promise::new(env::current_account_id()).function_call(
+                b"resolve_near_transfer".to_vec(),
+                json!({ "account_id": caller, "amount": amount })
+                    .to_string()
+                    .into_bytes(),
+                0,
+                gas::RESOLVE_NEAR_GAS,
+            ))
+    }
+
+    /// Get the account's internal NEAR balance
+    pub fn get_near_balance(&self, account_id: ValidAccountId) -> U128 {
+        U128(
+            self.balances
+                .get_ft_balance(account_id.as_ref(), &NEAR_TOKEN_ID.to_string()),
+        )
+    }
+
+    /// Set the wNEAR contract which the WrapNear and UnwrapNear actions go through. Only the owner can do this.
+    /// The malloc contract must be registered with the wNEAR contract's storage management
+    pub fn set_wnear_contract_id(&mut self, wnear_contract_id: ValidAccountId) {
+        self.assert_owner();
+        self.wnear_contract_id = Some(wnear_contract_id.into());
+    }
+
+    pub fn get_wnear_contract_id(&self) -> Option<AccountId> {
+        self.wnear_contract_id.clone()
+    }
+
+    /// Resolve a NEAR transfer. If the transfer failed, the NEAR is credited back to the account
+    /// @returns the amount which was sent
+    #[private]
+    pub fn resolve_near_transfer(&mut self, account_id: AccountId, amount: U128) -> U128 {
+        self.resolve_near_action(&account_id, &NEAR_TOKEN_ID.to_string(), None, amount)
+    }
+
+    /// Resolve wrapping NEAR. The wNEAR is credited to the account, or the NEAR is credited back if the deposit failed
+    /// @returns the amount of wNEAR which was received
+    #[private]
+    pub fn resolve_wrap_near(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        wnear_contract_id: AccountId,
+    ) -> U128 {
+        self.resolve_near_action(
+            &account_id,
+            &NEAR_TOKEN_ID.to_string(),
+            Some(&wnear_contract_id),
+            amount,
+        )
+    }
+
+    /// Resolve unwrapping wNEAR. The NEAR is credited to the account, or the wNEAR is credited back if the withdrawal failed
+    /// @returns the amount of NEAR which was received
+    #[private]
+    pub fn resolve_unwrap_near(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        wnear_contract_id: AccountId,
+    ) -> U128 {
+        self.resolve_near_action(
+            &account_id,
+            &wnear_contract_id,
+            Some(&NEAR_TOKEN_ID.to_string()),
+            amount,
+        )
+    }
+
+    /// Withdraw the caller's balance queried from the malloc call back to the caller's balance in this contract
+    /// @param amount - the amount which the timed out action call sent, which is the most that is withdrawn
+    #[private]
+    pub fn resolve_reclaim_balance(
+        &mut self,
+        malloc_call_id: AccountId,
+        caller: AccountId,
+        token_id: AccountId,
+        amount: U128,
+    ) {
+        let balance = utils::promise_result_as_success()
+            .and_then(|bytes| serde_json::from_slice::<U128>(&bytes).ok())
+            .unwrap_or_else(|| panic!("{}", MallocError::ReclaimBalanceNotParsable));
+        let balance = U128(balance.0.min(amount.0));
+        if balance.0 == 0 {
+            log!("Nothing of {} to reclaim from {}", token_id, malloc_call_id);
+            return;
+        }
+        let args = FungibleTokenBalances::get_withdraw_to_args(
+            &caller,
+            balance,
+            &token_id,
+            &env::current_account_id(),
+            None,
+            TransferType::TransferCallMalloc(),
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+        log!("Reclaiming {} of {} from {} for {}", balance.0, token_id, malloc_call_id, caller);
+        Promise::new(malloc_call_id).function_call(
+            b"withdraw_to".to_vec(),
+            args.into_bytes(),
+            1,
+            MALLOC_CALL_CORE_GAS_FOR_WITHDRAW_TO + gas::CROSS_CONTRACT_BASE_GAS,
+        );
+    }
+
     #[private]
     pub fn handle_action_callback(
         &mut self,
@@ -181,65 +842,137 @@ impl Contract {
         caller: AccountId,
         token_return_id: Option<ValidAccountId>,
     ) -> Option<u64> {
-        // TODO: err handle!!
-        let mut action_call = self.action_calls.get(&action_call_id).unwrap();
-        let ret_bytes = match utils::promise_result_as_success() {
-            None => panic!("TODO:"),
-            Some(bytes) => bytes,
+        // The action call may have timed out and been collected before its callback arrived
+        let mut action_call = match self.action_calls.get(&action_call_id) {
+            Some(action_call) if action_call.is_executing() => action_call,
+            _ => {
+                log!("Action call {} is no longer executing, ignoring its callback", action_call_id);
+                return None;
+            }
+        };
+        match utils::promise_result_as_success() {
+            // A failed transfer was already credited back by its resolve step, see handle_action_failure_internal
+            None => {
+                action_call.handle_action_failure_internal(
+                    self,
+                    construction_call_id.clone(),
+                    MallocError::ActionCallFailed,
+                );
+                self.reclaim_failed_action_call(&construction_call_id, &action_call, &caller);
+            }
+            Some(ret_bytes) => {
+                let res = ActionCall::get_results_from_returned_bytes(ret_bytes, token_return_id)
+                    .and_then(|results| {
+                        action_call.handle_action_callback_internal(
+                            self,
+                            construction_call_id.clone(),
+                            results,
+                        )
+                    });
+                if let Err(e) = res {
+                    action_call.handle_action_failure_internal(
+                        self,
+                        construction_call_id.clone(),
+                        e,
+                    );
+                }
+            }
         };
-        let results: Vec<ReturnItem> =
-            ActionCall::get_results_from_returned_bytes(ret_bytes, token_return_id).unwrap();
-        action_call.handle_action_callback_internal(self, construction_call_id, caller, results)
+        self.action_calls.insert(&action_call_id, &action_call);
+        MallocEvent::emit_action_call_finished(&construction_call_id, action_call_id, &action_call);
+        if self.try_collect_construction_call(&construction_call_id) {
+            return None;
+        }
+
+        // While paused, the callbacks still record the results but no new action calls are started
+        let construction_call = self.get_construction_call_unchecked(&construction_call_id);
+        if construction_call.is_self_driving
+            && !construction_call.next_action_calls.is_empty()
+            && !self.is_paused
+        {
+            if let Err(e) = self._run_steps(construction_call_id, Self::get_self_drive_gas_budget()) {
+                log!("Stopped running the construction call: {}", e);
+            }
+        }
+        None
     }
 }
 
 #[near_bindgen]
 impl Contract {
+    /// @param owner_id - the account which can pause the contract, the predecessor if it is not given
     #[init]
-    pub fn new() -> Self {
+    pub fn new(owner_id: Option<ValidAccountId>) -> Self {
+        migrations::write_state_version(migrations::CURRENT_STATE_VERSION);
         Contract {
             balances: FungibleTokenBalances::new("malloc-ft".as_bytes()),
             action_calls: UnorderedMap::<ActionCallId, ActionCall>::new("actioncalls".as_bytes()),
             actions: UnorderedMap::new("actions".as_bytes()),
+            action_versions: LookupMap::new("action-versions".as_bytes()),
+            owner_actions: LookupMap::new("owner-actions".as_bytes()),
+            action_references: LookupMap::new("action-references".as_bytes()),
             next_action_call_id: 0,
             constructions: UnorderedMap::new("constructions".as_bytes()),
+            construction_versions: LookupMap::new("construction-versions".as_bytes()),
+            owner_constructions: LookupMap::new("owner-constructions".as_bytes()),
+            live_construction_calls: LookupMap::new("live-construction-calls".as_bytes()),
             construction_calls: UnorderedMap::new("construction-call-stack".as_bytes()),
+            caller_construction_calls: LookupMap::new("caller-construction-calls".as_bytes()),
+            construction_call_summaries: LookupMap::new("construction-call-summaries".as_bytes()),
             malloc_contract_id: env::current_account_id(),
+            wnear_contract_id: None,
+            malloc_call_registry: UnorderedMap::new("malloc-call-registry".as_bytes()),
+            storage_deposits: LookupMap::new("storage-deposits".as_bytes()),
+            owner_id: owner_id
+                .map(|a| a.into())
+                .unwrap_or_else(env::predecessor_account_id),
+            is_paused: false,
         }
     }
+
+    /// Upgrade the stored state to the layout of the deployed code. Only the owner can call this,
+    /// usually in the same transaction which deploys the new code
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let contract = migrations::migrate_state().unwrap_or_else(|e| panic!("{}", e));
+        contract.assert_owner();
+        migrations::write_state_version(migrations::CURRENT_STATE_VERSION);
+        contract
+    }
+
+    /// Get the layout version of the stored state
+    pub fn get_state_version(&self) -> u32 {
+        migrations::read_state_version()
+    }
 }
 
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
-    const INIT_ACCOUNT_BAL: u128 = 10_000;
-
     use std::convert::TryFrom;
 
+    use crate::action::ActionCallStatus;
     use crate::actions::ft_calls::FtTransferCallToMallocCall;
+    use crate::construction::SchedulingMode;
+    use crate::vector_wrapper::VectorWrapper;
+    use crate::actions::ActionFunctions;
     use crate::malloc_utils::GenericId;
+    use crate::test_utils::tests::{
+        get_context, get_deposit_context, init_json_construction_call, register_json_actions,
+        register_json_construction, swap_action,
+    };
 
     use super::*;
     use near_sdk::json_types::ValidAccountId;
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::serde_json::Value;
+    use near_sdk::test_utils::accounts;
     use near_sdk::testing_env;
-    use near_sdk::MockedBlockchain;
-
-    // mock the context for testing, notice "signer_account_id" that was accessed above from env::
-    fn get_context(predecessor_account_id: ValidAccountId) -> VMContextBuilder {
-        let mut builder = VMContextBuilder::new();
-        builder
-            .current_account_id(accounts(0))
-            .signer_account_id(predecessor_account_id.clone())
-            .predecessor_account_id(predecessor_account_id)
-            .account_balance(INIT_ACCOUNT_BAL);
-        builder
-    }
+    use near_sdk::{MockedBlockchain, PromiseResult, RuntimeFeesConfig, VMConfig};
 
     #[test]
     fn test_register_construction() {
-        let mut context = get_context(accounts(0));
+        let mut context = get_deposit_context(accounts(0));
         testing_env!(context.build());
-        let mut contract = Contract::new();
+        let mut contract = Contract::new(None);
         let token_id1 = ValidAccountId::try_from("wrapp.localnet".to_string()).unwrap();
         let token_id2 = ValidAccountId::try_from("wrappppp.localnet".to_string()).unwrap();
 
@@ -262,28 +995,53 @@ mod tests {
                     GenericId {
                         name: "action1".to_string(),
                         owner: accounts(0).to_string(),
+                        version: None,
                     },
                     GenericId {
                         name: "action2".to_string(),
                         owner: accounts(0).to_string(),
+                        version: None,
                     },
                 ],
                 "my prefix".as_bytes(),
             ),
+            initial_action_indices: serde_json::from_str("[0, 1]").unwrap(),
+            initial_splits: serde_json::from_str("[\"1\", \"1\"]").unwrap(),
+            next_actions_indices: serde_json::from_str("[[[]], [[]]]").unwrap(),
+            next_actions_splits: serde_json::from_str("[[[]], [[]]]").unwrap(),
+            scheduling: None,
+            action_call_timeout_blocks: None,
+            next_actions_token_ids: None,
+            min_returned_amounts: None,
         };
         contract.register_construction(construction_name.clone(), construction.clone());
         let construction_got = contract.get_construction(&GenericId {
             name: construction_name,
             owner: accounts(0).to_string(),
+            version: None,
         });
-        assert_eq!(construction_got.unwrap(), construction);
+        let construction_got = construction_got.unwrap();
+        // The actions are pinned to their latest version
+        assert_eq!(
+            construction_got.actions.0.to_vec(),
+            construction
+                .actions
+                .0
+                .iter()
+                .map(|action_id| action_id.with_version(0))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            construction_got.next_actions_indices,
+            construction.next_actions_indices
+        );
     }
 
     #[test]
     fn test_init_construction() {
-        let mut context = get_context(accounts(0));
+        let mut context = get_deposit_context(accounts(0));
         testing_env!(context.build());
-        let mut contract = Contract::new();
+        let mut contract = Contract::new(None);
         let token_id1 = ValidAccountId::try_from("wrapp.localnet".to_string()).unwrap();
         let token_id2 = ValidAccountId::try_from("wrappppp.localnet".to_string()).unwrap();
 
@@ -306,14 +1064,24 @@ mod tests {
                     GenericId {
                         name: "action1".to_string(),
                         owner: accounts(0).to_string(),
+                        version: None,
                     },
                     GenericId {
                         name: "action2".to_string(),
                         owner: accounts(0).to_string(),
+                        version: None,
                     },
                 ],
                 "my prefix".as_bytes(),
             ),
+            initial_action_indices: serde_json::from_str("[0, 1]").unwrap(),
+            initial_splits: serde_json::from_str("[\"1\", \"1\"]").unwrap(),
+            next_actions_indices: serde_json::from_str("[[[]], [[]]]").unwrap(),
+            next_actions_splits: serde_json::from_str("[[[]], [[]]]").unwrap(),
+            scheduling: None,
+            action_call_timeout_blocks: None,
+            next_actions_token_ids: None,
+            min_returned_amounts: None,
         };
         contract.register_construction(construction_name.clone(), construction.clone());
 
@@ -321,10 +1089,11 @@ mod tests {
         let construction_id = GenericId {
             name: construction_name.clone(),
             owner: accounts(0).into(),
+            version: None,
         };
         let amount = U128(100);
         let initial_action_indices = vec![0, 1];
-        let initial_splits: VectorWrapper<U128> = serde_json::from_str("[\"1\", \"2\"]").unwrap();
+        let initial_splits: SplitSpec = serde_json::from_str("[\"1\", \"2\"]").unwrap();
         let next_actions_indices: NextActionsIndicesForConstruction =
             serde_json::from_str("[[[]], [[]]]").unwrap();
         let next_actions_splits: NextActionsSplitsForConstruction =
@@ -333,23 +1102,23 @@ mod tests {
         contract.init_construction(
             construction_call_id.clone(),
             construction_id.clone(),
-            amount.clone(),
-            initial_action_indices.clone(),
-            initial_splits.clone(),
-            next_actions_indices.clone(),
-            next_actions_splits.clone(),
+            amount,
+            Some(initial_action_indices.clone()),
+            Some(initial_splits.clone()),
+            Some(next_actions_indices.clone()),
+            Some(next_actions_splits.clone()),
         );
 
         let construction_call = ConstructionCall::new(
-            &mut &mut contract,
+            &mut contract,
             accounts(0).to_string(),
             construction_id,
             &"aaaaaaa".to_string(), // Have a new construction call id to avoid re-registering
             amount.0,
-            initial_action_indices,
-            initial_splits,
-            next_actions_indices,
-            next_actions_splits,
+            Some(initial_action_indices),
+            Some(initial_splits),
+            Some(next_actions_indices),
+            Some(next_actions_splits),
         )
         .unwrap();
         let registered = contract.get_construction_call_unchecked(&construction_call_id);
@@ -361,24 +1130,54 @@ mod tests {
             &construction_call.construction_id
         );
         assert_eq!(
-            &registered.next_action_calls_stack,
-            &construction_call.next_action_calls_stack
+            &registered.next_action_calls,
+            &construction_call.next_action_calls
         );
         assert_eq!(
-            &registered.next_actions_indices_in_construction,
-            &construction_call.next_actions_indices_in_construction
+            &registered.next_actions_indices_override,
+            &construction_call.next_actions_indices_override
         );
         assert_eq!(
-            &registered.next_actions_splits,
-            &construction_call.next_actions_splits
+            &registered.next_actions_splits_override,
+            &construction_call.next_actions_splits_override
+        );
+    }
+
+    #[test]
+    fn test_init_construction_with_registered_topology() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let construction_id =
+            register_json_construction(&mut contract, "registered", test_actions(), test_construction());
+
+        contract.init_construction(
+            "mycall".to_string(),
+            construction_id,
+            U128(100),
+            None,
+            None,
+            None,
+            None,
         );
+        let registered = contract.get_construction_call_unchecked(&"mycall".to_string());
+        assert_eq!(registered.next_action_calls.len(), 2);
+        assert!(registered.next_actions_indices_override.is_none());
+        assert!(registered.next_actions_splits_override.is_none());
+        let amounts: Vec<u128> = registered
+            .action_calls
+            .0
+            .iter()
+            .map(|id| contract.get_action_call_unchecked(U64(id)).amount)
+            .collect();
+        assert_eq!(amounts, vec![50, 50]);
     }
 
     #[test]
     fn test_register_actions() {
-        let mut context = get_context(accounts(0));
+        let mut context = get_deposit_context(accounts(0));
         testing_env!(context.build());
-        let mut contract = Contract::new();
+        let mut contract = Contract::new(None);
 
         let token_id1 = ValidAccountId::try_from("wrapp.localnet".to_string()).unwrap();
         let token_id2 = ValidAccountId::try_from("wrappppp.localnet".to_string()).unwrap();
@@ -395,18 +1194,1653 @@ mod tests {
             vec!["action1".to_string(), "action2".to_string()],
             vec![action1_prereigster.clone(), action2_prereigster.clone()],
         );
-        let action1 = contract.actions.get(&GenericId {
+        let action1 = contract.get_action(&GenericId {
             owner: accounts(0).to_string(),
             name: "action1".to_string(),
+            version: None,
         });
-        let action2 = contract.actions.get(&GenericId {
+        let action2 = contract.get_action(&GenericId {
             owner: accounts(0).to_string(),
             name: "action2".to_string(),
+            version: None,
         });
-        assert!(action1.is_some());
-        assert!(action2.is_some());
+        assert!(action1.is_ok());
+        assert!(action2.is_ok());
 
         assert_eq!(action1.unwrap(), action1_prereigster);
         assert_eq!(action2.unwrap(), action2_prereigster);
     }
+    /// Get two actions which send wrapp.localnet and wrappppp.localnet to the malloc call accounts(2)
+    fn test_actions() -> Vec<(&'static str, Action)> {
+        let action2 = Action::FtTransferCallToMallocCall(FtTransferCallToMallocCall {
+            malloc_call_id: accounts(2),
+            token_id: ValidAccountId::try_from("wrappppp.localnet").unwrap(),
+        });
+        vec![("action1", swap_action()), ("action2", action2)]
+    }
+
+    /// Get a construction which splits the amount evenly between both test actions
+    fn test_construction() -> Value {
+        json!({
+            "actions": [
+                { "owner": accounts(0), "name": "action1" },
+                { "owner": accounts(0), "name": "action2" }
+            ],
+            "initial_action_indices": [0, 1],
+            "initial_splits": ["1", "1"],
+            "next_actions_indices": [[[]], [[]]],
+            "next_actions_splits": [[[]], [[]]]
+        })
+    }
+
+    #[test]
+    fn test_delete_construction() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let construction_id =
+            register_json_construction(&mut contract, "to delete", test_actions(), test_construction());
+
+        contract.delete_construction(construction_id.clone(), Some(true));
+        assert!(contract.get_construction(&construction_id).is_err());
+        assert!(contract
+            .get_action(&GenericId {
+                name: "action1".to_string(),
+                owner: accounts(0).to_string(),
+                version: None,
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_delete_construction_versions() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let construction_id =
+            register_json_construction(&mut contract, "versioned", test_actions(), test_construction());
+        register_json_construction(&mut contract, "versioned", test_actions(), test_construction());
+
+        // Deleting the latest version leaves the name resolving to the version before it
+        contract.delete_construction(construction_id.with_version(1), Some(true));
+        assert_eq!(
+            contract.resolve_construction_id(&construction_id).unwrap(),
+            construction_id.with_version(0)
+        );
+        assert!(contract.get_construction(&construction_id).is_ok());
+        let action_id = GenericId {
+            name: "action1".to_string(),
+            owner: accounts(0).to_string(),
+            version: None,
+        };
+        assert_eq!(
+            contract.resolve_action_id(&action_id).unwrap(),
+            action_id.with_version(0)
+        );
+
+        // Deleting without a version deletes every version
+        register_json_construction(&mut contract, "versioned", test_actions(), test_construction());
+        contract.delete_construction(construction_id.clone(), Some(false));
+        assert!(contract.get_construction(&construction_id.with_version(0)).is_err());
+        assert!(contract.get_construction(&construction_id.with_version(1)).is_err());
+        assert!(contract.resolve_construction_id(&construction_id).is_err());
+        assert!(contract
+            .get_constructions_by_owner(accounts(0).to_string(), None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_delete_construction_keep_actions() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let construction_id =
+            register_json_construction(&mut contract, "to delete", test_actions(), test_construction());
+
+        contract.delete_construction(construction_id.clone(), None);
+        assert!(contract.get_construction(&construction_id).is_err());
+        assert!(contract
+            .get_action(&GenericId {
+                name: "action1".to_string(),
+                owner: accounts(0).to_string(),
+                version: None,
+            })
+            .is_ok());
+    }
+
+    #[test]
+    fn test_delete_construction_keeps_shared_actions() {
+        let context = get_deposit_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let action = Action::FtTransferCallToMallocCall(FtTransferCallToMallocCall {
+            malloc_call_id: accounts(2),
+            token_id: ValidAccountId::try_from("wrapp.localnet").unwrap(),
+        });
+        contract.register_actions(vec!["shared".to_string()], vec![action]);
+        let construction = json!({
+            "actions": [{ "owner": accounts(0), "name": "shared" }],
+            "initial_action_indices": [0],
+            "initial_splits": ["1"],
+            "next_actions_indices": [[[]]],
+            "next_actions_splits": [[[]]]
+        });
+        contract.register_construction("first".to_string(), serde_json::from_value(construction.clone()).unwrap());
+        contract.register_construction("second".to_string(), serde_json::from_value(construction).unwrap());
+        let action_id = GenericId {
+            name: "shared".to_string(),
+            owner: accounts(0).to_string(),
+            version: Some(0),
+        };
+        assert_eq!(contract.get_action_references(&action_id), 2);
+
+        // The second construction still uses the action, so it is kept
+        let construction_id = |name: &str| GenericId {
+            name: name.to_string(),
+            owner: accounts(0).to_string(),
+            version: None,
+        };
+        contract.delete_construction(construction_id("first"), Some(true));
+        assert!(contract.get_action(&action_id).is_ok());
+        assert_eq!(contract.get_action_references(&action_id), 1);
+
+        contract.delete_construction(construction_id("second"), Some(true));
+        assert!(contract.get_action(&action_id).is_err());
+        assert_eq!(contract.get_action_references(&action_id), 0);
+    }
+
+    #[test]
+    fn test_delete_construction_refunds_only_deposits() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let construction_id =
+            register_json_construction(&mut contract, "to delete", test_actions(), test_construction());
+        let deposit = contract.storage_deposits.get(&accounts(0).to_string()).unwrap();
+        assert!(deposit > 0);
+
+        // The refund for the freed storage is capped by what the owner deposited
+        contract.storage_deposits.insert(&accounts(0).to_string(), &1);
+        let balance_before = env::account_balance();
+        contract.delete_construction(construction_id, Some(true));
+        assert!(contract.storage_deposits.get(&accounts(0).to_string()).is_none());
+        assert_eq!(env::account_balance(), balance_before - 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "The attached deposit does not cover the storage used")]
+    fn test_register_without_storage_deposit() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        contract.register_actions(vec!["unpaid".to_string()], vec![swap_action()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The caller does not own the construction")]
+    fn test_delete_construction_not_owner() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let construction_id =
+            register_json_construction(&mut contract, "to delete", test_actions(), test_construction());
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.delete_construction(construction_id, None);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "The construction cannot be deleted while construction calls still reference it"
+    )]
+    fn test_delete_construction_with_live_calls() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let construction_id =
+            register_json_construction(&mut contract, "to delete", test_actions(), test_construction());
+
+        contract.init_construction(
+            "mycall".to_string(),
+            construction_id.clone(),
+            U128(100),
+            None,
+            None,
+            None,
+            None,
+        );
+        contract.delete_construction(construction_id, None);
+    }
+
+    #[test]
+    fn test_construction_call_collected_when_finished() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let construction_id =
+            register_json_construction(&mut contract, "to collect", test_actions(), test_construction());
+
+        // Deposit the tokens used by the first action
+        let token_id = ValidAccountId::try_from("wrapp.localnet".to_string()).unwrap();
+        testing_env!(get_context(token_id.clone()).build());
+        contract.ft_on_transfer(accounts(0).to_string(), "100".to_string(), "".to_string());
+
+        testing_env!(get_context(accounts(0)).build());
+        let construction_call_id = "mycall".to_string();
+        contract.init_construction(
+            construction_call_id.clone(),
+            construction_id.clone(),
+            U128(100),
+            Some(vec![0]),
+            Some(serde_json::from_str("[\"1\"]").unwrap()),
+            None,
+            None,
+        );
+        contract.process_next_action_call(construction_call_id.clone());
+        assert!(contract
+            .construction_call_summaries
+            .get(&construction_call_id)
+            .is_none());
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"100\"".to_vec())]
+        );
+        contract.handle_action_callback(
+            construction_call_id.clone(),
+            0,
+            accounts(0).to_string(),
+            Some(token_id),
+        );
+
+        assert!(contract.construction_calls.get(&construction_call_id).is_none());
+        assert!(contract.action_calls.get(&0).is_none());
+        let summary = contract
+            .get_construction_call_summary(construction_call_id)
+            .unwrap();
+        assert_eq!(summary.number_of_action_calls, 1);
+        assert_eq!(summary.number_of_errors, 0);
+        assert_eq!(contract.get_live_construction_calls(&construction_id), 0);
+        assert!(contract
+            .get_active_construction_calls(accounts(0).to_string(), None, None)
+            .is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "The given construction call id has already been registered: mycall")]
+    fn test_init_construction_with_finished_call_id() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let route: Action = serde_json::from_value(json!({
+            "Route": { "token_id": "wrapp.localnet", "predicates": [{ "AtLeast": "50" }] }
+        }))
+        .unwrap();
+        // The route leaves the amount with the caller, so the construction call finishes right away
+        init_json_construction_call(
+            &mut contract,
+            "mycall",
+            vec![("route", route)],
+            json!({
+                "actions": [{ "owner": accounts(0), "name": "route" }],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[], []]],
+                "next_actions_splits": [[[], []]]
+            }),
+            10,
+        );
+        assert!(contract.get_construction_call_summary("mycall".to_string()).is_some());
+
+        // Reusing the id would overwrite the summary of the finished construction call
+        contract.init_construction(
+            "mycall".to_string(),
+            GenericId {
+                name: "mycall".to_string(),
+                owner: accounts(0).to_string(),
+                version: None,
+            },
+            U128(10),
+            None,
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_run_construction_call() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        init_json_construction_call(&mut contract, "mycall", test_actions(), test_construction(), 100);
+
+        testing_env!(get_context(accounts(0)).prepaid_gas(300_000_000_000_000).build());
+        contract.run_construction_call("mycall".to_string());
+        let construction_call = contract.get_construction_call_unchecked(&"mycall".to_string());
+        assert!(construction_call.is_self_driving);
+        assert!(construction_call.next_action_calls.is_empty());
+    }
+
+    #[test]
+    fn test_run_construction_call_out_of_gas() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        init_json_construction_call(&mut contract, "mycall", test_actions(), test_construction(), 100);
+
+        // Only enough gas for one of the two action calls
+        testing_env!(get_context(accounts(0)).prepaid_gas(150_000_000_000_000).build());
+        contract.run_construction_call("mycall".to_string());
+        let construction_call = contract.get_construction_call_unchecked(&"mycall".to_string());
+        assert_eq!(construction_call.next_action_calls.len(), 1);
+    }
+
+    #[test]
+    fn test_get_construction_gas_estimate() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let construction_id =
+            register_json_construction(&mut contract, "to estimate", test_actions(), test_construction());
+
+        // Action 0 feeds into action 1. Deserializing the arguments does not write to storage, as a view call cannot
+        let storage_before = env::storage_usage();
+        let estimate = contract.get_construction_gas_estimate(
+            construction_id,
+            U128(100),
+            Some(vec![0]),
+            Some(serde_json::from_str("[\"1\"]").unwrap()),
+            Some(serde_json::from_str("[[[1]], [[]]]").unwrap()),
+        );
+        assert_eq!(env::storage_usage(), storage_before);
+        assert_eq!(estimate.action_calls.len(), 2);
+        assert_eq!(estimate.action_calls[0].action_index_in_construction, 0);
+        assert_eq!(estimate.action_calls[1].action_index_in_construction, 1);
+
+        let action = contract.actions.get(&estimate.action_calls[0].action_id).unwrap();
+        let step_gas = action
+            .get_step_gas(&ActionCall::new_unregistered(100, 0))
+            .unwrap();
+        assert_eq!(estimate.action_calls[0].gas.0, step_gas);
+        assert_eq!(
+            estimate.total.0,
+            estimate.action_calls[0].gas.0
+                + estimate.action_calls[1].gas.0
+                + gas::CROSS_CONTRACT_BASE_GAS
+                + gas::SELF_DRIVE_RESERVE_GAS
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The construction's next actions form a cycle")]
+    fn test_get_construction_gas_estimate_cycle() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let construction_id =
+            register_json_construction(&mut contract, "to estimate", test_actions(), test_construction());
+
+        contract.get_construction_gas_estimate(
+            construction_id,
+            U128(100),
+            Some(vec![0]),
+            Some(serde_json::from_str("[\"1\"]").unwrap()),
+            Some(serde_json::from_str("[[[1]], [[0]]]").unwrap()),
+        );
+    }
+    #[test]
+    fn test_scheduling_modes() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let modes = vec![
+            (SchedulingMode::Dfs, 1),
+            (SchedulingMode::Bfs, 0),
+            (SchedulingMode::Priority, 0),
+        ];
+        for (i, (mode, expected_action_index)) in modes.into_iter().enumerate() {
+            let name = format!("construction {}", i);
+            let mut construction = test_construction();
+            construction["scheduling"] = serde_json::to_value(mode).unwrap();
+            let construction_id =
+                register_json_construction(&mut contract, &name, test_actions(), construction);
+            let construction_call_id = format!("call {}", i);
+            // Schedule action 1 before action 0 so that priority differs from the insertion order
+            let initial_action_indices = match mode {
+                SchedulingMode::Priority => vec![1, 0],
+                _ => vec![0, 1],
+            };
+            contract.init_construction(
+                construction_call_id.clone(),
+                construction_id,
+                U128(100),
+                Some(initial_action_indices),
+                None,
+                None,
+                None,
+            );
+            let construction_call = contract.get_construction_call_unchecked(&construction_call_id);
+            assert_eq!(construction_call.scheduling_mode, mode);
+            let next_index = construction_call.peek_next_action_call(&contract).unwrap();
+            let action_call_id = construction_call.action_calls.0.get(next_index).unwrap();
+            let action_call = contract.get_action_call_unchecked(U64(action_call_id));
+            assert_eq!(action_call.action_index_in_construction, expected_action_index);
+        }
+    }
+
+    /// Start a construction call where action 1 is a join of the initial split and the tokens returned by action 0
+    fn init_join_construction_call(contract: &mut Contract, construction_call_id: &str) {
+        let mut construction = test_construction();
+        construction["next_actions_indices"] = json!([[[1]], [[]]]);
+        construction["next_actions_splits"] = json!([[["1"]], [[]]]);
+        init_json_construction_call(contract, construction_call_id, test_actions(), construction, 100);
+        contract.process_next_action_call(construction_call_id.to_string());
+    }
+
+    #[test]
+    fn test_join_waits_for_all_inputs() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        init_join_construction_call(&mut contract, "join");
+
+        // Only action 0 is scheduled, the join waits on its tokens
+        let construction_call = contract.get_construction_call_unchecked(&"join".to_string());
+        assert!(construction_call.next_action_calls.is_empty());
+        assert_eq!(construction_call.action_calls.0.len(), 2);
+        let join_id = construction_call.action_calls.0.get(1).unwrap();
+        let join = contract.get_action_call_unchecked(U64(join_id));
+        assert!(join.is_waiting_inputs());
+        assert_eq!(join.expected_number_inputs, 2);
+        assert_eq!(join.number_inputs_received, 1);
+
+        let action_call_id = construction_call.action_calls.0.get(0).unwrap();
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"30\"".to_vec())]
+        );
+        contract.handle_action_callback(
+            "join".to_string(),
+            action_call_id,
+            accounts(0).to_string(),
+            Some(ValidAccountId::try_from("wrappppp.localnet".to_string()).unwrap()),
+        );
+
+        // The join is scheduled once with the sum of its inputs
+        let construction_call = contract.get_construction_call_unchecked(&"join".to_string());
+        assert_eq!(construction_call.action_calls.0.len(), 2);
+        assert_eq!(construction_call.next_action_calls.to_vec(), vec![1]);
+        let join = contract.get_action_call_unchecked(U64(join_id));
+        assert!(!join.is_waiting_inputs());
+        assert_eq!(join.number_inputs_received, 2);
+        assert_eq!(join.amount, 80);
+    }
+
+    #[test]
+    fn test_join_scheduled_when_input_fails() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        init_join_construction_call(&mut contract, "join");
+
+        let construction_call = contract.get_construction_call_unchecked(&"join".to_string());
+        let action_call_id = construction_call.action_calls.0.get(0).unwrap();
+        let join_id = construction_call.action_calls.0.get(1).unwrap();
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.handle_action_callback(
+            "join".to_string(),
+            action_call_id,
+            accounts(0).to_string(),
+            Some(ValidAccountId::try_from("wrappppp.localnet".to_string()).unwrap()),
+        );
+
+        assert!(contract
+            .get_action_call_unchecked(U64(action_call_id))
+            .is_error());
+        let construction_call = contract.get_construction_call_unchecked(&"join".to_string());
+        assert_eq!(construction_call.next_action_calls.to_vec(), vec![1]);
+        let join = contract.get_action_call_unchecked(U64(join_id));
+        assert_eq!(join.number_inputs_failed, 1);
+        assert_eq!(join.amount, 50);
+    }
+
+    #[test]
+    fn test_register_new_versions() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let construction_id =
+            register_json_construction(&mut contract, "versioned", test_actions(), test_construction());
+        contract.init_construction(
+            "first".to_string(),
+            construction_id.clone(),
+            U128(100),
+            None,
+            None,
+            None,
+            None,
+        );
+
+        // Registering under the same names creates new versions instead of overwriting
+        register_json_construction(&mut contract, "versioned", test_actions(), test_construction());
+        assert_eq!(
+            contract.resolve_construction_id(&construction_id).unwrap(),
+            construction_id.with_version(1)
+        );
+        let first = contract
+            .get_construction(&construction_id.with_version(0))
+            .unwrap();
+        let latest = contract.get_construction(&construction_id).unwrap();
+        assert_eq!(first.actions.0.get(0).unwrap().version, Some(0));
+        assert_eq!(latest.actions.0.get(0).unwrap().version, Some(1));
+
+        // The running call stays pinned to the version it was started with
+        let construction_call = contract.get_construction_call_unchecked(&"first".to_string());
+        assert_eq!(
+            construction_call.construction_id,
+            construction_id.with_version(0)
+        );
+
+        // A call can pin an exact version
+        contract.init_construction(
+            "pinned".to_string(),
+            construction_id.with_version(0),
+            U128(100),
+            None,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(contract.get_live_construction_calls(&construction_id.with_version(0)), 2);
+        assert_eq!(contract.get_live_construction_calls(&construction_id.with_version(1)), 0);
+    }
+
+    #[test]
+    fn test_construction_call_status() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        assert!(contract
+            .get_construction_call_status("mycall".to_string())
+            .is_none());
+        init_json_construction_call(&mut contract, "mycall", test_actions(), test_construction(), 100);
+        assert_eq!(
+            contract.get_active_construction_calls(accounts(0).to_string(), None, None),
+            vec!["mycall".to_string()]
+        );
+
+        contract.process_next_action_call("mycall".to_string());
+        let status = contract
+            .get_construction_call_status("mycall".to_string())
+            .unwrap();
+        assert_eq!(status.caller, accounts(0).to_string());
+        assert_eq!(status.action_calls.len(), 2);
+        assert_eq!(status.number_scheduled, 1);
+        assert_eq!(status.number_executing, 1);
+        let executing = status
+            .action_calls
+            .iter()
+            .find(|action_call| action_call.block_index_start.is_some())
+            .unwrap();
+        assert_eq!(executing.amount, U128(50));
+        assert_eq!(executing.action_id.version, Some(0));
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"50\"".to_vec())]
+        );
+        contract.handle_action_callback(
+            "mycall".to_string(),
+            executing.action_call_id.0,
+            accounts(0).to_string(),
+            Some(ValidAccountId::try_from("wrapp.localnet".to_string()).unwrap()),
+        );
+        let status = contract
+            .get_construction_call_status("mycall".to_string())
+            .unwrap();
+        assert_eq!(status.number_succeeded, 1);
+        let succeeded = status
+            .action_calls
+            .iter()
+            .find(|action_call| action_call.status == ActionCallStatus::Success)
+            .unwrap();
+        assert!(succeeded.block_index_end.is_some());
+        assert_eq!(succeeded.returned_items[0].amount, U128(50));
+        assert_eq!(
+            contract.get_active_construction_calls(accounts(0).to_string(), Some(U64(1)), None),
+            Vec::<ConstructionCallId>::new()
+        );
+    }
+
+    #[test]
+    fn test_get_by_owner() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let first_id =
+            register_json_construction(&mut contract, "first", test_actions(), test_construction());
+        register_json_construction(&mut contract, "second", test_actions(), test_construction());
+
+        let constructions =
+            contract.get_constructions_by_owner(accounts(0).to_string(), None, None);
+        assert_eq!(constructions.len(), 2);
+        assert_eq!(constructions[0].id, first_id.with_version(0));
+        assert_eq!(constructions[0].number_of_actions, 2);
+        assert_eq!(
+            constructions[0].token_ids,
+            vec!["wrapp.localnet".to_string(), "wrappppp.localnet".to_string()]
+        );
+        let page = contract.get_constructions_by_owner(
+            accounts(0).to_string(),
+            Some(U64(1)),
+            Some(U64(5)),
+        );
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id.name, "second");
+        assert!(contract
+            .get_constructions_by_owner(accounts(1).to_string(), None, None)
+            .is_empty());
+
+        // Each registration of the test construction registers a new version of both actions
+        let actions = contract.get_actions_by_owner(accounts(0).to_string(), None, Some(U64(3)));
+        assert_eq!(actions.len(), 3);
+        assert_eq!(actions[0].token_id, "wrapp.localnet".to_string());
+
+        contract.delete_construction(first_id, Some(true));
+        let constructions =
+            contract.get_constructions_by_owner(accounts(0).to_string(), None, None);
+        assert_eq!(constructions.len(), 1);
+        assert_eq!(
+            contract
+                .get_actions_by_owner(accounts(0).to_string(), None, None)
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_failed_action_call_records_error() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        init_json_construction_call(&mut contract, "mycall", test_actions(), test_construction(), 100);
+        let token_id = "wrappppp.localnet".to_string();
+
+        // Depth first scheduling runs action 1 first
+        contract.process_next_action_call("mycall".to_string());
+        assert_eq!(
+            contract.balances.get_ft_balance(&accounts(0).to_string(), &token_id),
+            50
+        );
+        let construction_call = contract.get_construction_call_unchecked(&"mycall".to_string());
+        let action_call_id = construction_call.action_calls.0.get(1).unwrap();
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.handle_action_callback(
+            "mycall".to_string(),
+            action_call_id,
+            accounts(0).to_string(),
+            Some(ValidAccountId::try_from(token_id.clone()).unwrap()),
+        );
+
+        // The transfer's resolve step credits back a failed transfer, so the callback credits nothing more
+        assert_eq!(
+            contract.balances.get_ft_balance(&accounts(0).to_string(), &token_id),
+            50
+        );
+        let status = contract
+            .get_construction_call_status("mycall".to_string())
+            .unwrap();
+        assert_eq!(status.number_errored, 1);
+        // The sibling branch is still scheduled
+        assert_eq!(status.number_scheduled, 1);
+        assert_eq!(
+            contract.get_action_call_unchecked(U64(action_call_id)).get_status(),
+            &ActionCallStatus::from(MallocError::ActionCallFailed)
+        );
+    }
+
+    #[test]
+    fn test_failed_malloc_call_reclaims_sent_tokens() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        resolve_test_malloc_call_registration(&mut contract, "swap.localnet", 20);
+        init_json_construction_call(
+            &mut contract,
+            "mycall",
+            vec![("swap", test_malloc_call_action("swap.localnet"))],
+            json!({
+                "actions": [{ "owner": accounts(0), "name": "swap" }],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[]]],
+                "next_actions_splits": [[[]]]
+            }),
+            100,
+        );
+        contract.process_next_action_call("mycall".to_string());
+        let token_id = "wrapp.localnet".to_string();
+        assert_eq!(
+            contract.balances.get_ft_balance(&accounts(0).to_string(), &token_id),
+            0
+        );
+        let construction_call = contract.get_construction_call_unchecked(&"mycall".to_string());
+        let action_call_id = construction_call.action_calls.0.get(0).unwrap();
+
+        // The transfer into the malloc call went through but the malloc call failed, so no resolve step credits it back
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.handle_action_callback("mycall".to_string(), action_call_id, accounts(0).to_string(), None);
+        let summary = contract
+            .get_construction_call_summary("mycall".to_string())
+            .unwrap();
+        assert_eq!(summary.number_of_errors, 1);
+        // The malloc call's balance is queried and then withdrawn back
+        assert_eq!(near_sdk::test_utils::get_created_receipts().len(), 2);
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"100\"".to_vec())]
+        );
+        contract.resolve_reclaim_balance(
+            "swap.localnet".to_string(),
+            accounts(0).to_string(),
+            token_id,
+            U128(100),
+        );
+        assert!(near_sdk::test_utils::get_logs()
+            .contains(&format!("Reclaiming 100 of wrapp.localnet from swap.localnet for {}", accounts(0))));
+    }
+
+    #[test]
+    fn test_callback_shape_mismatch_records_error() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        init_json_construction_call(&mut contract, "mycall", test_actions(), test_construction(), 100);
+        contract.process_next_action_call("mycall".to_string());
+        let construction_call = contract.get_construction_call_unchecked(&"mycall".to_string());
+        let action_call_id = construction_call.action_calls.0.get(1).unwrap();
+
+        // The action has one set of next actions but two return items are returned
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                b"[{\"token_id\": \"a.near\", \"amount\": \"1\"}, {\"token_id\": \"b.near\", \"amount\": \"1\"}]"
+                    .to_vec()
+            )]
+        );
+        contract.handle_action_callback(
+            "mycall".to_string(),
+            action_call_id,
+            accounts(0).to_string(),
+            None,
+        );
+
+        assert_eq!(
+            contract.get_action_call_unchecked(U64(action_call_id)).get_status(),
+            &ActionCallStatus::from(MallocError::ReturnLengthMismatch {
+                expected: 1,
+                actual: 2
+            })
+        );
+        // Nothing is credited back as the action call's promise succeeded
+        assert_eq!(
+            contract
+                .balances
+                .get_ft_balance(&accounts(0).to_string(), &"wrappppp.localnet".to_string()),
+            50
+        );
+    }
+
+    #[test]
+    fn test_abort_construction_call() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        init_json_construction_call(&mut contract, "mycall", test_actions(), test_construction(), 100);
+        contract.process_next_action_call("mycall".to_string());
+        let construction_call = contract.get_construction_call_unchecked(&"mycall".to_string());
+        let succeeded_id = construction_call.action_calls.0.get(1).unwrap();
+        let executing_id = construction_call.action_calls.0.get(0).unwrap();
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"30\"".to_vec())]
+        );
+        contract.handle_action_callback(
+            "mycall".to_string(),
+            succeeded_id,
+            accounts(0).to_string(),
+            Some(ValidAccountId::try_from("wrappppp.localnet".to_string()).unwrap()),
+        );
+        testing_env!(get_context(accounts(0)).build());
+        contract.process_next_action_call("mycall".to_string());
+
+        let err = contract
+            .abort_construction_call_internal(&"mycall".to_string(), &accounts(1).to_string())
+            .unwrap_err();
+        assert_eq!(
+            err,
+            MallocError::CallerDoesNotOwnConstructionCall {
+                caller: accounts(1).to_string(),
+                owner: accounts(0).to_string()
+            }
+        );
+
+        // The ft transfer action has no revert method, so no promise is created and it keeps its success status
+        let last_promise = contract
+            .abort_construction_call_internal(&"mycall".to_string(), &accounts(0).to_string())
+            .unwrap();
+        assert_eq!(last_promise, None);
+        assert_eq!(
+            contract.get_action_call_unchecked(U64(succeeded_id)).get_status(),
+            &ActionCallStatus::Success
+        );
+        // Action calls which are still executing are left to finish and are not reverted
+        assert!(!contract.get_action_call_unchecked(U64(executing_id)).is_finished());
+        let construction_call = contract.get_construction_call_unchecked(&"mycall".to_string());
+        assert!(construction_call.is_aborted);
+        assert_eq!(
+            contract
+                .abort_construction_call_internal(&"mycall".to_string(), &accounts(0).to_string())
+                .unwrap_err(),
+            MallocError::ConstructionCallAlreadyAborted
+        );
+    }
+
+    #[test]
+    fn test_abort_construction_call_with_scheduled_calls() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        init_json_construction_call(&mut contract, "mycall", test_actions(), test_construction(), 100);
+        contract.process_next_action_call("mycall".to_string());
+        let construction_call = contract.get_construction_call_unchecked(&"mycall".to_string());
+        let scheduled_id = construction_call.action_calls.0.get(0).unwrap();
+
+        contract
+            .abort_construction_call_internal(&"mycall".to_string(), &accounts(0).to_string())
+            .unwrap();
+        let construction_call = contract.get_construction_call_unchecked(&"mycall".to_string());
+        assert!(construction_call.next_action_calls.is_empty());
+        assert_eq!(
+            contract.get_action_call_unchecked(U64(scheduled_id)).get_status(),
+            &ActionCallStatus::from(MallocError::ConstructionCallAborted)
+        );
+    }
+
+    #[test]
+    fn test_reclaim_timed_out_action_call() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        init_json_construction_call(&mut contract, "mycall", test_actions(), test_construction(), 100);
+        contract.process_next_action_call("mycall".to_string());
+        let construction_call = contract.get_construction_call_unchecked(&"mycall".to_string());
+        let executing_id = construction_call.action_calls.0.get(1).unwrap();
+        let scheduled_id = construction_call.action_calls.0.get(0).unwrap();
+
+        assert_eq!(
+            contract
+                .timeout_action_call_internal(&"mycall".to_string(), executing_id)
+                .unwrap_err(),
+            MallocError::ActionCallNotTimedOut {
+                block_index_timeout: construction::DEFAULT_ACTION_CALL_TIMEOUT_BLOCKS
+            }
+        );
+        assert_eq!(
+            contract
+                .timeout_action_call_internal(&"mycall".to_string(), scheduled_id)
+                .unwrap_err(),
+            MallocError::ActionCallNotExecuting
+        );
+
+        // Any account can reclaim the action call once the timeout passed
+        let mut context = get_context(accounts(1));
+        context.block_index(construction::DEFAULT_ACTION_CALL_TIMEOUT_BLOCKS);
+        testing_env!(context.build());
+        contract.reclaim_timed_out_action_call("mycall".to_string(), U64(executing_id));
+        assert_eq!(
+            contract.get_action_call_unchecked(U64(executing_id)).get_status(),
+            &ActionCallStatus::from(MallocError::ActionCallTimedOut)
+        );
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 2);
+
+        // Only as much as the action call sent is reclaimed, even if the caller holds more at the malloc call
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"500\"".to_vec())]
+        );
+        contract.resolve_reclaim_balance(
+            accounts(2).to_string(),
+            accounts(0).to_string(),
+            "wrappppp.localnet".to_string(),
+            U128(50),
+        );
+        assert!(near_sdk::test_utils::get_logs()
+            .contains(&format!("Reclaiming 50 of wrappppp.localnet from {} for {}", accounts(2), accounts(0))));
+
+        // A callback which arrives after the timeout is ignored
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"30\"".to_vec())]
+        );
+        contract.handle_action_callback(
+            "mycall".to_string(),
+            executing_id,
+            accounts(0).to_string(),
+            Some(ValidAccountId::try_from("wrappppp.localnet".to_string()).unwrap()),
+        );
+        assert!(contract
+            .get_action_call_unchecked(U64(executing_id))
+            .is_error());
+    }
+
+    #[test]
+    fn test_ft_transfer_action() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let token_id = ValidAccountId::try_from("wrapp.localnet".to_string()).unwrap();
+        let pay = Action::FtTransfer(actions::ft_calls::FtTransfer {
+            receiver_id: accounts(3),
+            token_id: token_id.clone(),
+        });
+        init_json_construction_call(
+            &mut contract,
+            "paycall",
+            vec![("pay", pay)],
+            json!({
+                "actions": [{ "owner": accounts(0), "name": "pay" }],
+                "initial_action_indices": [0],
+                "initial_splits": ["1"],
+                "next_actions_indices": [[[]]],
+                "next_actions_splits": [[[]]]
+            }),
+            60,
+        );
+        contract.process_next_action_call("paycall".to_string());
+        assert_eq!(
+            contract
+                .balances
+                .get_ft_balance(&accounts(0).to_string(), &token_id.to_string()),
+            40
+        );
+
+        // The resolve step reports the amount which was delivered
+        let construction_call = contract.get_construction_call_unchecked(&"paycall".to_string());
+        let action_call_id = construction_call.action_calls.0.get(0).unwrap();
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"60\"".to_vec())]
+        );
+        contract.handle_action_callback(
+            "paycall".to_string(),
+            action_call_id,
+            accounts(0).to_string(),
+            Some(token_id.clone()),
+        );
+        let summary = contract
+            .get_construction_call_summary("paycall".to_string())
+            .unwrap();
+        assert_eq!(summary.number_of_errors, 0);
+    }
+
+    #[test]
+    fn test_run_construction_call_insufficient_balance() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let construction_id =
+            register_json_construction(&mut contract, "to run", test_actions(), test_construction());
+        // Only the first action's token is deposited
+        testing_env!(get_context(ValidAccountId::try_from("wrapp.localnet").unwrap()).build());
+        contract.ft_on_transfer(accounts(0).to_string(), "100".to_string(), "".to_string());
+        testing_env!(get_context(accounts(0)).build());
+        contract.init_construction(
+            "mycall".to_string(),
+            construction_id,
+            U128(100),
+            None,
+            None,
+            None,
+            None,
+        );
+
+        testing_env!(get_context(accounts(0)).prepaid_gas(300_000_000_000_000).build());
+        contract.run_construction_call("mycall".to_string());
+        let construction_call = contract.get_construction_call_unchecked(&"mycall".to_string());
+        assert!(!construction_call.is_self_driving);
+        let action_call_id = construction_call.action_calls.0.get(1).unwrap();
+        match contract.get_action_call_unchecked(U64(action_call_id)).get_status() {
+            ActionCallStatus::Error { error, .. } => assert_eq!(error.code(), 513),
+            status => panic!("Expected the action call to error, got {:?}", status),
+        }
+        let action_call_id = construction_call.action_calls.0.get(0).unwrap();
+        assert!(matches!(
+            contract.get_action_call_unchecked(U64(action_call_id)).get_status(),
+            ActionCallStatus::Executing { .. }
+        ));
+    }
+
+    #[test]
+    fn test_deposit_near() {
+        let mut context = get_context(accounts(1));
+        context.attached_deposit(50);
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        contract.deposit_near(None);
+        contract.deposit_near(Some(accounts(2)));
+        assert_eq!(contract.get_near_balance(accounts(1)), U128(50));
+        assert_eq!(contract.get_near_balance(accounts(2)), U128(50));
+    }
+
+    #[test]
+    fn test_withdraw_near() {
+        let mut context = get_context(accounts(1));
+        context.attached_deposit(50);
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        contract.deposit_near(None);
+
+        let mut context = get_context(accounts(1));
+        context.attached_deposit(1);
+        testing_env!(context.build());
+        contract.withdraw_near(U128(20), None);
+        assert_eq!(contract.get_near_balance(accounts(1)), U128(30));
+
+        // A failed transfer is credited back
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.resolve_near_transfer(accounts(1).to_string(), U128(20));
+        assert_eq!(contract.get_near_balance(accounts(1)), U128(50));
+    }
+
+    #[test]
+    #[should_panic(expected = "InsufficientBalance")]
+    fn test_withdraw_near_insufficient_balance() {
+        let mut context = get_context(accounts(1));
+        context.attached_deposit(1);
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        contract.withdraw_near(U128(20), None);
+    }
+
+    #[test]
+    fn test_wrap_and_unwrap_near() {
+        let mut context = get_context(accounts(0));
+        context.attached_deposit(100);
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        contract.deposit_near(None);
+        let wnear_contract_id = accounts(4);
+        let wrap = actions::near_calls::WrapNear {
+            wnear_contract_id: wnear_contract_id.clone(),
+        };
+        let (action_call, _) = ActionCall::new(&mut contract, 60, 0);
+
+        // The wNEAR contract must be configured first
+        assert_eq!(
+            wrap.handle(&mut contract, &action_call, &"call".to_string(), 0, &accounts(0).to_string(), 0)
+                .unwrap_err(),
+            MallocError::WnearContractNotConfigured
+        );
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_wnear_contract_id(wnear_contract_id.clone());
+        wrap.handle(&mut contract, &action_call, &"call".to_string(), 0, &accounts(0).to_string(), 0)
+            .unwrap();
+        let near_balance = |contract: &Contract| {
+            contract
+                .balances
+                .get_ft_balance(&accounts(0).to_string(), &NEAR_TOKEN_ID.to_string())
+        };
+        assert_eq!(near_balance(&contract), 40);
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        let wrapped = contract.resolve_wrap_near(
+            accounts(0).to_string(),
+            U128(60),
+            wnear_contract_id.to_string(),
+        );
+        assert_eq!(wrapped, U128(60));
+        assert_eq!(
+            contract
+                .balances
+                .get_ft_balance(&accounts(0).to_string(), &wnear_contract_id.to_string()),
+            60
+        );
+
+        // A failed unwrap credits the wNEAR back
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract
+            .balances
+            .subtract_balance(&accounts(0).to_string(), &wnear_contract_id.to_string(), 60);
+        let unwrapped = contract.resolve_unwrap_near(
+            accounts(0).to_string(),
+            U128(60),
+            wnear_contract_id.to_string(),
+        );
+        assert_eq!(unwrapped, U128(0));
+        assert_eq!(near_balance(&contract), 40);
+        assert_eq!(
+            contract
+                .balances
+                .get_ft_balance(&accounts(0).to_string(), &wnear_contract_id.to_string()),
+            60
+        );
+    }
+
+    #[test]
+    fn test_error_serialization() {
+        let error = MallocError::ActionIndexOutOfBounds {
+            index: 2,
+            number_of_actions: 2,
+        };
+        let report: serde_json::Value = serde_json::from_str(&error.to_string()).unwrap();
+        assert_eq!(report["code"], 104);
+        assert_eq!(report["error"]["kind"], "ActionIndexOutOfBounds");
+        assert_eq!(report["error"]["index"], 2);
+        let deserialized: MallocError = serde_json::from_value(report["error"].clone()).unwrap();
+        assert_eq!(deserialized, error);
+
+        // The stored status of an errored action call carries the same error
+        let status = serde_json::to_value(ActionCallStatus::from(MallocError::ActionCallFailed)).unwrap();
+        assert_eq!(status["Error"]["error"]["kind"], "ActionCallFailed");
+        let stored: MallocError = serde_json::from_value(status["Error"]["error"].clone()).unwrap();
+        assert_eq!(stored.code(), 500);
+
+        // Stored errors keep their Borsh discriminants as new errors are appended
+        assert_eq!(MallocError::ActionCallFailed.try_to_vec().unwrap()[0], 22);
+        assert_eq!(MallocError::SplitsDoNotMatchAmounts.try_to_vec().unwrap()[0], 34);
+    }
+
+    /// Get the NEP-297 events logged in the current testing environment
+    fn get_events() -> Vec<serde_json::Value> {
+        near_sdk::test_utils::get_logs()
+            .iter()
+            .filter_map(|log| log.strip_prefix(malloc_call_core::events::EVENT_JSON_PREFIX))
+            .map(|event| serde_json::from_str(event).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_events() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        init_json_construction_call(&mut contract, "mycall", test_actions(), test_construction(), 100);
+        let events = get_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["standard"], events::EVENT_STANDARD);
+        assert_eq!(events[0]["event"], "construction_call_init");
+        assert_eq!(events[0]["data"][0]["construction_call_id"], "mycall");
+        assert_eq!(events[0]["data"][0]["amount"], "100");
+
+        testing_env!(get_context(accounts(0)).build());
+        contract.process_next_action_call("mycall".to_string());
+        let events = get_events();
+        assert_eq!(events[0]["event"], "action_call_start");
+        let action_call_id = contract
+            .get_construction_call_unchecked(&"mycall".to_string())
+            .action_calls
+            .0
+            .get(1)
+            .unwrap();
+        assert_eq!(events[0]["data"][0]["action_call_id"], action_call_id.to_string());
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.handle_action_callback(
+            "mycall".to_string(),
+            action_call_id,
+            accounts(0).to_string(),
+            None,
+        );
+        let events = get_events();
+        assert_eq!(events[0]["event"], "action_call_failure");
+        assert_eq!(events[0]["data"][0]["error"]["kind"], "ActionCallFailed");
+
+        // Deposits into the internal balances are emitted by the balances
+        let mut context = get_context(accounts(1));
+        context.attached_deposit(50);
+        testing_env!(context.build());
+        contract.deposit_near(None);
+        let events = get_events();
+        assert_eq!(events[0]["standard"], malloc_call_core::events::FT_EVENT_STANDARD);
+        assert_eq!(events[0]["event"], "ft_deposit");
+        assert_eq!(events[0]["data"][0]["token_id"], NEAR_TOKEN_ID);
+        assert_eq!(events[0]["data"][0]["amount"], "50");
+    }
+
+    #[test]
+    fn test_pause_keeps_withdrawals_open() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        assert_eq!(contract.get_owner(), accounts(0).to_string());
+        init_json_construction_call(&mut contract, "mycall", test_actions(), test_construction(), 100);
+
+        testing_env!(get_context(accounts(0)).build());
+        contract.pause();
+        assert!(contract.is_paused());
+
+        // The caller can still exit with their tokens
+        let token_id = ValidAccountId::try_from("wrapp.localnet").unwrap();
+        let mut context = get_context(accounts(0));
+        context.attached_deposit(1);
+        testing_env!(context.build());
+        contract.withdraw_to(
+            accounts(0),
+            U128(10),
+            token_id.clone(),
+            Some(accounts(0)),
+            None,
+            TransferType::Transfer(),
+        );
+        contract.abort_construction_call("mycall".to_string());
+        assert_eq!(
+            contract.get_ft_balance(accounts(0), token_id),
+            U128(90)
+        );
+
+        testing_env!(get_context(accounts(0)).build());
+        contract.unpause();
+        assert!(!contract.is_paused());
+    }
+
+    #[test]
+    #[should_panic(expected = "The contract is paused")]
+    fn test_pause_blocks_init_construction() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        contract.pause();
+        init_json_construction_call(&mut contract, "mycall", test_actions(), test_construction(), 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "The contract is paused")]
+    fn test_pause_blocks_deposits() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        contract.pause();
+        testing_env!(get_context(ValidAccountId::try_from("wrapp.localnet").unwrap()).build());
+        contract.ft_on_transfer(accounts(0).to_string(), "100".to_string(), "".to_string());
+    }
+
+    #[test]
+    fn test_pause_accepts_returned_tokens() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        init_json_construction_call(&mut contract, "mycall", test_actions(), test_construction(), 100);
+        // Depth first scheduling runs action 1 first
+        contract.process_next_action_call("mycall".to_string());
+        let construction_call = contract.get_construction_call_unchecked(&"mycall".to_string());
+        let action_call_id = construction_call.action_calls.0.get(1).unwrap();
+        contract.pause();
+
+        // The malloc call sends the tokens of the action call which was in flight back for the caller
+        let token_id = ValidAccountId::try_from("wrappppp.localnet").unwrap();
+        testing_env!(get_context(token_id.clone()).build());
+        contract.ft_on_transfer(
+            accounts(2).to_string(),
+            "30".to_string(),
+            json!({ "sender_id": accounts(0) }).to_string(),
+        );
+        assert_eq!(contract.get_ft_balance(accounts(0), token_id.clone()), U128(80));
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"30\"".to_vec())]
+        );
+        contract.handle_action_callback(
+            "mycall".to_string(),
+            action_call_id,
+            accounts(0).to_string(),
+            Some(token_id),
+        );
+        assert!(contract.get_action_call_unchecked(U64(action_call_id)).is_success());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract's owner can call this method")]
+    fn test_set_owner() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        contract.set_owner(accounts(1));
+        assert_eq!(contract.get_owner(), accounts(1).to_string());
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.pause();
+        assert!(contract.is_paused());
+
+        // The previous owner can no longer pause the contract
+        testing_env!(get_context(accounts(0)).build());
+        contract.unpause();
+    }
+
+    fn resolve_test_malloc_call_registration(
+        contract: &mut Contract,
+        malloc_call_id: &str,
+        gas_required: Gas,
+    ) -> MallocCallRegistryEntry {
+        let metadata = json!({
+            "name": "swap",
+            "gas_required": gas_required,
+            "attachment_required": "1"
+        });
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(metadata.to_string().into_bytes())]
+        );
+        contract.resolve_register_malloc_call(malloc_call_id.to_string())
+    }
+
+    fn test_malloc_call_action(malloc_call_id: &str) -> Action {
+        serde_json::from_value(json!({
+            "MallocCall": {
+                "malloc_call_id": malloc_call_id,
+                "token_id": "wrapp.localnet",
+                "json_args": "{}"
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_malloc_call_registry() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        let entry = resolve_test_malloc_call_registration(&mut contract, "swap.localnet", 20);
+        assert_eq!(entry.trust_level, TrustLevel::Unverified);
+        assert_eq!(entry.metadata.gas_required, 20);
+
+        contract.set_malloc_call_trust_level(
+            ValidAccountId::try_from("swap.localnet").unwrap(),
+            TrustLevel::Verified,
+        );
+        // Refreshing unchanged metadata keeps the trust level
+        let entry = resolve_test_malloc_call_registration(&mut contract, "swap.localnet", 20);
+        assert_eq!(entry.trust_level, TrustLevel::Verified);
+        // Changed metadata has to be verified again
+        let entry = resolve_test_malloc_call_registration(&mut contract, "swap.localnet", 30);
+        assert_eq!(entry.trust_level, TrustLevel::Unverified);
+        assert_eq!(contract.get_registered_malloc_calls(None, None), vec![entry]);
+
+        contract.remove_malloc_call(ValidAccountId::try_from("swap.localnet").unwrap());
+        assert!(contract
+            .get_malloc_call_registry_entry(ValidAccountId::try_from("swap.localnet").unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn test_malloc_call_defaults_from_registry() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        resolve_test_malloc_call_registration(&mut contract, "swap.localnet", 20);
+        register_json_actions(&mut contract, vec![("swap", test_malloc_call_action("swap.localnet"))]);
+        let action = contract.get_action(&GenericId {
+            owner: accounts(0).to_string(),
+            name: "swap".to_string(),
+            version: None,
+        });
+        let action = serde_json::to_value(action.unwrap()).unwrap();
+        assert_eq!(action["MallocCall"]["gas"], json!(20));
+        assert_eq!(action["MallocCall"]["attached_amount"], json!("1"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract's owner can call this method")]
+    fn test_register_malloc_call_by_non_owner() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        testing_env!(get_context(accounts(1)).build());
+        contract.register_malloc_call(ValidAccountId::try_from("swap.localnet").unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "The malloc call is not registered in the malloc call registry")]
+    fn test_register_unregistered_malloc_call() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        register_json_actions(&mut contract, vec![("swap", test_malloc_call_action("swap.localnet"))]);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(None);
+        contract.pause();
+        env::state_write(&contract);
+        assert_eq!(contract.get_state_version(), migrations::CURRENT_STATE_VERSION);
+
+        let contract = Contract::migrate();
+        assert_eq!(contract.get_owner(), accounts(0).to_string());
+        assert!(contract.is_paused());
+    }
+
+    #[test]
+    fn test_migrate_v0() {
+        use crate::migrations::{
+            ActionCallStatusV0, ActionCallV0, ActionV0, ConstructionCallV0, ConstructionV0,
+            ContractV0, GenericIdV0, MallocCallV0,
+        };
+
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let token_id = ValidAccountId::try_from("wrapp.localnet").unwrap();
+        let swap_id = GenericIdV0 {
+            owner: accounts(1).to_string(),
+            name: "swap".to_string(),
+        };
+        let call_id = GenericIdV0 {
+            owner: accounts(1).to_string(),
+            name: "call".to_string(),
+        };
+        let construction_id = GenericIdV0 {
+            owner: accounts(1).to_string(),
+            name: "swaps".to_string(),
+        };
+        let swap = FtTransferCallToMallocCall {
+            malloc_call_id: accounts(2),
+            token_id: token_id.clone(),
+        };
+
+        // The first release's state, written without a state version
+        let mut old = ContractV0 {
+            constructions: UnorderedMap::new("constructions".as_bytes()),
+            construction_calls: UnorderedMap::new("construction-call-stack".as_bytes()),
+            action_calls: UnorderedMap::new("actioncalls".as_bytes()),
+            actions: UnorderedMap::new("actions".as_bytes()),
+            balances: FungibleTokenBalances::new("malloc-ft".as_bytes()),
+            next_action_call_id: 1,
+            malloc_contract_id: accounts(0).to_string(),
+        };
+        old.actions
+            .insert(&swap_id, &ActionV0::FtTransferCallToMallocCall(swap.clone()));
+        old.actions.insert(
+            &call_id,
+            &ActionV0::MallocCall(MallocCallV0 {
+                check_callback: None,
+                skip_ft_transfer: None,
+                malloc_call_id: accounts(2).to_string(),
+                token_id: token_id.to_string(),
+                json_args: "{}".to_string(),
+                gas: 10_000_000_000_000,
+                attached_amount: U128(1),
+            }),
+        );
+        old.constructions.insert(
+            &construction_id,
+            &ConstructionV0 {
+                actions: VectorWrapper::from_vec(
+                    vec![swap_id.clone(), swap_id.clone(), call_id.clone()],
+                    "old-construction".as_bytes(),
+                ),
+            },
+        );
+        old.construction_calls.insert(
+            &"in flight".to_string(),
+            &ConstructionCallV0 {
+                caller: accounts(1).to_string(),
+                construction_id: construction_id.clone(),
+                next_action_calls_stack: VectorWrapper::from_vec(vec![0], "old-stack".as_bytes()),
+                action_calls: VectorWrapper::from_vec(vec![0], "old-action-calls".as_bytes()),
+                next_actions_indices_in_construction: VectorWrapper::new("old-indices".as_bytes()),
+                next_actions_splits: VectorWrapper::new("old-splits".as_bytes()),
+            },
+        );
+        old.action_calls.insert(
+            &0,
+            &ActionCallV0 {
+                action_index_in_construction: 0,
+                block_index: 0,
+                amount: 100,
+                status: ActionCallStatusV0::WaitingCall,
+            },
+        );
+        old.balances
+            .increase_balance(&accounts(1).to_string(), &token_id.to_string(), 100);
+        env::state_write(&old);
+        assert_eq!(migrations::read_state_version(), 0);
+
+        let contract = Contract::migrate();
+        assert_eq!(contract.get_state_version(), migrations::CURRENT_STATE_VERSION);
+        assert_eq!(contract.get_owner(), accounts(0).to_string());
+        assert_eq!(contract.next_action_call_id, 1);
+        assert_eq!(contract.get_ft_balance(accounts(1), token_id), U128(100));
+
+        let swap_v0 = GenericId::new("swap".to_string(), Some(accounts(1).to_string())).with_version(0);
+        assert_eq!(
+            contract.get_action(&swap_v0.unversioned()).unwrap(),
+            Action::FtTransferCallToMallocCall(swap)
+        );
+        let call_v0 = GenericId::new("call".to_string(), Some(accounts(1).to_string())).with_version(0);
+        assert!(matches!(contract.get_action(&call_v0).unwrap(), Action::MallocCall(_)));
+        assert!(contract
+            .owner_actions
+            .get(&accounts(1).to_string())
+            .unwrap()
+            .contains(&swap_v0));
+
+        let construction = contract
+            .get_construction(&GenericId::new("swaps".to_string(), Some(accounts(1).to_string())))
+            .unwrap();
+        assert_eq!(construction.actions.0.to_vec(), vec![swap_v0.clone(), swap_v0.clone(), call_v0.clone()]);
+        assert_eq!(construction.next_actions_indices.0.len(), 3);
+        assert_eq!(construction.next_actions_splits.0.len(), 3);
+        assert_eq!(contract.get_action_references(&swap_v0), 1);
+        assert_eq!(contract.get_action_references(&call_v0), 1);
+
+        // Construction calls in flight are dropped
+        assert!(contract.construction_calls.is_empty());
+        assert!(contract.action_calls.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract's owner can call this method")]
+    fn test_migrate_by_non_owner() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let contract = Contract::new(None);
+        env::state_write(&contract);
+
+        testing_env!(get_context(accounts(1)).build());
+        Contract::migrate();
+    }
+
+    #[test]
+    #[should_panic(expected = "which cannot be migrated to the current version")]
+    fn test_migrate_unknown_state_version() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let contract = Contract::new(None);
+        env::state_write(&contract);
+        migrations::write_state_version(migrations::CURRENT_STATE_VERSION + 1);
+        Contract::migrate();
+    }
 }
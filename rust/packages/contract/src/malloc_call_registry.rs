@@ -0,0 +1,62 @@
+use malloc_call_core::MallocCallMetadata;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, AccountId};
+
+use crate::errors::MallocError;
+use crate::Contract;
+
+/// How far the contract's owner vouches for a registered malloc call
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum TrustLevel {
+    /// The malloc call was registered by anyone and has not been reviewed
+    Unverified,
+    /// The contract's owner reviewed the malloc call
+    Verified,
+}
+
+/// A registered malloc call along with the metadata which was pulled from its metadata method
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MallocCallRegistryEntry {
+    pub malloc_call_id: AccountId,
+    pub metadata: MallocCallMetadata,
+    pub trust_level: TrustLevel,
+    /// The block at which the metadata was last pulled from the malloc call
+    pub block_index_cached: u64,
+}
+
+impl Contract {
+    pub(crate) fn get_registered_malloc_call(
+        &self,
+        malloc_call_id: &AccountId,
+    ) -> Result<MallocCallRegistryEntry, MallocError> {
+        self.malloc_call_registry
+            .get(malloc_call_id)
+            .ok_or(MallocError::MallocCallNotRegistered {
+                malloc_call_id: malloc_call_id.clone(),
+            })
+    }
+
+    /// Cache the metadata pulled from the malloc call. A verified malloc call stays verified
+    /// only as long as its metadata does not change
+    pub(crate) fn cache_malloc_call_metadata(
+        &mut self,
+        malloc_call_id: &AccountId,
+        metadata: MallocCallMetadata,
+    ) -> MallocCallRegistryEntry {
+        let trust_level = match self.malloc_call_registry.get(malloc_call_id) {
+            Some(entry) if entry.metadata == metadata => entry.trust_level,
+            _ => TrustLevel::Unverified,
+        };
+        let entry = MallocCallRegistryEntry {
+            malloc_call_id: malloc_call_id.clone(),
+            metadata,
+            trust_level,
+            block_index_cached: env::block_index(),
+        };
+        self.malloc_call_registry.insert(malloc_call_id, &entry);
+        entry
+    }
+}
@@ -1,4 +1,6 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, UnorderedSet};
+use near_sdk::json_types::U64;
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{env, serde, AccountId};
 use uint::construct_uint;
@@ -8,6 +10,9 @@ use uint::construct_uint;
 pub struct GenericId {
     pub owner: AccountId,
     pub name: String,
+    /// Registered objects are never overwritten. Registering under a name which is already used creates a new version.
+    /// If None, the id refers to the latest version
+    pub version: Option<u64>,
 }
 
 impl GenericId {
@@ -15,8 +20,119 @@ impl GenericId {
         GenericId {
             name,
             owner: owner.unwrap_or(env::predecessor_account_id()),
+            version: None,
         }
     }
+
+    pub fn unversioned(&self) -> GenericId {
+        GenericId {
+            version: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn with_version(&self, version: u64) -> GenericId {
+        GenericId {
+            version: Some(version),
+            ..self.clone()
+        }
+    }
+
+    /// Get the id for registering a new version and record it as the latest version
+    /// @param latest_versions - the latest registered version for each unversioned id
+    pub(crate) fn next_version(&self, latest_versions: &mut LookupMap<GenericId, u64>) -> GenericId {
+        let unversioned = self.unversioned();
+        let version = latest_versions
+            .get(&unversioned)
+            .map(|latest| latest + 1)
+            .unwrap_or(0);
+        latest_versions.insert(&unversioned, &version);
+        self.with_version(version)
+    }
+
+    /// Point the id without a version at the highest version which still exists, or forget it if no version is left.
+    /// This is done after deleting versions, so that the id without a version does not refer to a deleted version
+    /// @param exists - whether a version is still registered
+    pub(crate) fn repoint_latest_version(
+        &self,
+        latest_versions: &mut LookupMap<GenericId, u64>,
+        exists: impl Fn(&GenericId) -> bool,
+    ) {
+        let unversioned = self.unversioned();
+        let latest = match latest_versions.get(&unversioned) {
+            Some(latest) => latest,
+            None => return,
+        };
+        match (0..=latest).rev().find(|version| exists(&self.with_version(*version))) {
+            Some(version) => latest_versions.insert(&unversioned, &version),
+            None => latest_versions.remove(&unversioned),
+        };
+    }
+
+    /// Get the id of the exact version which the id refers to
+    /// @param latest_versions - the latest registered version for each unversioned id
+    /// @returns None if the id has no version and nothing was registered under it
+    pub(crate) fn resolve_version(&self, latest_versions: &LookupMap<GenericId, u64>) -> Option<GenericId> {
+        match self.version {
+            Some(_) => Some(self.clone()),
+            None => latest_versions
+                .get(self)
+                .map(|version| self.with_version(version)),
+        }
+    }
+}
+
+/// Insert the element into the set stored under the account. If the account has no set yet,
+/// one is created with a storage prefix made from prefix and the account
+pub(crate) fn insert_into_account_set<T: BorshSerialize + BorshDeserialize>(
+    map: &mut LookupMap<AccountId, UnorderedSet<T>>,
+    account: &AccountId,
+    prefix: &str,
+    element: &T,
+) {
+    let mut set = map
+        .get(account)
+        .unwrap_or_else(|| UnorderedSet::new(format!("{}-{}", prefix, account).as_bytes()));
+    set.insert(element);
+    map.insert(account, &set);
+}
+
+/// Remove the element from the set stored under the account. The set is removed once it is empty
+pub(crate) fn remove_from_account_set<T: BorshSerialize + BorshDeserialize>(
+    map: &mut LookupMap<AccountId, UnorderedSet<T>>,
+    account: &AccountId,
+    element: &T,
+) {
+    if let Some(mut set) = map.get(account) {
+        set.remove(element);
+        if set.is_empty() {
+            map.remove(account);
+        } else {
+            map.insert(account, &set);
+        }
+    }
+}
+
+/// Get a page of the elements in the set stored under the account
+/// @param from_index - the index of the first element of the page, defaults to 0
+/// @param limit - the maximum number of elements in the page, defaults to all remaining elements
+pub(crate) fn paginate_account_set<T: BorshSerialize + BorshDeserialize>(
+    map: &LookupMap<AccountId, UnorderedSet<T>>,
+    account: &AccountId,
+    from_index: Option<U64>,
+    limit: Option<U64>,
+) -> Vec<T> {
+    let set = match map.get(account) {
+        None => return vec![],
+        Some(set) => set,
+    };
+    let from_index = from_index.map(u64::from).unwrap_or(0);
+    let limit = limit.map(u64::from).unwrap_or(u64::MAX);
+    set.as_vector()
+        .iter()
+        .skip(from_index as usize)
+        .take(limit as usize)
+        .collect()
 }
 
 construct_uint! {
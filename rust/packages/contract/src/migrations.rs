@@ -0,0 +1,240 @@
+use std::convert::TryFrom;
+
+use malloc_call_core::ft::FungibleTokenBalances;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::{env, log, AccountId, Gas};
+
+use crate::action::{Action, ActionCallId, ActionId};
+use crate::actions::ft_calls::{FtTransferCallToMallocCall, WithdrawFromMallocCall};
+use crate::construction::{Construction, ConstructionCallId, ConstructionId};
+use crate::errors::MallocError;
+use crate::malloc_utils::insert_into_account_set;
+use crate::splits::SplitSpec;
+use crate::vector_wrapper::{ClearStorage, VectorWrapper};
+use crate::Contract;
+
+/// The storage key under which the version of the state's layout is kept, next to the contract's state
+const STATE_VERSION_KEY: &[u8] = b"STATE_VERSION";
+
+/// The version of Contract's current layout. Whenever a field of Contract changes, this is bumped, the previous
+/// layout is kept here as ContractV<n> and migrate_state learns to convert it into the current layout
+pub const CURRENT_STATE_VERSION: u32 = 1;
+
+/// Get the version of the stored state's layout.
+/// State written before the version key was introduced has the version 0 layout
+pub(crate) fn read_state_version() -> u32 {
+    env::storage_read(STATE_VERSION_KEY)
+        .map(|bytes| u32::try_from_slice(&bytes).unwrap())
+        .unwrap_or(0)
+}
+
+pub(crate) fn write_state_version(version: u32) {
+    env::storage_write(STATE_VERSION_KEY, &version.try_to_vec().unwrap());
+}
+
+/// Read the stored state in its own layout and upgrade it to the current layout
+pub(crate) fn migrate_state() -> Result<Contract, MallocError> {
+    match read_state_version() {
+        0 => read_state::<ContractV0>().map(migrate_v0),
+        CURRENT_STATE_VERSION => read_state::<Contract>(),
+        version => Err(MallocError::UnknownStateVersion {
+            version,
+            current_version: CURRENT_STATE_VERSION,
+        }),
+    }
+}
+
+fn read_state<T: BorshDeserialize>() -> Result<T, MallocError> {
+    env::state_read().ok_or(MallocError::ContractNotInitialized)
+}
+
+/// The id of an action or a construction before ids were versioned
+#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug)]
+pub(crate) struct GenericIdV0 {
+    pub owner: AccountId,
+    pub name: String,
+}
+
+impl GenericIdV0 {
+    /// What was registered under the id becomes the id's first version
+    fn into_first_version(self) -> ActionId {
+        ActionId {
+            owner: self.owner,
+            name: self.name,
+            version: Some(0),
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug)]
+pub(crate) struct MallocCallV0 {
+    pub check_callback: Option<bool>,
+    pub skip_ft_transfer: Option<bool>,
+    pub malloc_call_id: AccountId,
+    pub token_id: AccountId,
+    pub json_args: String,
+    pub gas: Gas,
+    pub attached_amount: U128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq, Debug)]
+#[allow(clippy::enum_variant_names)]
+pub(crate) enum ActionV0 {
+    FtTransferCallToMallocCall(FtTransferCallToMallocCall),
+    WithdrawFromMallocCall(WithdrawFromMallocCall),
+    MallocCall(MallocCallV0),
+}
+
+impl From<ActionV0> for Action {
+    fn from(action: ActionV0) -> Self {
+        match action {
+            ActionV0::FtTransferCallToMallocCall(a) => Action::FtTransferCallToMallocCall(a),
+            ActionV0::WithdrawFromMallocCall(a) => Action::WithdrawFromMallocCall(a),
+            ActionV0::MallocCall(a) => Action::MallocCall(a.into()),
+        }
+    }
+}
+
+/// Constructions had no topology of their own, it was given with every construction call
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct ConstructionV0 {
+    pub actions: VectorWrapper<GenericIdV0>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct ConstructionCallV0 {
+    pub caller: AccountId,
+    pub construction_id: GenericIdV0,
+    pub next_action_calls_stack: VectorWrapper<u64>,
+    pub action_calls: VectorWrapper<ActionCallId>,
+    pub next_actions_indices_in_construction: VectorWrapper<VectorWrapper<VectorWrapper<u64>>>,
+    pub next_actions_splits: VectorWrapper<VectorWrapper<VectorWrapper<U128>>>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) enum ActionCallStatusV0 {
+    Error { message: String },
+    WaitingCall,
+    Executing { block_index_start: u64 },
+    Success,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct ActionCallV0 {
+    pub action_index_in_construction: u64,
+    pub block_index: u64,
+    pub amount: u128,
+    pub status: ActionCallStatusV0,
+}
+
+/// The layout of the first release, before the state version key was introduced
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct ContractV0 {
+    pub constructions: UnorderedMap<GenericIdV0, ConstructionV0>,
+    pub construction_calls: UnorderedMap<ConstructionCallId, ConstructionCallV0>,
+    pub action_calls: UnorderedMap<ActionCallId, ActionCallV0>,
+    pub actions: UnorderedMap<GenericIdV0, ActionV0>,
+    pub balances: FungibleTokenBalances,
+    pub next_action_call_id: ActionCallId,
+    pub malloc_contract_id: AccountId,
+}
+
+/// Convert the first release's state. The actions and constructions are re-keyed by their first version and are
+/// owned by whoever registered them. The converted constructions have no topology, so their calls keep giving it
+/// with init_construction. The state had no owner, so the contract's own account becomes the owner.
+/// Construction calls which are still in flight cannot be continued by the current code and are dropped.
+/// The tokens they did not spend yet are still in their callers' balances
+fn migrate_v0(mut old: ContractV0) -> Contract {
+    let actions = old.actions.to_vec();
+    old.actions.clear();
+    let constructions = old.constructions.to_vec();
+    old.constructions.clear();
+
+    let dropped_calls = old.construction_calls.len();
+    for (_, mut construction_call) in old.construction_calls.iter() {
+        construction_call.next_action_calls_stack.clear_storage();
+        construction_call.action_calls.clear_storage();
+        construction_call
+            .next_actions_indices_in_construction
+            .clear_storage();
+        construction_call.next_actions_splits.clear_storage();
+    }
+    old.construction_calls.clear();
+    old.action_calls.clear();
+    if dropped_calls > 0 {
+        log!(
+            "Dropped {} construction calls which were in flight",
+            dropped_calls
+        );
+    }
+
+    let owner_id = ValidAccountId::try_from(env::current_account_id()).unwrap();
+    let mut contract = Contract::new(Some(owner_id));
+    contract.balances = old.balances;
+    contract.next_action_call_id = old.next_action_call_id;
+    contract.malloc_contract_id = old.malloc_contract_id;
+
+    for (id, action) in actions {
+        let id = id.into_first_version();
+        contract.action_versions.insert(&id.unversioned(), &0);
+        contract.actions.insert(&id, &action.into());
+        insert_into_account_set(&mut contract.owner_actions, &id.owner, "owner-actions", &id);
+    }
+
+    for (id, mut construction) in constructions {
+        let id: ConstructionId = id.into_first_version();
+        let construction = migrate_construction_v0(&id, &mut construction);
+        contract.construction_versions.insert(&id.unversioned(), &0);
+        contract.constructions.insert(&id, &construction);
+        contract.incr_action_references(&construction);
+        insert_into_account_set(
+            &mut contract.owner_constructions,
+            &id.owner,
+            "owner-constructions",
+            &id,
+        );
+    }
+    contract
+}
+
+fn migrate_construction_v0(id: &ConstructionId, construction: &mut ConstructionV0) -> Construction {
+    let prefix = format!("migrated-{}-{}", id.owner, id.name);
+    let action_ids: Vec<ActionId> = construction
+        .actions
+        .0
+        .iter()
+        .map(GenericIdV0::into_first_version)
+        .collect();
+    construction.actions.clear_storage();
+
+    let numb_actions = action_ids.len();
+    Construction {
+        actions: VectorWrapper::from_vec(action_ids, format!("{}-actions", prefix).as_bytes()),
+        initial_action_indices: VectorWrapper::new(format!("{}-initial", prefix).as_bytes()),
+        initial_splits: SplitSpec::Weights(vec![]),
+        next_actions_indices: VectorWrapper::from_vec(
+            (0..numb_actions)
+                .map(|i| VectorWrapper::new(format!("{}-indices-{}", prefix, i).as_bytes()))
+                .collect(),
+            format!("{}-indices", prefix).as_bytes(),
+        ),
+        next_actions_splits: VectorWrapper::from_vec(
+            (0..numb_actions)
+                .map(|i| VectorWrapper::new(format!("{}-splits-{}", prefix, i).as_bytes()))
+                .collect(),
+            format!("{}-splits", prefix).as_bytes(),
+        ),
+        next_actions_token_ids: None,
+        min_returned_amounts: None,
+        scheduling: None,
+        action_call_timeout_blocks: None,
+    }
+}
+
+impl ClearStorage for VectorWrapper<GenericIdV0> {
+    fn clear_storage(&mut self) {
+        self.0.clear();
+    }
+}
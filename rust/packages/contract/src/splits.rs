@@ -0,0 +1,207 @@
+use std::cmp::Ordering;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::log;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::errors::MallocError;
+use crate::malloc_utils::U256;
+use crate::vector_wrapper::{ClearStorage, VectorWrapper};
+
+const TOTAL_BASIS_POINTS: u32 = 10_000;
+
+/// How one output of a split gets its share of the amount
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Split {
+    /// A share of what is left after the fixed amounts and the basis points, proportional to the weight
+    Weight(U128),
+    /// A share of what is left after the fixed amounts, out of 10,000 basis points
+    BasisPoints(u16),
+    /// An absolute amount, which is taken out before any of the other outputs
+    Fixed(U128),
+    /// Whatever is left after all of the other outputs. A split can only have one such output
+    AllRemaining,
+}
+
+/// Where the amount which is left over from rounding down the proportional outputs goes
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DustPolicy {
+    LastOutput,
+    FirstOutput,
+    /// One unit each to the proportional outputs with the largest fractional remainders
+    LargestRemainder,
+    /// The dust is not passed on, so it stays in the caller's balance
+    RefundToCaller,
+}
+
+/// The specification of how an amount is split between a set of outputs.
+/// A list of weights, i.e. ["1", "2"], splits proportionally and gives the rounding dust to the last output.
+/// Otherwise, the splits are given along with a dust policy, i.e. {"splits": [{"Fixed": "100"}, "AllRemaining"]}
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde", untagged)]
+pub enum SplitSpec {
+    Weights(Vec<U128>),
+    Custom {
+        splits: Vec<Split>,
+        /// Defaults to the last output
+        dust_policy: Option<DustPolicy>,
+    },
+}
+
+impl ClearStorage for VectorWrapper<SplitSpec> {
+    fn clear_storage(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl SplitSpec {
+    /// The number of outputs
+    pub fn len(&self) -> u64 {
+        match self {
+            SplitSpec::Weights(weights) => weights.len() as u64,
+            SplitSpec::Custom { splits, .. } => splits.len() as u64,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get_splits_and_dust_policy(&self) -> (Vec<Split>, DustPolicy) {
+        match self {
+            SplitSpec::Weights(weights) => (
+                weights.iter().map(|w| Split::Weight(*w)).collect(),
+                DustPolicy::LastOutput,
+            ),
+            SplitSpec::Custom {
+                splits,
+                dust_policy,
+            } => (
+                splits.clone(),
+                dust_policy.unwrap_or(DustPolicy::LastOutput),
+            ),
+        }
+    }
+
+    /// Check that the splits always account for the whole amount, apart from the dust and fixed amounts
+    /// which cannot be covered
+    pub fn validate(&self) -> Result<(), MallocError> {
+        let (splits, _) = self.get_splits_and_dust_policy();
+        let mut weight_sum = U256::from(0);
+        let mut number_of_weights = 0;
+        let mut total_basis_points: u32 = 0;
+        let mut number_of_all_remaining = 0;
+        for split in splits.iter() {
+            match split {
+                Split::Weight(weight) => {
+                    weight_sum += U256::from(weight.0);
+                    number_of_weights += 1;
+                }
+                Split::BasisPoints(basis_points) => total_basis_points += *basis_points as u32,
+                Split::Fixed(_) => (),
+                Split::AllRemaining => number_of_all_remaining += 1,
+            }
+        }
+        if total_basis_points > TOTAL_BASIS_POINTS {
+            return Err(MallocError::BasisPointsExceedTotal { total_basis_points });
+        }
+        if number_of_all_remaining > 1 {
+            return Err(MallocError::MultipleAllRemainingSplits);
+        }
+        if number_of_all_remaining == 1 && number_of_weights > 0 {
+            return Err(MallocError::AllRemainingWithWeights);
+        }
+        if number_of_weights > 0 && weight_sum.is_zero() {
+            return Err(MallocError::SplitsSumToZero);
+        }
+        if number_of_weights == 0
+            && number_of_all_remaining == 0
+            && total_basis_points != TOTAL_BASIS_POINTS
+        {
+            return Err(MallocError::SplitsDoNotCoverRemainder { total_basis_points });
+        }
+        Ok(())
+    }
+
+    /// Convert the split specification and a given amount to the amount for each output.
+    /// The amounts are computed with U256 math and never sum to more than the given amount. Anything which is
+    /// not passed on, i.e. the dust under the RefundToCaller policy, stays in the caller's balance
+    pub fn get_amounts(&self, amount: u128) -> Result<Vec<u128>, MallocError> {
+        let (splits, dust_policy) = self.get_splits_and_dust_policy();
+        let mut amounts = vec![0u128; splits.len()];
+
+        let fixed_sum = splits.iter().fold(U256::from(0), |sum, split| match split {
+            Split::Fixed(fixed) => sum + U256::from(fixed.0),
+            _ => sum,
+        });
+        if fixed_sum > U256::from(amount) {
+            return Err(MallocError::FixedSplitsExceedAmount {
+                fixed: U128(fixed_sum.as_u128()),
+                amount: U128(amount),
+            });
+        }
+        let remainder = U256::from(amount) - fixed_sum;
+
+        // The exact share of each proportional output as a fraction, which the largest remainder policy ranks by
+        let mut fractions: Vec<(usize, U256, U256)> = vec![];
+        for (i, split) in splits.iter().enumerate() {
+            match split {
+                Split::Fixed(fixed) => amounts[i] = fixed.0,
+                Split::BasisPoints(basis_points) => {
+                    let numerator = U256::from(*basis_points) * remainder;
+                    let denominator = U256::from(TOTAL_BASIS_POINTS);
+                    amounts[i] = (numerator / denominator).as_u128();
+                    fractions.push((i, numerator, denominator));
+                }
+                _ => (),
+            }
+        }
+        let after_basis_points = U256::from(amount) - U256::from(amounts.iter().sum::<u128>());
+        let weight_sum = splits.iter().fold(U256::from(0), |sum, split| match split {
+            Split::Weight(weight) => sum + U256::from(weight.0),
+            _ => sum,
+        });
+        for (i, split) in splits.iter().enumerate() {
+            if let Split::Weight(weight) = split {
+                let numerator = U256::from(weight.0) * after_basis_points;
+                amounts[i] = (numerator / weight_sum).as_u128();
+                fractions.push((i, numerator, weight_sum));
+            }
+        }
+
+        let mut unused: u128 = amount - amounts.iter().sum::<u128>();
+        if let Some(i) = splits.iter().position(|s| *s == Split::AllRemaining) {
+            amounts[i] = unused;
+            unused = 0;
+        }
+        if unused == 0 || amounts.is_empty() {
+            return Ok(amounts);
+        }
+        match dust_policy {
+            DustPolicy::LastOutput => *amounts.last_mut().unwrap() += unused,
+            DustPolicy::FirstOutput => amounts[0] += unused,
+            DustPolicy::LargestRemainder => {
+                // Each proportional output loses less than one unit to rounding, so the dust is always
+                // less than the number of proportional outputs
+                fractions.sort_by(|(i, num_a, den_a), (j, num_b, den_b)| {
+                    let rem_a = (*num_a % *den_a) * *den_b;
+                    let rem_b = (*num_b % *den_b) * *den_a;
+                    match rem_b.cmp(&rem_a) {
+                        Ordering::Equal => i.cmp(j),
+                        ordering => ordering,
+                    }
+                });
+                for (i, _, _) in fractions.iter().cycle().take(unused as usize) {
+                    amounts[*i] += 1;
+                }
+            }
+            DustPolicy::RefundToCaller => {
+                log!("Leaving the dust of {} with the caller", unused);
+            }
+        }
+        Ok(amounts)
+    }
+}
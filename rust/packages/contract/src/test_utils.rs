@@ -1,16 +1,28 @@
 #[cfg(all(test, not(target_arch = "wasm32")))]
 pub mod tests {
     const INIT_ACCOUNT_BAL: u128 = 10_000;
+    /// Enough to cover the storage of the registrations, the rest of it is refunded from the account's balance
+    const STORAGE_DEPOSIT: u128 = 10u128.pow(24);
+
+    use std::convert::TryFrom;
+
+    use malloc_call_core::ft::FungibleTokenHandlers;
     use malloc_call_core::ReturnItem;
     use near_sdk::{
         borsh::BorshSerialize,
         collections::Vector,
-        json_types::ValidAccountId,
+        json_types::{ValidAccountId, U128},
+        serde_json::{self, Value},
         test_utils::{accounts, VMContextBuilder},
-        AccountId,
+        testing_env, AccountId, MockedBlockchain,
     };
 
+    use crate::action::Action;
+    use crate::actions::ft_calls::FtTransferCallToMallocCall;
+    use crate::construction::ConstructionId;
+    use crate::malloc_utils::GenericId;
     use crate::vector_wrapper::VectorWrapper;
+    use crate::{Contract, CoreFunctionality};
 
     pub(crate) fn return_item_eq(a: &ReturnItem, b: &ReturnItem) -> bool {
         let a_tok_id: AccountId = a.token_id.clone().into();
@@ -36,4 +48,82 @@ pub mod tests {
             .account_balance(INIT_ACCOUNT_BAL);
         builder
     }
+
+    /// Get a context which attaches enough to cover the storage of registrations
+    pub(crate) fn get_deposit_context(predecessor_account_id: ValidAccountId) -> VMContextBuilder {
+        let mut builder = get_context(predecessor_account_id);
+        builder
+            .attached_deposit(STORAGE_DEPOSIT)
+            .account_balance(INIT_ACCOUNT_BAL + 100 * STORAGE_DEPOSIT);
+        builder
+    }
+
+    /// Get an action which sends wrapp.localnet to the malloc call accounts(2)
+    pub(crate) fn swap_action() -> Action {
+        Action::FtTransferCallToMallocCall(FtTransferCallToMallocCall {
+            malloc_call_id: accounts(2),
+            token_id: ValidAccountId::try_from("wrapp.localnet").unwrap(),
+        })
+    }
+
+    /// Register the named actions under accounts(0)
+    pub(crate) fn register_json_actions(contract: &mut Contract, actions: Vec<(&str, Action)>) {
+        testing_env!(get_deposit_context(accounts(0)).build());
+        let (action_names, actions) = actions
+            .into_iter()
+            .map(|(name, action)| (name.to_string(), action))
+            .unzip();
+        contract.register_actions(action_names, actions);
+    }
+
+    /// Register the named actions and the construction which uses them under accounts(0)
+    /// @returns the unversioned id of the construction
+    pub(crate) fn register_json_construction(
+        contract: &mut Contract,
+        construction_name: &str,
+        actions: Vec<(&str, Action)>,
+        construction: Value,
+    ) -> ConstructionId {
+        register_json_actions(contract, actions);
+        contract.register_construction(
+            construction_name.to_string(),
+            serde_json::from_value(construction).unwrap(),
+        );
+        GenericId {
+            name: construction_name.to_string(),
+            owner: accounts(0).to_string(),
+            version: None,
+        }
+    }
+
+    /// Register the actions and the construction under accounts(0), deposit 100 of each token which the actions
+    /// take for accounts(0) and start a construction call of the construction with the given amount.
+    /// The construction is registered under the construction call's id
+    pub(crate) fn init_json_construction_call(
+        contract: &mut Contract,
+        construction_call_id: &str,
+        actions: Vec<(&str, Action)>,
+        construction: Value,
+        amount: u128,
+    ) {
+        let mut token_ids: Vec<AccountId> = actions.iter().map(|(_, action)| action.get_token_id()).collect();
+        token_ids.sort();
+        token_ids.dedup();
+        let construction_id = register_json_construction(contract, construction_call_id, actions, construction);
+
+        for token_id in token_ids {
+            testing_env!(get_context(ValidAccountId::try_from(token_id).unwrap()).build());
+            contract.ft_on_transfer(accounts(0).to_string(), "100".to_string(), "".to_string());
+        }
+        testing_env!(get_context(accounts(0)).build());
+        contract.init_construction(
+            construction_call_id.to_string(),
+            construction_id,
+            U128(amount),
+            None,
+            None,
+            None,
+            None,
+        );
+    }
 }
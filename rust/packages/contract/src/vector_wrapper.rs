@@ -5,6 +5,7 @@ use near_sdk::{
     borsh::{self, BorshDeserialize, BorshSerialize},
     collections::Vector,
     env::{self, random_seed, used_gas},
+    json_types::U128,
     log,
     serde::{
         self,
@@ -18,7 +19,7 @@ use near_sdk::{
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct VectorWrapper<T>(pub Vector<T>);
 
-fn get_random_bytes_prefix() -> Vec<u8> {
+pub(crate) fn get_random_bytes_prefix() -> Vec<u8> {
     let mut unique_prefix = env::random_seed();
     unique_prefix.append(&mut used_gas().to_be_bytes().to_vec());
     unique_prefix
@@ -36,6 +37,42 @@ impl<T: BorshSerialize> VectorWrapper<T> {
     }
 }
 
+/// Remove all of the elements a collection holds in storage, including the elements of nested collections
+pub trait ClearStorage {
+    fn clear_storage(&mut self);
+}
+
+impl ClearStorage for VectorWrapper<u64> {
+    fn clear_storage(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl ClearStorage for VectorWrapper<U128> {
+    fn clear_storage(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl ClearStorage for VectorWrapper<Option<u64>> {
+    fn clear_storage(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl<T> ClearStorage for VectorWrapper<VectorWrapper<T>>
+where
+    VectorWrapper<T>: ClearStorage,
+    T: BorshSerialize + BorshDeserialize,
+{
+    fn clear_storage(&mut self) {
+        for mut inner in self.0.iter() {
+            inner.clear_storage();
+        }
+        self.0.clear();
+    }
+}
+
 pub struct VectorWrapperVisitor<T> {
     marker: PhantomData<fn() -> VectorWrapper<T>>,
 }
@@ -179,7 +216,6 @@ mod tests {
         v.0.push(&10);
         v.0.push(&11);
         let str = serde_json::to_string(&v).unwrap();
-        println!("{}", str);
         assert_eq!(str, "[10,11]");
     }
 
@@ -0,0 +1,58 @@
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::{log, serde_json, AccountId};
+
+/// The prefix of NEP-297 event logs
+pub const EVENT_JSON_PREFIX: &str = "EVENT_JSON:";
+
+pub const FT_EVENT_STANDARD: &str = "malloc_call_core_ft";
+pub const FT_EVENT_VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a, T: Serialize> {
+    standard: &'a str,
+    version: &'a str,
+    #[serde(flatten)]
+    event: &'a T,
+}
+
+/// Log an event in the NEP-297 format, i.e. EVENT_JSON:{"standard": ..., "version": ..., "event": ..., "data": ...}.
+/// The event must serialize to an object with the event and data fields
+pub fn emit_event<T: Serialize>(standard: &str, version: &str, event: &T) {
+    let event_log = EventLog {
+        standard,
+        version,
+        event,
+    };
+    log!(
+        "{}{}",
+        EVENT_JSON_PREFIX,
+        serde_json::to_string(&event_log).unwrap()
+    );
+}
+
+/// A change to an account's internal balance of a token
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtBalanceEventData {
+    pub account_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+/// The events of FungibleTokenBalances
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde", tag = "event", content = "data", rename_all = "snake_case")]
+pub enum FtEvent {
+    /// Tokens were deposited into an account's internal balance
+    FtDeposit(Vec<FtBalanceEventData>),
+    /// Tokens were withdrawn from an account's internal balance to another contract or account
+    FtWithdraw(Vec<FtBalanceEventData>),
+}
+
+impl FtEvent {
+    pub fn emit(&self) {
+        emit_event(FT_EVENT_STANDARD, FT_EVENT_VERSION, self);
+    }
+}
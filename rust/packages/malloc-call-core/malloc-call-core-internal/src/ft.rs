@@ -11,6 +11,8 @@ use near_sdk::serde_json::{json, Value};
 use near_sdk::{collections::LookupMap, json_types::U128, AccountId, Balance};
 use near_sdk::{env, log, serde_json, Gas, Promise, PromiseResult};
 
+use crate::events::{FtBalanceEventData, FtEvent};
+
 // TODO: make lower??
 const GAS_BUFFER: Gas = 5_000_000_000_000;
 const GAS_FOR_INTERNAL_RESOLVE: Gas = 5_000_000_000_000;
@@ -178,14 +180,30 @@ impl FungibleTokenBalances {
                 .unwrap_or_else(|e| panic!("Failed to deserialize transfer opts: {}", e))
         };
         let token_id = env::predecessor_account_id();
-        let current_amount = self.get_ft_balance(&opts.sender_id, &token_id);
         let amount = amount.parse::<u128>().unwrap();
+        self.deposit(&opts.sender_id, &token_id, amount);
+
+        "0".to_string()
+    }
+
+    /// Credit tokens which were sent to the contract to the account's internal balance and emit an ft_deposit event
+    pub fn deposit(&mut self, account_id: &AccountId, token_id: &AccountId, amount: u128) {
+        self.increase_balance(account_id, token_id, amount);
+        FtEvent::FtDeposit(vec![FtBalanceEventData {
+            account_id: account_id.clone(),
+            token_id: token_id.clone(),
+            amount: U128(amount),
+        }])
+        .emit();
+    }
+
+    /// Credit an amount of the token to the account's internal balance
+    pub fn increase_balance(&mut self, account_id: &AccountId, token_id: &AccountId, amount: u128) {
+        let current_amount = self.get_ft_balance(account_id, token_id);
         self.account_to_contract_balances.insert(
-            &Self::get_balances_key(&opts.sender_id, &token_id),
+            &Self::get_balances_key(account_id, token_id),
             &(amount + current_amount),
         );
-
-        "0".to_string()
     }
 
     pub fn internal_ft_transfer(
@@ -320,7 +338,8 @@ impl FungibleTokenBalances {
         )
     }
 
-    /// Resolve the ft transfer by updating the amount used in the balances
+    /// Resolve the ft transfer by updating the amount used in the balances.
+    /// The amount which left the account's balance is emitted in an ft_withdraw event
     /// @returns the amount used
     pub fn resolve_internal_ft_transfer_call(
         &mut self,
@@ -334,15 +353,17 @@ impl FungibleTokenBalances {
         }
 
         let current_balance = self.get_ft_balance(account_id, &token_id);
-        match near_sdk::utils::promise_result_as_success() {
+        let amount_used = match near_sdk::utils::promise_result_as_success() {
             None => {
                 log!("The FT transfer call failed, redepositing funds");
                 self.account_to_contract_balances.insert(
                     &Self::get_balances_key(&account_id, &token_id),
                     &(current_balance + amount),
                 );
-                U128(0)
+                0
             }
+            // A plain ft_transfer returns nothing, so the whole amount was delivered
+            Some(data) if data.is_empty() => amount,
             Some(data) => {
                 // TODO: err handling?
                 let amount_used_str: String = serde_json::from_slice(data.as_slice())
@@ -360,13 +381,23 @@ impl FungibleTokenBalances {
                         &(current_balance + amount_unused),
                     );
                 }
-                U128(amount_used)
+                amount_used
             }
+        };
+        if amount_used > 0 {
+            FtEvent::FtWithdraw(vec![FtBalanceEventData {
+                account_id: account_id.clone(),
+                token_id,
+                amount: U128(amount_used),
+            }])
+            .emit();
         }
+        U128(amount_used)
     }
 
     /********** Helper functions **************/
-    fn subtract_balance(&mut self, sender: &AccountId, token_id: &AccountId, amount: u128) {
+    /// Debit an amount of the token from the account's internal balance
+    pub fn subtract_balance(&mut self, sender: &AccountId, token_id: &AccountId, amount: u128) {
         let current_balance = self.get_ft_balance(sender, token_id);
 
         if current_balance < amount {
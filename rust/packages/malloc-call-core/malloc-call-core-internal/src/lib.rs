@@ -1,7 +1,9 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::json_types::ValidAccountId;
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{Balance, Gas};
 use near_sdk::{json_types::U128, AccountId, Promise};
+pub mod events;
 pub mod ft;
 pub mod utils;
 pub mod gas;
@@ -14,7 +16,11 @@ pub fn resolver_method_name() -> Vec<u8> {
     "resolver".to_string().into_bytes()
 }
 
-#[derive(Serialize, Deserialize)]
+pub fn revert_method_name() -> Vec<u8> {
+    "revert".to_string().into_bytes()
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct MallocCallMetadata {
     pub name: String,
@@ -30,9 +36,27 @@ pub struct ReturnItem {
     pub amount: String,
 }
 
-// TODO: implement for calls
-pub trait Revert {
-    //    fn revert()
+/// A description of a completed call which is passed to the call's revert method
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RevertCallContext<CallArgs> {
+    /// The args which the call was called with
+    pub args: CallArgs,
+    /// The amount of tokens which the call received
+    pub amount: U128,
+    pub token_id: ValidAccountId,
+    pub caller: ValidAccountId,
+    /// The tokens which the call returned
+    pub returned: Vec<ReturnItem>,
+}
+
+/// A compensation interface for malloc calls. When a construction call is aborted, the malloc contract
+/// calls revert on each of the construction call's completed calls, in the reverse order of their completion
+pub trait Revert<CallArgs> {
+    /// Undo or refund the call described by call_context. Any refunded tokens should be credited
+    /// to the caller's balance held by the malloc call
+    /// @returns the tokens which were refunded
+    fn revert(&mut self, call_context: RevertCallContext<CallArgs>) -> Vec<ReturnItem>;
 }
 
 pub trait MallocCallNoCallback<CallArgs> {
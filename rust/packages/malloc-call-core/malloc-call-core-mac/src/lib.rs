@@ -4,12 +4,38 @@ use syn::parse::{Nothing, Parser};
 use syn::token::{Struct, Token};
 use syn::{
     parse_macro_input, parse_quote, DataEnum, DataUnion, DeriveInput, Field, FieldsNamed,
-    FieldsUnnamed, ItemStruct,
+    FieldsUnnamed, Ident, ItemStruct, Lit, Meta, NestedMeta,
 };
 
-#[proc_macro_derive(MallocCallFT)]
+/// Get the method named by #[malloc_call_ft(deposit_guard = "method_name")], if any.
+/// The method is called with the sender_id and msg of every ft_on_transfer before the deposit, so it can panic to reject deposits
+fn get_deposit_guard(input_struct: &ItemStruct) -> Option<Ident> {
+    let attr = input_struct
+        .attrs
+        .iter()
+        .find(|attr| attr.path.is_ident("malloc_call_ft"))?;
+    let list = match attr.parse_meta() {
+        Ok(Meta::List(list)) => list,
+        _ => panic!("Expected #[malloc_call_ft(deposit_guard = \"method_name\")]"),
+    };
+    let deposit_guard = list.nested.iter().find_map(|nested| match nested {
+        NestedMeta::Meta(Meta::NameValue(name_value)) if name_value.path.is_ident("deposit_guard") => {
+            match &name_value.lit {
+                Lit::Str(method_name) => Some(Ident::new(&method_name.value(), method_name.span())),
+                _ => panic!("Expected the deposit guard to be a string of the method name"),
+            }
+        }
+        _ => None,
+    });
+    deposit_guard
+}
+
+#[proc_macro_derive(MallocCallFT, attributes(malloc_call_ft))]
 pub fn malloc_call_ft(input: TokenStream) -> TokenStream {
     let mut input_struct = parse_macro_input!(input as ItemStruct);
+    let deposit_guard = get_deposit_guard(&input_struct).map(|method_name| {
+        quote! { self.#method_name(&sender_id, &msg); }
+    });
     let mut fields = input_struct.fields;
 
     // // Add the whitelisted malloc contract
@@ -48,6 +74,7 @@ pub fn malloc_call_ft(input: TokenStream) -> TokenStream {
         #[near_sdk::near_bindgen]
         impl #impl_generics malloc_call_core::ft::FungibleTokenHandlers for #struct_name #ty_generics #where_clause {
             fn ft_on_transfer(&mut self, sender_id: String, amount: String, msg: String) -> String {
+                #deposit_guard
                 self.balances.ft_on_transfer(sender_id, amount, msg)
             }
 
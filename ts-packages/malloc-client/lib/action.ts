@@ -1,5 +1,6 @@
 import BN from "bn.js";
 import { MallocErrors } from "./errors";
+import { NEAR_TOKEN_ID } from "./ft-token";
 import {
   SpecialAccount,
   TxAction,
@@ -8,7 +9,11 @@ import {
   ActionTypesLibraryFacing,
   ActionTypesContractFacing,
   Action,
+  MallocCallRegistryEntry,
+  Transaction,
+  TxHashOrUndefined,
 } from "./interfaces";
+import { executeMultipleTxNoDeposit, MAX_GAS_STR } from "./tx";
 
 /**
  * Fill in the the metadata from malloc call's metadata into the given action
@@ -44,6 +49,55 @@ export const getMallocCallMetadata = async (
   return metadata;
 };
 
+/**
+ * Pull the malloc call's metadata into the malloc contract's registry. Only the malloc contract's owner can do this.
+ * MallocCall actions can only be registered and run for registered malloc calls
+ */
+export const registerMallocCall = async <
+  SpecialAccountGeneric extends SpecialAccount
+>(
+  callerAccount: SpecialAccountGeneric,
+  mallocAccountId: AccountId,
+  mallocCallId: AccountId
+): Promise<TxHashOrUndefined<SpecialAccountGeneric>> => {
+  const txs: Transaction[] = [
+    {
+      receiverId: mallocAccountId,
+      actions: [
+        {
+          functionCall: {
+            methodName: "register_malloc_call",
+            args: {
+              malloc_call_id: mallocCallId,
+            },
+            gas: MAX_GAS_STR,
+            amount: "0",
+          },
+        },
+      ],
+    },
+  ];
+
+  const txRets = await executeMultipleTxNoDeposit(callerAccount, txs, {});
+
+  //@ts-ignore
+  if (txRets instanceof Array) return txRets[0];
+  //@ts-ignore
+  return txRets;
+};
+
+export const getMallocCallRegistryEntry = async (
+  callerAccount: SpecialAccount,
+  mallocAccountId: AccountId,
+  mallocCallId: AccountId
+): Promise<MallocCallRegistryEntry | null> => {
+  return await callerAccount.viewFunction(
+    mallocAccountId,
+    "get_malloc_call_registry_entry",
+    { malloc_call_id: mallocCallId }
+  );
+};
+
 export const getActionInputToken = (
   action: Action<ActionTypesLibraryFacing>
 ): AccountId => {
@@ -53,6 +107,14 @@ export const getActionInputToken = (
     return action.WithdrawFromMallocCall.token_id;
   } else if (action.FtTransferCallToMallocCall) {
     return action.FtTransferCallToMallocCall.token_id;
+  } else if (action.FtTransfer) {
+    return action.FtTransfer.token_id;
+  } else if (action.NearTransfer || action.WrapNear) {
+    return NEAR_TOKEN_ID;
+  } else if (action.UnwrapNear) {
+    return action.UnwrapNear.wnear_contract_id;
+  } else if (action.Route) {
+    return action.Route.token_id;
   }
   throw MallocErrors.EXPECTED_ACTION_PROPERTY();
 };
@@ -10,6 +10,10 @@ import {
   MallocCallMetadata,
   ConstructionId,
   ConstructionCall,
+  ConstructionCallStatus,
+  ConstructionCallSummary,
+  ConstructionInfo,
+  ActionInfo,
   SpecialAccountWithKeyPair,
   TransactionWithPromiseResultFlag,
   ConstructionCallId,
@@ -25,6 +29,9 @@ import {
   ActionTypesLibraryFacing,
   ActionTypesContractFacing,
   Action,
+  SplitSpec,
+  NextActionsKey,
+  TokenAmount,
 } from "./interfaces";
 import {
   executeMultipleTx,
@@ -36,14 +43,25 @@ import {
 
 interface RunEphemeralOpts {
   gas: BigNumberish;
+  // Attached to each registration to cover its storage, whatever is not used is refunded
+  storageDeposit: BigNumberish;
   depositTransactionHash?: string | null;
+  nextActionsTokenIds?: (NextActionsKey[] | null)[];
+  minReturnedAmounts?: TokenAmount[][];
 }
 
 const defaultRunEphemeralOpts: RunEphemeralOpts = {
   gas: MAX_GAS,
+  storageDeposit: "100000000000000000000000",
   depositTransactionHash: null,
 };
 
+/**
+ * Weights are passed as strings, other split specifications are passed as they are
+ */
+const toSplitSpec = (splits: BigNumberish[] | SplitSpec): SplitSpec =>
+  Array.isArray(splits) ? splits.map((i) => i.toString()) : splits;
+
 const makeid = (length: number) => {
   let result = "";
   const characters =
@@ -56,6 +74,7 @@ const makeid = (length: number) => {
 };
 
 /**
+ * Delete a construction owned by the caller. If the construction id has no version, every version is deleted
  * @returns the transaction's hash
  */
 export const deleteConstruction = async <
@@ -91,6 +110,83 @@ export const deleteConstruction = async <
   return txRetsInit;
 };
 
+/**
+ * Abort a construction call owned by the caller. The malloc calls which already completed are reverted
+ * @returns the transaction's hash
+ */
+export const abortConstructionCall = async <
+  SpecialAccountGeneric extends SpecialAccount
+>(
+  callerAccount: SpecialAccountGeneric,
+  mallocAccountId: AccountId,
+  constructionCallId: ConstructionCallId
+): Promise<TxHashOrUndefined<SpecialAccountGeneric>> => {
+  const txs: Transaction[] = [
+    {
+      receiverId: mallocAccountId,
+      actions: [
+        {
+          functionCall: {
+            methodName: "abort_construction_call",
+            args: {
+              construction_call_id: constructionCallId,
+            },
+            gas: MAX_GAS_STR,
+            amount: "0",
+          },
+        },
+      ],
+    },
+  ];
+
+  const txRets = await executeMultipleTxNoDeposit(callerAccount, txs, {});
+
+  //@ts-ignore
+  if (txRets instanceof Array) return txRets[0];
+  //@ts-ignore
+  return txRets;
+};
+
+/**
+ * Mark an action call which is stuck executing as timed out and reclaim the tokens left with its malloc call.
+ * Any account can reclaim an action call once its construction's timeout passed
+ * @returns the transaction's hash
+ */
+export const reclaimTimedOutActionCall = async <
+  SpecialAccountGeneric extends SpecialAccount
+>(
+  callerAccount: SpecialAccountGeneric,
+  mallocAccountId: AccountId,
+  constructionCallId: ConstructionCallId,
+  actionCallId: ActionCallId
+): Promise<TxHashOrUndefined<SpecialAccountGeneric>> => {
+  const txs: Transaction[] = [
+    {
+      receiverId: mallocAccountId,
+      actions: [
+        {
+          functionCall: {
+            methodName: "reclaim_timed_out_action_call",
+            args: {
+              construction_call_id: constructionCallId,
+              action_call_id: actionCallId.toString(),
+            },
+            gas: MAX_GAS_STR,
+            amount: "0",
+          },
+        },
+      ],
+    },
+  ];
+
+  const txRets = await executeMultipleTxNoDeposit(callerAccount, txs, {});
+
+  //@ts-ignore
+  if (txRets instanceof Array) return txRets[0];
+  //@ts-ignore
+  return txRets;
+};
+
 export const getActionCallData = async (
   callerAccount: SpecialAccount,
   mallocAccountId: AccountId,
@@ -103,6 +199,10 @@ export const getActionCallData = async (
   );
 };
 
+/**
+ * Get the full state of a construction call. The view panics once the construction call is garbage collected,
+ * so use {@link getConstructionCallStatus} and {@link getConstructionCallSummary} to follow a construction call's progress
+ */
 export const getConstructionCallData = async (
   callerAccount: SpecialAccount,
   mallocAccountId: AccountId,
@@ -115,6 +215,88 @@ export const getConstructionCallData = async (
   );
 };
 
+export const getConstructionCallStatus = async (
+  callerAccount: SpecialAccount,
+  mallocAccountId: AccountId,
+  constructionCallId: string
+): Promise<ConstructionCallStatus | null> => {
+  return await callerAccount.viewFunction(
+    mallocAccountId,
+    "get_construction_call_status",
+    { id: constructionCallId }
+  );
+};
+
+/**
+ * Get the summary of a construction call which finished and was garbage collected
+ * @returns null if the construction call was not collected yet
+ */
+export const getConstructionCallSummary = async (
+  callerAccount: SpecialAccount,
+  mallocAccountId: AccountId,
+  constructionCallId: string
+): Promise<ConstructionCallSummary | null> => {
+  return await callerAccount.viewFunction(
+    mallocAccountId,
+    "get_construction_call_summary",
+    { id: constructionCallId }
+  );
+};
+
+export const getActiveConstructionCalls = async (
+  callerAccount: SpecialAccount,
+  mallocAccountId: AccountId,
+  caller: AccountId,
+  fromIndex?: number,
+  limit?: number
+): Promise<ConstructionCallId[]> => {
+  return await callerAccount.viewFunction(
+    mallocAccountId,
+    "get_active_construction_calls",
+    {
+      caller,
+      from_index: fromIndex?.toString(),
+      limit: limit?.toString(),
+    }
+  );
+};
+
+export const getConstructionsByOwner = async (
+  callerAccount: SpecialAccount,
+  mallocAccountId: AccountId,
+  owner: AccountId,
+  fromIndex?: number,
+  limit?: number
+): Promise<ConstructionInfo[]> => {
+  return await callerAccount.viewFunction(
+    mallocAccountId,
+    "get_constructions_by_owner",
+    {
+      owner,
+      from_index: fromIndex?.toString(),
+      limit: limit?.toString(),
+    }
+  );
+};
+
+export const getActionsByOwner = async (
+  callerAccount: SpecialAccount,
+  mallocAccountId: AccountId,
+  owner: AccountId,
+  fromIndex?: number,
+  limit?: number
+): Promise<ActionInfo[]> => {
+  return await callerAccount.viewFunction(
+    mallocAccountId,
+    "get_actions_by_owner",
+    {
+      owner,
+      from_index: fromIndex?.toString(),
+      limit: limit?.toString(),
+    }
+  );
+};
+
 const checkTransactionSuccessful = async (
   hashes: string[],
   accountId: string
@@ -137,9 +319,9 @@ export const runEphemeralConstruction = async (
   actions: Action<ActionTypesLibraryFacing>[],
   amount: BigNumberish,
   initial_action_indices: number[],
-  initial_splits: BigNumberish[],
+  initial_splits: BigNumberish[] | SplitSpec,
   next_actions_indices: number[][][],
-  next_actions_splits: BigNumberish[][][],
+  next_actions_splits: (BigNumberish[] | SplitSpec)[][],
   signerKp: KeyPair,
   opts?: Partial<RunEphemeralOpts>
 ): Promise<string[]> => {
@@ -157,6 +339,12 @@ export const runEphemeralConstruction = async (
         owner: callerAccount.accountId,
       };
     }),
+    initial_action_indices,
+    initial_splits: toSplitSpec(initial_splits),
+    next_actions_indices,
+    next_actions_splits: next_actions_splits.map((o) => o.map(toSplitSpec)),
+    next_actions_token_ids: _opts.nextActionsTokenIds,
+    min_returned_amounts: _opts.minReturnedAmounts,
   };
 
   const actionsContractFacing: Action<ActionTypesContractFacing>[] =
@@ -185,7 +373,7 @@ export const runEphemeralConstruction = async (
                 actions: actionsContractFacing,
               } as RegisterActionsArgs,
               gas: MAX_GAS.toString(),
-              amount: "0", // TODO: storage
+              amount: _opts.storageDeposit.toString(),
             },
           },
         ],
@@ -201,7 +389,7 @@ export const runEphemeralConstruction = async (
                 construction,
               } as RegisterConstructionArgs,
               gas: MAX_GAS.toString(),
-              amount: "0", //TODO: storage deposit goes here ya heard
+              amount: _opts.storageDeposit.toString(),
             },
           },
         ],
@@ -222,12 +410,6 @@ export const runEphemeralConstruction = async (
                   owner: callerAccount.accountId,
                 },
                 amount: amount.toString(),
-                initial_action_indices: initial_action_indices,
-                initial_splits: initial_splits.map((i) => i.toString()),
-                next_actions_indices,
-                next_actions_splits: next_actions_splits.map((o) =>
-                  o.map((o) => o.map((item) => item.toString()))
-                ),
               } as InitConstructionArgs,
               gas: MAX_GAS.divn(3).toString(),
               amount: "0", //TODO: storage deposit goes here ya heard
@@ -237,10 +419,11 @@ export const runEphemeralConstruction = async (
       },
     ];
 
-    const txRetsInit = await executeMultipleTxNoDeposit(callerAccount, [
+    // The registrations attach a storage deposit, which a function call access key cannot do
+    const txRetsInit = (await executeMultipleTx(callerAccount, [
       ...txs,
       ...initTx,
-    ]);
+    ])) as string[] | undefined;
 
     // Throws if unsuccessful
     await checkTransactionSuccessful(txRetsInit || [], callerAccount.accountId);
@@ -249,31 +432,19 @@ export const runEphemeralConstruction = async (
   };
 
   const runNextActionCalls = async (): Promise<string[]> => {
-    let constructionCallData = await getConstructionCallData(
+    // The construction call is garbage collected once it finishes, after which it has no status
+    let status = await getConstructionCallStatus(
       callerAccount,
       mallocAccountId,
       construction_call_id
     );
     let txHashes: string[] = [];
 
-    while (constructionCallData.next_action_calls_stack.length > 0) {
-      const action_call_index =
-        constructionCallData.next_action_calls_stack[
-          constructionCallData.next_action_calls_stack.length - 1
-        ];
-
-      const action_call_id =
-        constructionCallData.action_calls[action_call_index];
-      const action_call = await getActionCallData(
-        callerAccount,
-        mallocAccountId,
-        action_call_id as any
-      );
-      // console.log(action_call);
-      console.log(constructionCallData);
-      const txs: Transaction[] = new Array(
-        constructionCallData.next_action_calls_stack.length
-      )
+    while (status && status.number_scheduled > 0) {
+      // The contract takes the next action call off of the deque by the construction's scheduling mode,
+      // so one process_next_action_call is sent per pending action call without picking them here
+      console.log(status);
+      const txs: Transaction[] = new Array(status.number_scheduled)
         .fill(0)
         .map((_) => {
           return {
@@ -299,7 +470,7 @@ export const runEphemeralConstruction = async (
       await checkTransactionSuccessful(txRets || [], callerAccount.accountId);
       txHashes.push(...(txRets || []));
 
-      constructionCallData = await getConstructionCallData(
+      status = await getConstructionCallStatus(
         callerAccount,
         mallocAccountId,
         construction_call_id
@@ -319,11 +490,17 @@ export const runEphemeralConstruction = async (
     return [...txsInit, ...txsNextStep];
   } catch (e) {
     console.trace(e);
-    const call_state = await getConstructionCallData(
-      callerAccount,
-      mallocAccountId,
-      construction_call_id
-    );
+    const call_state =
+      (await getConstructionCallStatus(
+        callerAccount,
+        mallocAccountId,
+        construction_call_id
+      )) ??
+      (await getConstructionCallSummary(
+        callerAccount,
+        mallocAccountId,
+        construction_call_id
+      ));
     console.info(
       "The error resolved with malloc in the following state",
       JSON.stringify(call_state)
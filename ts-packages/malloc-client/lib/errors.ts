@@ -1,4 +1,4 @@
-import { AccountId, MallocError } from "./interfaces";
+import { AccountId, ContractErrorReport, MallocError } from "./interfaces";
 
 export namespace MallocErrors {
   export const TRANSACTION_PROMISE_FAILED = (message?: string) =>
@@ -31,3 +31,30 @@ const createError = (message: string): MallocError => {
     message,
   };
 };
+
+/**
+ * Parse the malloc contract's error out of a panic message, such as the message of a failed transaction.
+ * The message may itself be JSON encoded, as it is in TransactionWithPromiseResult
+ * @returns the contract's error report or undefined if the message does not contain one
+ */
+export const parseContractError = (
+  message?: string
+): ContractErrorReport | undefined => {
+  if (!message) return undefined;
+  const parse = (s: string) => {
+    try {
+      return JSON.parse(s);
+    } catch (e) {
+      return undefined;
+    }
+  };
+  const decoded = parse(message);
+  const panicMessage = typeof decoded === "string" ? decoded : message;
+  const report = parse(
+    panicMessage.slice(
+      panicMessage.indexOf("{"),
+      panicMessage.lastIndexOf("}") + 1
+    )
+  );
+  return report?.error?.kind !== undefined ? report : undefined;
+};
@@ -2,11 +2,86 @@ import * as nearAPI from "near-api-js";
 import {
   AccountId,
   FunctionCallOptions,
+  SpecialAccountConnectedWallet,
+  SpecialAccountWithKeyPair,
   Transaction,
   TransferType,
+  TxHashesOrUndefined,
 } from "./interfaces";
 import { executeMultipleTx, MAX_GAS, MAX_GAS_STR } from "./tx";
 
+// The token id under which native NEAR is held in the malloc contract's internal balances.
+// It is not a valid account id, so query it with getNearBalance rather than getTokenBalance
+export const NEAR_TOKEN_ID = "NEAR";
+
+/**
+ * Deposit NEAR into the internal NEAR balance of accountId, or of the caller if it is not given
+ */
+export const depositNear = async <
+  T extends SpecialAccountConnectedWallet | SpecialAccountWithKeyPair
+>(
+  callerAccount: T,
+  mallocAccountId: AccountId,
+  amount: string,
+  accountId?: AccountId
+): Promise<TxHashesOrUndefined<T>> => {
+  const txs: Transaction[] = [
+    {
+      receiverId: mallocAccountId,
+      actions: [
+        {
+          functionCall: {
+            methodName: "deposit_near",
+            args: accountId ? { account_id: accountId } : {},
+            gas: MAX_GAS_STR,
+            amount,
+          },
+        },
+      ],
+    },
+  ];
+  return await executeMultipleTx(callerAccount, txs);
+};
+
+/**
+ * Withdraw NEAR from the caller's internal NEAR balance to the recipient, or to the caller if it is not given
+ */
+export const withdrawNear = async <
+  T extends SpecialAccountConnectedWallet | SpecialAccountWithKeyPair
+>(
+  callerAccount: T,
+  mallocAccountId: AccountId,
+  amount: string,
+  recipient?: AccountId
+): Promise<TxHashesOrUndefined<T>> => {
+  const txs: Transaction[] = [
+    {
+      receiverId: mallocAccountId,
+      actions: [
+        {
+          functionCall: {
+            methodName: "withdraw_near",
+            args: recipient ? { amount, recipient } : { amount },
+            gas: MAX_GAS_STR,
+            amount: "1",
+          },
+        },
+      ],
+    },
+  ];
+  return await executeMultipleTx(callerAccount, txs);
+};
+
+export const getNearBalance = (
+  caller: nearAPI.Account,
+  mallocAccountId: AccountId,
+  accountId: AccountId
+): Promise<string> => {
+  return caller.viewFunction(mallocAccountId, "get_near_balance", {
+    account_id: accountId,
+  });
+};
+
 export const getTokenBalance = (
   caller: nearAPI.Account,
   mallocOrCallContractId: AccountId,
@@ -1,4 +1,4 @@
-import { AccountId } from "./shared";
+import { AccountId, ActionId } from "./shared";
 
 export interface MallocCall {
   check_callback?: boolean;
@@ -9,8 +9,29 @@ export interface MallocCall {
 }
 
 export interface MallocCallWithGasAndAttached extends MallocCall {
-  gas: number;
-  attached_amount: string;
+  // Defaults to the gas required by the malloc call's registered metadata
+  gas?: number;
+  // Defaults to the attachment required by the malloc call's registered metadata
+  attached_amount?: string;
+  // The gas for the malloc call's revert method, if it implements one
+  revert_gas?: number;
+}
+
+export interface FtTransfer {
+  receiver_id: AccountId;
+  token_id: AccountId;
+}
+
+export interface NearTransfer {
+  receiver_id: AccountId;
+}
+
+export interface WrapNear {
+  wnear_contract_id: AccountId;
+}
+
+export interface UnwrapNear {
+  wnear_contract_id: AccountId;
 }
 
 export interface FtTransferCallToMallocCall {
@@ -24,11 +45,23 @@ export interface WithdrawFromMallocCall {
   recipient?: AccountId;
 }
 
+export type AmountPredicate = { AtLeast: string } | { LessThan: string };
+
+// The amount goes to the next action set of the first predicate which holds, or to the last set if none holds
+export interface Route {
+  token_id: AccountId;
+  predicates: AmountPredicate[];
+}
+
 export interface Action<
   T extends ActionTypesLibraryFacing | ActionTypesContractFacing
 > {
   // SimpleTransfer?: { recipient: AccountId };
-  // FTTransfer?: { recipient: AccountId };
+  FtTransfer?: T extends MallocCall
+    ? undefined
+    : T extends FtTransfer
+    ? FtTransfer
+    : undefined;
   MallocCall?: T extends MallocCallWithGasAndAttached
     ? MallocCallWithGasAndAttached
     : T extends MallocCall
@@ -44,14 +77,34 @@ export interface Action<
     : T extends WithdrawFromMallocCall
     ? WithdrawFromMallocCall
     : undefined;
+  NearTransfer?: NearTransfer;
+  WrapNear?: WrapNear;
+  UnwrapNear?: UnwrapNear;
+  Route?: Route;
 }
 
 export type ActionTypesLibraryFacing =
+  | FtTransfer
   | FtTransferCallToMallocCall
   | MallocCall
-  | WithdrawFromMallocCall;
+  | WithdrawFromMallocCall
+  | NearTransfer
+  | WrapNear
+  | UnwrapNear
+  | Route;
 
 export type ActionTypesContractFacing =
+  | FtTransfer
   | FtTransferCallToMallocCall
   | WithdrawFromMallocCall
-  | MallocCallWithGasAndAttached;
+  | MallocCallWithGasAndAttached
+  | NearTransfer
+  | WrapNear
+  | UnwrapNear
+  | Route;
+
+export interface ActionInfo {
+  id: ActionId;
+  token_id: AccountId;
+  action: Action<ActionTypesContractFacing>;
+}
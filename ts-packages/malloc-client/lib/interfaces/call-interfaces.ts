@@ -1,25 +1,75 @@
 /********** Call Interfaces ************/
 
-import { AccountId, ConstructionId, ActionCallId } from "./shared";
+import { AccountId, ConstructionId, ActionCallId, ActionId } from "./shared";
+import { ContractError } from "./error-interfaces";
 
 export interface ActionCallStatus {
-  Error?: { message: string };
+  Error?: { error: ContractError };
   // empty enum
   WaitingCall?: any;
   Executing?: { block_index_start: number };
   Success?: any;
+  WaitingInputs?: any;
+  Reverted?: any;
+}
+
+export interface TokenAmount {
+  token_id: AccountId;
+  amount: string;
 }
 
 export interface ActionCall {
   action_index_in_construction: string,
   block_index: string,
   amount: string,
-  status: ActionCallStatus
+  status: ActionCallStatus,
+  expected_number_inputs: number,
+  number_inputs_received: number,
+  number_inputs_failed: number,
+  input_amounts: TokenAmount[],
+  returned_items: TokenAmount[],
+  // The branch which a route action call passed its input on to
+  route_branch?: number,
+  block_index_start?: number,
+  block_index_end?: number
+}
+
+export interface ActionCallProgress {
+  action_call_id: string;
+  action_index_in_construction: number;
+  action_id: ActionId;
+  amount: string;
+  status: ActionCallStatus;
+  returned_items: TokenAmount[];
+  route_branch?: number;
+  block_index_start?: string;
+  block_index_end?: string;
+}
+
+export interface ConstructionCallStatus {
+  caller: AccountId;
+  construction_id: ConstructionId;
+  action_calls: ActionCallProgress[];
+  number_scheduled: number;
+  number_waiting_inputs: number;
+  number_executing: number;
+  number_succeeded: number;
+  number_errored: number;
+  number_reverted: number;
+}
+
+/** What is kept of a construction call once it finished and was garbage collected */
+export interface ConstructionCallSummary {
+  caller: AccountId;
+  construction_id: ConstructionId;
+  number_of_action_calls: number;
+  number_of_errors: number;
+  block_index_finished: number;
 }
 
 export interface ConstructionCall {
   caller: AccountId;
   construction_id: ConstructionId;
-  next_action_calls_stack: number[];
+  next_action_calls: number[];
   action_calls: ActionCallId[];
 }
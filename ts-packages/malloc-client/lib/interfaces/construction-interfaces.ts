@@ -1,6 +1,45 @@
-import { ActionId } from "./shared";
+import { AccountId, ActionId, ConstructionId } from "./shared";
+import { TokenAmount } from "./call-interfaces";
 
 // TODO:?
 export interface Construction {
 	actions: ActionId[]
-}
\ No newline at end of file
+	initial_action_indices: number[]
+	initial_splits: SplitSpec
+	next_actions_indices: number[][][]
+	next_actions_splits: SplitSpec[][]
+	// For each action, the keys its returned tokens are matched to its sets of next actions by, or null to match by position
+	next_actions_token_ids?: (NextActionsKey[] | null)[]
+	// For each action, the minimum amount of each token it must return, below which it fails and passes nothing on
+	min_returned_amounts?: TokenAmount[][]
+	scheduling?: SchedulingMode
+	// The number of blocks after which an executing action call can be reclaimed
+	action_call_timeout_blocks?: number
+}
+
+export type SchedulingMode = "Dfs" | "Bfs" | "Priority";
+
+// A catch-all set of next actions takes a returned token which none of the action's other sets are keyed by
+export type NextActionsKey = { TokenId: AccountId } | "CatchAll";
+
+// Weights share what is left after the fixed amounts and basis points, AllRemaining takes whatever is left after the other outputs
+export type Split =
+	| { Weight: string }
+	| { BasisPoints: number }
+	| { Fixed: string }
+	| "AllRemaining";
+
+export type DustPolicy =
+	| "LastOutput"
+	| "FirstOutput"
+	| "LargestRemainder"
+	| "RefundToCaller";
+
+// Either proportional weights, with the rounding dust going to the last output, or splits with a dust policy
+export type SplitSpec = string[] | { splits: Split[]; dust_policy?: DustPolicy };
+
+export interface ConstructionInfo {
+	id: ConstructionId
+	number_of_actions: number
+	token_ids: AccountId[]
+}
@@ -3,4 +3,22 @@ import { ConstructionCallId } from "./shared";
 export interface CallEphemeralError {
   constructionCallId?: ConstructionCallId;
   message?: string;
-}
\ No newline at end of file
+}
+
+/**
+ * An error of the malloc contract, see MallocError in the contract's errors.rs.
+ * The kind names the error and the other fields depend on the kind,
+ * i.e. { kind: "ActionIndexOutOfBounds", index: 2, number_of_actions: 2 }
+ */
+export interface ContractError {
+  kind: string;
+  [field: string]: any;
+}
+
+/** How the malloc contract reports an error in its panic messages */
+export interface ContractErrorReport {
+  /** The stable numeric code of the error */
+  code: number;
+  message: string;
+  error: ContractError;
+}
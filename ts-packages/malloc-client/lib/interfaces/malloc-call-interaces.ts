@@ -4,4 +4,13 @@ export interface MallocCallMetadata {
   gas_required: number;
   attachment_required: BN | string;
   name: string;
-}
\ No newline at end of file
+}
+export type TrustLevel = "Unverified" | "Verified";
+
+export interface MallocCallRegistryEntry {
+  malloc_call_id: string;
+  metadata: MallocCallMetadata;
+  trust_level: TrustLevel;
+  // The block at which the metadata was last pulled from the malloc call
+  block_index_cached: number;
+}
@@ -1,4 +1,4 @@
-import { Construction } from "./construction-interfaces";
+import { Construction, SplitSpec } from "./construction-interfaces";
 import { Action, ActionTypesContractFacing } from "./action-interfaces";
 import { ConstructionCallId, ConstructionId, TransferType } from "./shared";
 
@@ -6,10 +6,10 @@ export interface InitConstructionArgs {
   construction_call_id: ConstructionCallId;
   construction_id: ConstructionId;
   amount: string;
-  initial_action_indices: number[];
-  initial_splits: string[];
-  next_actions_indices: number[][][];
-  next_actions_splits: string[][][];
+  initial_action_indices?: number[];
+  initial_splits?: SplitSpec;
+  next_actions_indices?: number[][][];
+  next_actions_splits?: SplitSpec[][];
 }
 
 export interface RegisterConstructionArgs {
@@ -82,6 +82,8 @@ export type ActionCallId = number | string;
 interface GenericId {
   owner: AccountId;
   name: string;
+  // If not set, the id refers to the latest registered version
+  version?: number;
 }
 export type ConstructionId = GenericId;
 export type ActionId = GenericId;
@@ -22,6 +22,8 @@ import {
   ConstructionId,
   TxHashOrUndefined,
   ConstructionCall,
+  ConstructionCallStatus,
+  ConstructionCallSummary,
   BigNumberish,
   MallocCallMetadata,
   ExecuteMultipleTxOpts,
@@ -29,10 +31,13 @@ import {
   WithdrawToArgs,
   TxHashesOrUndefined,
   Action,
+  SplitSpec,
 } from "./interfaces";
 import {
   deleteConstruction,
   getConstructionCallData,
+  getConstructionCallStatus,
+  getConstructionCallSummary,
   runEphemeralConstruction,
 } from "./construction";
 import {
@@ -127,9 +132,9 @@ export interface IRunEphemeralConstruction {
   actions: Action<ActionTypesLibraryFacing>[];
   amount: string;
   initialActionIndices: number[];
-  initialSplits: BigNumberish[];
+  initialSplits: BigNumberish[] | SplitSpec;
   nextActionsIndices: number[][][];
-  nextActionsSplits: BigNumberish[][][];
+  nextActionsSplits: (BigNumberish[] | SplitSpec)[][];
   opts?: Partial<RunEphemeralOpts>;
 }
 
@@ -334,6 +339,32 @@ export class MallocClient<
     );
   }
 
+  /**
+   * See {@link getConstructionCallStatus}. Resolves to null once the construction call finished and was garbage collected
+   */
+  public getConstructionCallStatus(
+    constructionCallID: ConstructionCallId
+  ): Promise<ConstructionCallStatus | null> {
+    return getConstructionCallStatus(
+      this.account,
+      this.mallocAccountId,
+      constructionCallID
+    );
+  }
+
+  /**
+   * See {@link getConstructionCallSummary}
+   */
+  public getConstructionCallSummary(
+    constructionCallID: ConstructionCallId
+  ): Promise<ConstructionCallSummary | null> {
+    return getConstructionCallSummary(
+      this.account,
+      this.mallocAccountId,
+      constructionCallID
+    );
+  }
+
   /**
    * See {@link runEphemeralConstruction}
    */